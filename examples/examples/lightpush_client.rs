@@ -14,19 +14,17 @@
 //! Every line you feed into stdin will be the unencrypted payload of a message to be pushed,
 //! where the content topic is defined by the CONTENT_TOPIC constant.
 
-use async_std::io;
-use async_std::io::prelude::BufReadExt;
-use futures::select;
 use libp2p::futures::StreamExt;
-use libp2p::{identity::Keypair, swarm::Swarm, PeerId};
+use libp2p::{identity::Keypair, noise, tcp, yamux, Multiaddr, PeerId, SwarmBuilder};
 use log::info;
-use std::error::Error;
+use std::{error::Error, time::Duration};
+use tokio::io::{self, AsyncBufReadExt};
 use waku_protocol::waku_lightpush::network_behaviour::WakuLightPushBehaviour;
 use waku_protocol::waku_message::WakuMessage;
 
 const CONTENT_TOPIC: &str = "content_topic";
 
-#[async_std::main]
+#[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
@@ -36,21 +34,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer id: {:?}", local_peer_id);
 
-    let transport = libp2p::development_transport(local_key.clone()).await?;
-
-    let waku_lp_behaviour = WakuLightPushBehaviour::new();
+    let waku_lp_behaviour = WakuLightPushBehaviour::default();
 
     let pubsub_topic = match std::env::args().nth(1) {
         Some(t) => t,
         None => panic!("No pubsub topic provided!"),
     };
 
-    let mut swarm = Swarm::new(transport, waku_lp_behaviour, local_peer_id);
+    let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|_| waku_lp_behaviour)?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
     swarm
         .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
         .unwrap();
 
-    let peer_addr = match std::env::args().nth(2) {
+    let peer_addr: Multiaddr = match std::env::args().nth(2) {
         Some(s) => match s.parse() {
             Ok(p) => p,
             Err(_) => panic!("Cannot parse provided MultiAddr!"),
@@ -66,17 +71,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
         None => panic!("No PeerId provided!"),
     };
 
-    swarm.behaviour_mut().add_lightpush_peer(peer_id, peer_addr);
+    swarm.add_peer_address(peer_id, peer_addr);
 
     // Read full lines from stdin
-    let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
+    let mut stdin = io::BufReader::new(io::stdin()).lines();
 
     let mut req_id = 0;
     loop {
-        select! {
-            line = stdin.select_next_some() => {
+        tokio::select! {
+            Ok(Some(line)) = stdin.next_line() => {
                 let mut msg = WakuMessage::new();
-                msg.set_payload(line.expect("Stdin not to close").as_bytes().to_vec());
+                msg.set_payload(line.as_bytes().to_vec());
                 msg.set_content_topic(CONTENT_TOPIC.to_string());
 
                 swarm.behaviour_mut().send_request(peer_id, req_id.to_string(), pubsub_topic.clone(), msg);
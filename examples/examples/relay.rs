@@ -14,19 +14,17 @@
 //! Every line you feed into stdin will be the unencrypted payload of a message sent to the Relay
 //! where the content topic is defined by the CONTENT_TOPIC constant.
 
-use async_std::io;
-use async_std::io::prelude::BufReadExt;
-use futures::select;
 use libp2p::futures::StreamExt;
-use libp2p::{identity::Keypair, swarm::Swarm, Multiaddr, PeerId};
+use libp2p::{identity::Keypair, noise, tcp, yamux, Multiaddr, PeerId, SwarmBuilder};
 use log::info;
-use std::error::Error;
+use std::{error::Error, time::Duration};
+use tokio::io::{self, AsyncBufReadExt};
 use waku_protocol::waku_message::WakuMessage;
 use waku_protocol::waku_relay::network_behaviour::WakuRelayBehaviour;
 
 const CONTENT_TOPIC: &str = "content_topic";
 
-#[async_std::main]
+#[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
@@ -36,9 +34,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer id: {:?}", local_peer_id);
 
-    let transport = libp2p::development_transport(local_key.clone()).await?;
-
-    let mut waku_relay_behaviour = WakuRelayBehaviour::new();
+    let mut waku_relay_behaviour = WakuRelayBehaviour::default();
 
     let pubsub_topic = match std::env::args().nth(1) {
         Some(t) => t,
@@ -47,7 +43,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     waku_relay_behaviour.subscribe(&pubsub_topic).unwrap();
 
-    let mut swarm = Swarm::new(transport, waku_relay_behaviour, local_peer_id);
+    let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|_| waku_relay_behaviour)?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
     swarm
         .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
         .unwrap();
@@ -64,16 +69,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
 
     // Read full lines from stdin
-    let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
+    let mut stdin = io::BufReader::new(io::stdin()).lines();
 
     loop {
-        select! {
-            line = stdin.select_next_some() => {
+        tokio::select! {
+            Ok(Some(line)) = stdin.next_line() => {
                 let mut msg = WakuMessage::new();
-                msg.set_payload(line.expect("Stdin not to close").as_bytes().to_vec());
+                msg.set_payload(line.as_bytes().to_vec());
                 msg.set_content_topic(CONTENT_TOPIC.to_string());
                 match swarm.behaviour_mut().publish(&pubsub_topic, msg) {
-                    Ok(m) => info!("Published message: {}", m),
+                    Ok(Some(m)) => info!("Published message: {}", m),
+                    Ok(None) => info!("Proving message before publishing it"),
                     Err(e) => info!("Error publishing message: {}", e),
                 };
             },
@@ -20,21 +20,20 @@
 //! If no relay or lightpush has fed any messages to the Store node, all queries will be unsuccessful.
 //! You should run the relay or lightpush examples before this one.
 
-use async_std::io;
-use async_std::io::prelude::BufReadExt;
-use futures::select;
 use libp2p::futures::StreamExt;
-use libp2p::{identity::Keypair, swarm::Swarm, Multiaddr, PeerId};
+use libp2p::{identity::Keypair, noise, tcp, yamux, Multiaddr, PeerId, SwarmBuilder};
 use log::info;
-use std::error::Error;
+use std::{error::Error, time::Duration};
+use tokio::io::{self, AsyncBufReadExt};
 use waku_protocol::waku_message::WakuMessage;
+use waku_protocol::waku_relay::config::WakuRelayConfig;
 use waku_protocol::waku_store::network_behaviour::{compute_index, WakuStoreBehaviour};
 
 const CONTENT_TOPIC: &str = "content_topic";
 const PAGE_SIZE: u64 = 3;
 const DIRECTION: bool = true; // true = FORWARD
 
-#[async_std::main]
+#[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
@@ -44,9 +43,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer id: {:?}", local_peer_id);
 
-    let transport = libp2p::development_transport(local_key.clone()).await?;
-
-    let mut waku_store_behaviour = WakuStoreBehaviour::new(10);
+    let mut waku_store_behaviour = WakuStoreBehaviour::new(10, WakuRelayConfig::default());
 
     let pubsub_topic = match std::env::args().nth(1) {
         Some(t) => t,
@@ -55,7 +52,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     waku_store_behaviour.subscribe(&pubsub_topic).unwrap();
 
-    let mut swarm = Swarm::new(transport, waku_store_behaviour, local_peer_id);
+    let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|_| waku_store_behaviour)?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
     swarm
         .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
         .unwrap();
@@ -79,19 +85,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
         None => panic!("No PeerId provided!"),
     };
 
-    let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
+    let mut stdin = io::BufReader::new(io::stdin()).lines();
 
     loop {
-        select! {
-            line = stdin.select_next_some() => {
+        tokio::select! {
+            Ok(Some(line)) = stdin.next_line() => {
                 let mut msg = WakuMessage::new();
-                msg.set_payload(line.expect("Stdin not to close").as_bytes().to_vec());
+                msg.set_payload(line.as_bytes().to_vec());
                 msg.set_content_topic(CONTENT_TOPIC.to_string());
 
-                let cursor = compute_index(msg);
+                let cursor = compute_index(&pubsub_topic, msg);
 
-                let mut content_topics = Vec::new();
-                content_topics.push(CONTENT_TOPIC.to_string());
+                let content_topics = vec![CONTENT_TOPIC.to_string()];
                 swarm.behaviour_mut().send_query(
                     peer_id,
                     "test_request_id".to_string(),
@@ -0,0 +1,275 @@
+// Portable format of store archives, used by `waku-node store export`, `waku-node store
+// import` and the /store/v1/archive REST endpoint. Archives are JSON lines, one archived
+// message per line, in timestamp order:
+//
+// {"pubsubTopic":"/waku/2/default-waku/proto","digest":"<base64>","receiverTime":<ns>,
+//  "senderTime":<ns>,"message":{"payload":"<base64>","contentTopic":"/toy/1/chat/proto",
+//  "version":0,"timestamp":<ns>,"meta":"<base64>","rateLimitProof":"<base64>"}}
+//
+// Bytes are standard padded base64, times are nanoseconds since the Unix epoch. meta and
+// rateLimitProof, the protobuf encoded 17/WAKU2-RLN-RELAY proof, are omitted when empty.
+// The digest is the one store nodes index messages by, see compute_digest.
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_rustls::{client::TlsStream, TlsConnector};
+use hyper::{body, body::Bytes, client::conn, Body, Method, Request, Uri};
+use log::info;
+use protobuf::Message;
+use rustls::{ClientConfig, RootCertStore};
+use rustls_pki_types::{pem::PemObject, CertificateDer, Der, ServerName, TrustAnchor};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::Path, sync::Arc};
+use tokio::net::TcpStream;
+use tokio_util::{
+    compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
+    either::Either,
+};
+use waku_protocol::{
+    waku_message::{RateLimitProof, WakuMessage},
+    waku_store::{message_queue::IndexedWakuMessage, network_behaviour::compute_digest, Index},
+};
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchivedMessageSerDe {
+    pubsubTopic: String,
+    digest: String,
+    receiverTime: i64,
+    senderTime: i64,
+    message: ArchivedWakuMessageSerDe,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchivedWakuMessageSerDe {
+    payload: String,
+    contentTopic: String,
+    version: u32,
+    timestamp: i64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    meta: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    rateLimitProof: String,
+}
+
+pub fn to_json_line(indexed_message: &IndexedWakuMessage) -> String {
+    let message = indexed_message.message();
+    let rate_limit_proof = match message.has_rate_limit_proof() {
+        true => BASE64.encode(
+            message
+                .get_rate_limit_proof()
+                .write_to_bytes()
+                .expect("Proof encodes"),
+        ),
+        false => String::new(),
+    };
+    let archived = ArchivedMessageSerDe {
+        pubsubTopic: indexed_message.pubsub_topic().clone(),
+        digest: BASE64.encode(indexed_message.index().get_digest()),
+        receiverTime: indexed_message.index().get_receiver_time(),
+        senderTime: indexed_message.index().get_sender_time(),
+        message: ArchivedWakuMessageSerDe {
+            payload: BASE64.encode(message.get_payload()),
+            contentTopic: message.get_content_topic().to_string(),
+            version: message.get_version(),
+            timestamp: message.get_timestamp(),
+            meta: BASE64.encode(message.get_meta()),
+            rateLimitProof: rate_limit_proof,
+        },
+    };
+    serde_json::to_string(&archived).expect("Archived messages serialize")
+}
+
+fn from_json_line(line: &str) -> Result<IndexedWakuMessage, Box<dyn Error>> {
+    let archived: ArchivedMessageSerDe = serde_json::from_str(line)?;
+
+    let mut message = WakuMessage::new();
+    message.set_payload(BASE64.decode(archived.message.payload)?);
+    message.set_content_topic(archived.message.contentTopic);
+    message.set_version(archived.message.version);
+    message.set_timestamp(archived.message.timestamp);
+    message.set_meta(BASE64.decode(archived.message.meta)?);
+    if !archived.message.rateLimitProof.is_empty() {
+        let proof_bytes = BASE64.decode(archived.message.rateLimitProof)?;
+        message.set_rate_limit_proof(RateLimitProof::parse_from_bytes(&proof_bytes)?);
+    }
+
+    // Digests are what imports are deduplicated by, so they must match the message
+    let digest = BASE64.decode(archived.digest)?;
+    if digest != compute_digest(&archived.pubsubTopic, &message) {
+        return Err("digest does not match the message".into());
+    }
+    let mut index = Index::new();
+    index.set_digest(digest);
+    index.set_receiver_time(archived.receiverTime);
+    index.set_sender_time(archived.senderTime);
+    index.set_pubsub_topic(archived.pubsubTopic.clone());
+    Ok(IndexedWakuMessage::new(
+        message,
+        index,
+        archived.pubsubTopic,
+    ))
+}
+
+// Parses a whole archive, failing on the first malformed line
+pub fn parse(archive: &str) -> Result<Vec<IndexedWakuMessage>, String> {
+    archive
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| from_json_line(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+// How export and import reach the REST API of a node
+pub struct RestClient<'a> {
+    pub url: &'a str,
+    pub auth_token: Option<&'a str>,
+    // PEM certificates trusted for https URLs, e.g. the self-signed --rest-tls-cert of the
+    // node. The Mozilla root certificates are trusted if there are none
+    pub tls_ca: Option<&'a Path>,
+}
+
+fn tls_connector(tls_ca: Option<&Path>) -> Result<TlsConnector, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    match tls_ca {
+        Some(path) => {
+            for cert in CertificateDer::pem_file_iter(path)? {
+                roots.add(cert?)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().map(|a| TrustAnchor {
+            subject: Der::from_slice(a.subject),
+            subject_public_key_info: Der::from_slice(a.spki),
+            name_constraints: a.name_constraints.map(Der::from_slice),
+        })),
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+// Sends a request to the REST API at path, returning the body of a successful response
+async fn rest_request(
+    client: &RestClient<'_>,
+    method: Method,
+    path: &str,
+    body: Bytes,
+) -> Result<Bytes, Box<dyn Error>> {
+    let url: Uri = format!("{}{}", client.url.trim_end_matches('/'), path).parse()?;
+    let (host, https) = match (url.host(), url.scheme_str()) {
+        (Some(host), Some("http")) => (host, false),
+        (Some(host), Some("https")) => (host, true),
+        _ => return Err(format!("{} is not an http or https URL", client.url).into()),
+    };
+    // IPv6 hosts are bracketed in URLs only
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let stream: Either<TcpStream, Compat<TlsStream<Compat<TcpStream>>>> = match https {
+        true => {
+            let server_name = ServerName::try_from(host.to_string())?;
+            let connector = tls_connector(client.tls_ca)?;
+            Either::Right(
+                connector
+                    .connect(server_name, stream.compat())
+                    .await?
+                    .compat(),
+            )
+        }
+        false => Either::Left(stream),
+    };
+    let (mut sender, connection) = conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            info!("REST API connection failed: {}", e);
+        }
+    });
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(url.path_and_query().map_or("/", |p| p.as_str()))
+        .header("host", url.authority().expect("URL has a host").as_str())
+        .header("content-type", "application/x-ndjson")
+        .header("content-length", body.len());
+    if let Some(token) = client.auth_token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+
+    let response = sender.send_request(request.body(Body::from(body))?).await?;
+    let status = response.status();
+    let reply = body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, String::from_utf8_lossy(&reply)).into());
+    }
+    Ok(reply)
+}
+
+// Downloads the archive of the node serving the REST API
+pub async fn export(client: &RestClient<'_>, path: &Path) -> Result<(), Box<dyn Error>> {
+    let archive = rest_request(client, Method::GET, "/store/v1/archive", Bytes::new()).await?;
+
+    fs::write(path, &archive)?;
+    println!(
+        "Exported {} messages to {:?}",
+        archive
+            .split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .count(),
+        path
+    );
+    Ok(())
+}
+
+// Uploads an archive to the node serving the REST API
+pub async fn import(client: &RestClient<'_>, path: &Path) -> Result<(), Box<dyn Error>> {
+    let archive = fs::read_to_string(path)?;
+    // Fails early, without a round trip, on malformed files
+    parse(&archive)?;
+
+    let reply = rest_request(
+        client,
+        Method::POST,
+        "/store/v1/archive",
+        Bytes::from(archive),
+    )
+    .await?;
+
+    println!("{}", String::from_utf8_lossy(&reply));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{parse, to_json_line};
+    use waku_protocol::{
+        waku_message::WakuMessage,
+        waku_store::{message_queue::IndexedWakuMessage, network_behaviour::compute_index},
+    };
+
+    #[test]
+    fn test_archive_round_trip() {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(vec![0, 159, 146, 150]);
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        msg.set_timestamp(1_670_000_000_000_000_000);
+        msg.mut_rate_limit_proof().set_epoch(vec![1; 32]);
+        let indexed = IndexedWakuMessage::new(
+            msg.clone(),
+            compute_index("/waku/2/default-waku/proto", msg),
+            "/waku/2/default-waku/proto".to_string(),
+        );
+
+        let archive = format!("{}\n\n", to_json_line(&indexed));
+        let parsed = parse(&archive).unwrap();
+        assert_eq!(
+            "/waku/2/default-waku/proto",
+            parsed[0].index().get_pubsub_topic()
+        );
+        assert_eq!(vec![indexed], parsed);
+
+        let tampered = archive.replace("\"version\":0", "\"version\":0,\"payload\":\"AA==\"");
+        assert!(parse(&tampered).unwrap_err().starts_with("line 1:"));
+    }
+}
@@ -1,31 +1,224 @@
 use crate::network_behaviour::WakuNodeEvent;
-use clap::Parser;
+use archive::RestClient;
+use clap::{Parser, Subcommand};
 use libp2p::{
-    futures::StreamExt, gossipsub::GossipsubEvent, identity::Keypair, swarm::Swarm,
-    swarm::SwarmEvent, Multiaddr, PeerId,
+    futures::StreamExt, gossipsub, identify, identity::Keypair, multiaddr::Protocol, noise,
+    request_response, swarm::SwarmEvent, tcp, yamux, Multiaddr, PeerId, SwarmBuilder,
 };
 use log::info;
 use network_behaviour::WakuNodeBehaviour;
-use protobuf::Message;
-use std::error::Error;
-use tokio::sync::mpsc;
+use prometheus_client::{metrics::counter::Counter, registry::Registry};
+use protobuf::{Message, RepeatedField};
+use relay_cache::{RelayCache, DEFAULT_RELAY_CACHE_CAPACITY};
+use rest_api::{
+    AdminRequest, AllowedOrigin, LightPushError, LightPushReply, LightPushRequest, PeerInfo,
+    RestConfig, StoreArchiveRequest, StoreQueryReply, StoreQueryRequest,
+};
+use rpc_api::RpcConfig;
+use std::{
+    collections::HashMap, error::Error, net::IpAddr, path::PathBuf, sync::Arc, time::Duration,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
 use waku_protocol::{
+    rate_limit::RateLimit,
     waku_lightpush::network_behaviour::WakuLightPushEvent,
     waku_message::WakuMessage,
-    waku_relay::network_behaviour::{WakuRelayEvent, DEFAULT_PUBSUB_TOPIC},
-    waku_store::network_behaviour::WakuStoreEvent,
+    waku_relay::{
+        config::{
+            WakuRelayConfig, WakuRelayScoringConfig, DEFAULT_DUPLICATE_CACHE_TIME_SECS,
+            DEFAULT_FLOOD_PUBLISH, DEFAULT_GOSSIP_THRESHOLD, DEFAULT_GRAYLIST_THRESHOLD,
+            DEFAULT_HEARTBEAT_INTERVAL_MS, DEFAULT_HISTORY_LENGTH,
+            DEFAULT_MAX_TIMESTAMP_DRIFT_SECS, DEFAULT_MAX_TRANSMIT_SIZE, DEFAULT_MESH_N,
+            DEFAULT_MESH_N_HIGH, DEFAULT_MESH_N_LOW, DEFAULT_PUBLISH_THRESHOLD,
+        },
+        network_behaviour::{WakuRelayEvent, DEFAULT_PUBSUB_TOPIC},
+        protected_topics::ProtectedTopic,
+    },
+    waku_rln_relay::{
+        fr_to_hex,
+        membership::StaticMembership,
+        rln::{
+            IdentityCredential, RlnKeys, WakuRlnConfig, WakuRlnRelay, DEFAULT_EPOCH_PERIOD_SECS,
+            DEFAULT_MAX_EPOCH_GAP, DEFAULT_ROOTS_WINDOW, DEFAULT_TREE_DEPTH,
+        },
+    },
+    waku_store::{
+        client::{QueryStrategy, StoreQueryResult, WakuStoreClientEvent},
+        network_behaviour::{WakuStoreEvent, DEFAULT_RESUME_WINDOW, DEFAULT_SYNC_INTERVAL},
+        retention::{SizeRetention, TimeRetention, TopicRetention},
+        HistoryResponse,
+    },
 };
 
+mod archive;
 mod network_behaviour;
+mod openapi;
+mod relay_cache;
 mod rest_api;
+mod rpc_api;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Enable relay protocol
     #[clap(long, action = clap::ArgAction::Set, default_value = "true")]
     relay: bool,
 
+    /// Target number of peers in each relay topic mesh (gossipsub D)
+    #[clap(long, default_value_t = DEFAULT_MESH_N)]
+    relay_mesh_n: usize,
+
+    /// Minimum number of peers in each relay topic mesh (gossipsub D_lo)
+    #[clap(long, default_value_t = DEFAULT_MESH_N_LOW)]
+    relay_mesh_n_low: usize,
+
+    /// Maximum number of peers in each relay topic mesh (gossipsub D_hi)
+    #[clap(long, default_value_t = DEFAULT_MESH_N_HIGH)]
+    relay_mesh_n_high: usize,
+
+    /// Interval between relay heartbeats, in milliseconds
+    #[clap(long, default_value_t = DEFAULT_HEARTBEAT_INTERVAL_MS)]
+    relay_heartbeat_interval: u64,
+
+    /// Number of heartbeats relayed messages are kept in the message cache
+    #[clap(long, default_value_t = DEFAULT_HISTORY_LENGTH)]
+    relay_history_length: usize,
+
+    /// Maximum size of a relay RPC, in bytes
+    #[clap(long, default_value_t = DEFAULT_MAX_TRANSMIT_SIZE)]
+    relay_max_transmit_size: usize,
+
+    /// Publish own messages to all peers of a topic, not only to the mesh
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = DEFAULT_FLOOD_PUBLISH)]
+    relay_flood_publish: bool,
+
+    /// Time relayed message IDs are remembered for duplicate detection, in seconds
+    #[clap(long, default_value_t = DEFAULT_DUPLICATE_CACHE_TIME_SECS)]
+    relay_duplicate_cache_time: u64,
+
+    /// Maximum drift between a relayed message's timestamp and the local clock, in seconds
+    #[clap(long, default_value_t = DEFAULT_MAX_TIMESTAMP_DRIFT_SECS)]
+    relay_max_timestamp_drift: u64,
+
+    /// Enable gossipsub v1.1 peer scoring on relay
+    #[clap(long, action = clap::ArgAction::Set, default_value = "true")]
+    relay_peer_scoring: bool,
+
+    /// Peer score below which no gossip is emitted to or accepted from a peer
+    #[clap(long, default_value_t = DEFAULT_GOSSIP_THRESHOLD, allow_hyphen_values = true)]
+    relay_gossip_threshold: f64,
+
+    /// Peer score below which own messages are not published to a peer
+    #[clap(long, default_value_t = DEFAULT_PUBLISH_THRESHOLD, allow_hyphen_values = true)]
+    relay_publish_threshold: f64,
+
+    /// Peer score below which all RPCs from a peer are ignored
+    #[clap(long, default_value_t = DEFAULT_GRAYLIST_THRESHOLD, allow_hyphen_values = true)]
+    relay_graylist_threshold: f64,
+
+    /// Pubsub topic that only accepts messages signed by a secp256k1 key, as
+    /// <topic>:<hex public key>. Passing the hex private key instead also signs own messages.
+    /// Option may be repeated
+    #[clap(long)]
+    protected_topic: Vec<ProtectedTopic>,
+
+    /// Enable RLN spam protection on relay, modelled on 17/WAKU2-RLN-RELAY. Local only: proofs
+    /// and id commitments are not compatible with nwaku or zerokit, only with other waku-rs
+    /// nodes sharing --rln-relay-keys-path
+    #[clap(long, action = clap::ArgAction::Set, default_value = "false")]
+    rln_relay: bool,
+
+    /// File listing the hex encoded id commitments of the RLN group, one per line
+    #[clap(long)]
+    rln_relay_membership_file: Option<PathBuf>,
+
+    /// File holding the RLN identity secret. A new identity is created if it does not exist.
+    /// Without it, the node validates but cannot publish
+    #[clap(long)]
+    rln_relay_cred_path: Option<PathBuf>,
+
+    /// File holding the RLN proving key shared by the group
+    #[clap(long, default_value = "rln_keys.bin")]
+    rln_relay_keys_path: PathBuf,
+
+    /// Generate the RLN proving key if --rln-relay-keys-path does not exist. Only one node of
+    /// the group should do this, and share the file with the others
+    #[clap(long, action = clap::ArgAction::Set, default_value = "false")]
+    rln_relay_generate_keys: bool,
+
+    /// Depth of the RLN membership Merkle tree
+    #[clap(long, default_value_t = DEFAULT_TREE_DEPTH)]
+    rln_relay_tree_depth: usize,
+
+    /// Length of an RLN epoch, in seconds. Members may publish one message per epoch
+    #[clap(long, default_value_t = DEFAULT_EPOCH_PERIOD_SECS)]
+    rln_relay_epoch_period: u64,
+
+    /// Maximum number of epochs between a relayed message and the local clock
+    #[clap(long, default_value_t = DEFAULT_MAX_EPOCH_GAP)]
+    rln_relay_max_epoch_gap: u64,
+
+    /// Enable the REST API
+    #[clap(long, action = clap::ArgAction::Set, default_value = "true")]
+    rest: bool,
+
+    /// Address the REST API listens on
+    #[clap(long, default_value = "127.0.0.1")]
+    rest_address: IpAddr,
+
+    /// Port the REST API listens on
+    #[clap(long, default_value_t = 5000)]
+    rest_port: u16,
+
+    /// Origin allowed to make cross-origin requests to the REST API, e.g.
+    /// http://localhost:3000, or * for any. Option may be repeated
+    #[clap(long)]
+    rest_allow_origin: Vec<AllowedOrigin>,
+
+    /// PEM file with the certificate chain to serve the REST API over HTTPS with
+    #[clap(long, requires = "rest-tls-key")]
+    rest_tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of --rest-tls-cert
+    #[clap(long, requires = "rest-tls-cert")]
+    rest_tls_key: Option<PathBuf>,
+
+    /// Bearer token that REST API requests must carry in their Authorization header
+    #[clap(long)]
+    rest_auth_token: Option<String>,
+
+    /// Enable the JSON-RPC API (16/WAKU2-RPC)
+    #[clap(long, action = clap::ArgAction::Set, default_value = "false")]
+    rpc: bool,
+
+    /// Address the JSON-RPC API listens on
+    #[clap(long, default_value = "127.0.0.1")]
+    rpc_address: IpAddr,
+
+    /// Port the JSON-RPC API listens on
+    #[clap(long, default_value_t = 8545)]
+    rpc_port: u16,
+
+    /// PEM file with the certificate chain to serve the JSON-RPC API over HTTPS with
+    #[clap(long, requires = "rpc-tls-key")]
+    rpc_tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of --rpc-tls-cert
+    #[clap(long, requires = "rpc-tls-cert")]
+    rpc_tls_key: Option<PathBuf>,
+
+    /// Bearer token that JSON-RPC API requests must carry in their Authorization header
+    #[clap(long)]
+    rpc_auth_token: Option<String>,
+
+    /// Maximum number of messages cached per subscribed topic for REST and JSON-RPC
+    /// polling. The oldest ones are dropped first
+    #[clap(long, default_value_t = DEFAULT_RELAY_CACHE_CAPACITY)]
+    rest_relay_cache_capacity: usize,
+
     /// Multiaddr of peer to directly connect with. Option may be repeated
     #[clap(long)]
     static_node: Option<Vec<Multiaddr>>,
@@ -42,25 +235,259 @@ struct Cli {
     #[clap(long, default_value = "50000")]
     store_capacity: usize,
 
+    /// Maximum time messages are stored, in seconds. Unlimited if not set
+    #[clap(long)]
+    store_retention_time: Option<u64>,
+
+    /// Maximum total size of stored messages, in bytes. Unlimited if not set
+    #[clap(long)]
+    store_retention_size: Option<usize>,
+
+    /// Maximum number of stored messages of a topic, as <pubsub_topic>=<max> or
+    /// <pubsub_topic>,<content_topic>=<max>. Option may be repeated
+    #[clap(long)]
+    store_topic_retention: Vec<TopicRetention>,
+
+    /// Multiaddr, including /p2p/<peer id>, of a store node to send history queries to,
+    /// instead of the local archive. Queries fail over to, or fan out across, all of them.
+    /// Option may be repeated
+    #[clap(long)]
+    storenode: Vec<Multiaddr>,
+
+    /// Send each history query to all --storenode peers at once and merge their results,
+    /// instead of failing over from one to the next
+    #[clap(long, action = clap::ArgAction::Set, default_value = "false")]
+    storenode_fan_out: bool,
+
+    /// Multiaddr, including /p2p/<peer id>, of a store node to backfill missed history
+    /// from on every connection to it. Option may be repeated
+    #[clap(long)]
+    store_resume_node: Vec<Multiaddr>,
+
+    /// How far back store resume goes at most, in seconds
+    #[clap(long, default_value_t = DEFAULT_RESUME_WINDOW.as_secs())]
+    store_resume_window: u64,
+
+    /// Multiaddr, including /p2p/<peer id>, of a store node to keep the archive in sync
+    /// with. Sync requests are only served to these nodes, so both sides must list each other.
+    /// Option may be repeated
+    #[clap(long)]
+    store_sync_node: Vec<Multiaddr>,
+
+    /// Interval between store syncs, in seconds
+    #[clap(long, default_value_t = DEFAULT_SYNC_INTERVAL.as_secs())]
+    store_sync_interval: u64,
+
+    /// Maximum store queries per peer, as <volume>/<period> with the period in s, m or h,
+    /// e.g. 100/1s. Unlimited if not set
+    #[clap(long)]
+    store_rate_limit: Option<RateLimit>,
+
     /// Enable lightpush protocol
     #[clap(long, action = clap::ArgAction::Set, default_value = "false")]
     lightpush: bool,
+
+    /// Maximum lightpush requests per peer, as <volume>/<period>. Unlimited if not set
+    #[clap(long)]
+    lightpush_rate_limit: Option<RateLimit>,
+
+    /// Multiaddr, including /p2p/<peer id>, of a lightpush service node to push messages
+    /// from the REST API through. The first one is used. Option may be repeated
+    #[clap(long)]
+    lightpushnode: Vec<Multiaddr>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage the message archive of a running store node
+    #[clap(subcommand)]
+    Store(StoreCommand),
+}
+
+#[derive(Subcommand)]
+enum StoreCommand {
+    /// Save the archive of a store node to a JSON lines file
+    Export {
+        file: PathBuf,
+
+        /// URL of the node's REST API, http or https
+        #[clap(long, default_value = "http://127.0.0.1:5000")]
+        rest_url: String,
+
+        /// Bearer token of the node's REST API
+        #[clap(long)]
+        rest_auth_token: Option<String>,
+
+        /// PEM file with the certificates to trust for an https --rest-url, e.g. the
+        /// --rest-tls-cert of the node. The Mozilla root certificates are trusted if not set
+        #[clap(long)]
+        rest_tls_ca: Option<PathBuf>,
+    },
+    /// Archive the messages of an exported file, skipping the ones already archived. Messages
+    /// are validated like relayed ones, except for their age, and rejected if they fail
+    Import {
+        file: PathBuf,
+
+        /// URL of the node's REST API, http or https
+        #[clap(long, default_value = "http://127.0.0.1:5000")]
+        rest_url: String,
+
+        /// Bearer token of the node's REST API
+        #[clap(long)]
+        rest_auth_token: Option<String>,
+
+        /// PEM file with the certificates to trust for an https --rest-url, e.g. the
+        /// --rest-tls-cert of the node. The Mozilla root certificates are trusted if not set
+        #[clap(long)]
+        rest_tls_ca: Option<PathBuf>,
+    },
+}
+
+// Relay messages on their way to the cache of each API
+const RELAY_CACHE_CHANNEL_CAPACITY: usize = 1024;
+
+// Copies a relay message to the caches of the REST and JSON-RPC APIs, without holding back
+// the swarm loop. A message that finds the channel of an API full is counted as dropped by
+// its cache. Sends to a disabled API fail and are ignored
+fn cache_relay_message(
+    relay_cache_txs: &[(mpsc::Sender<(WakuMessage, String)>, Counter)],
+    waku_message: WakuMessage,
+    topic: String,
+) {
+    for (tx, dropped) in relay_cache_txs {
+        if let Err(TrySendError::Full(_)) = tx.try_send((waku_message.clone(), topic.clone())) {
+            dropped.inc();
+        }
+    }
+}
+
+// The merged results of a query to the --storenode peers, as a single page without cursor
+// since the peers page their archives differently
+fn merged_store_response(result: StoreQueryResult) -> Result<HistoryResponse, String> {
+    if result.succeeded.is_empty() {
+        return Err(format!(
+            "none of the {} store peers answered the query",
+            result.failed.len()
+        ));
+    }
+    let mut response = HistoryResponse::new();
+    response.set_messages(RepeatedField::from_vec(result.messages));
+    Ok(response)
+}
+
+// The peer a /p2p/<peer id> terminated multiaddr points to
+fn peer_id(address: &Multiaddr) -> Result<PeerId, Box<dyn Error>> {
+    match address.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok(peer_id),
+        _ => Err(format!("{} has no /p2p/<peer id>", address).into()),
+    }
+}
+
+// Loads, or creates on first run, the RLN credentials and keys, and the static group
+fn rln_relay(args: &Cli) -> Result<WakuRlnRelay, Box<dyn Error>> {
+    let config = WakuRlnConfig {
+        tree_depth: args.rln_relay_tree_depth,
+        epoch_period: Duration::from_secs(args.rln_relay_epoch_period),
+        max_epoch_gap: args.rln_relay_max_epoch_gap,
+        roots_window: DEFAULT_ROOTS_WINDOW,
+    };
+
+    let membership = match &args.rln_relay_membership_file {
+        Some(path) => StaticMembership::from_file(path)?,
+        None => return Err("--rln-relay requires --rln-relay-membership-file".into()),
+    };
+
+    let credential = match &args.rln_relay_cred_path {
+        Some(path) if path.exists() => Some(IdentityCredential::load(path)?),
+        Some(path) => {
+            let credential = IdentityCredential::generate(&mut rand::rngs::OsRng);
+            credential.save(path)?;
+            info!("Created RLN credentials in {:?}", path);
+            Some(credential)
+        }
+        None => None,
+    };
+    if let Some(credential) = &credential {
+        info!(
+            "RLN id commitment: {}",
+            fr_to_hex(&credential.id_commitment)
+        );
+    }
+
+    let keys = match args.rln_relay_keys_path.exists() {
+        true => RlnKeys::load(&args.rln_relay_keys_path)?,
+        false if !args.rln_relay_generate_keys => {
+            return Err(format!(
+                "RLN keys {:?} not found, copy them from another node of the group or pass \
+                 --rln-relay-generate-keys true",
+                args.rln_relay_keys_path
+            )
+            .into())
+        }
+        false => {
+            info!(
+                "Generating RLN keys in {:?}, every node of the group must use this file",
+                args.rln_relay_keys_path
+            );
+            let keys = RlnKeys::generate(config.tree_depth, &mut rand::rngs::OsRng)?;
+            keys.save(&args.rln_relay_keys_path)?;
+            keys
+        }
+    };
+
+    let rln = WakuRlnRelay::new(config, keys, membership, credential)?;
+    if args.rln_relay_cred_path.is_some() && rln.member_index().is_none() {
+        info!("RLN id commitment is not in the membership file, publishing is disabled");
+    }
+    Ok(rln)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+        // RLN proving emits tracing spans for every constraint, which end up in log
+        env_logger::Env::default().filter_or(
+            env_logger::DEFAULT_FILTER_ENV,
+            "info,tracing=warn,r1cs=warn",
+        ),
     );
 
     let args = Cli::parse();
 
+    match &args.command {
+        Some(Command::Store(StoreCommand::Export {
+            file,
+            rest_url,
+            rest_auth_token,
+            rest_tls_ca,
+        })) => {
+            let client = RestClient {
+                url: rest_url,
+                auth_token: rest_auth_token.as_deref(),
+                tls_ca: rest_tls_ca.as_deref(),
+            };
+            return archive::export(&client, file).await;
+        }
+        Some(Command::Store(StoreCommand::Import {
+            file,
+            rest_url,
+            rest_auth_token,
+            rest_tls_ca,
+        })) => {
+            let client = RestClient {
+                url: rest_url,
+                auth_token: rest_auth_token.as_deref(),
+                tls_ca: rest_tls_ca.as_deref(),
+            };
+            return archive::import(&client, file).await;
+        }
+        None => {}
+    }
+
     let local_key = Keypair::generate_ed25519();
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer id: {:?}", local_peer_id);
 
-    let transport = libp2p::development_transport(local_key.clone()).await?;
-
     if args.store && args.lightpush {
         panic!(
             "This implementation cannot run Store and LightPush at the same time! \
@@ -68,46 +495,216 @@ async fn main() -> Result<(), Box<dyn Error>> {
         );
     }
 
-    let mut waku_node_behaviour =
-        WakuNodeBehaviour::new(args.relay, args.store, args.store_capacity, args.lightpush);
-
-    match args.topics {
-        Some(topics) => {
-            for t in topics {
-                waku_node_behaviour.subscribe(&t).unwrap();
-            }
+    let peer_scoring = match args.relay_peer_scoring {
+        true => {
+            let mut scoring = WakuRelayScoringConfig::default();
+            scoring.thresholds.gossip_threshold = args.relay_gossip_threshold;
+            scoring.thresholds.publish_threshold = args.relay_publish_threshold;
+            scoring.thresholds.graylist_threshold = args.relay_graylist_threshold;
+            Some(scoring)
         }
-        None => waku_node_behaviour.subscribe(DEFAULT_PUBSUB_TOPIC).unwrap(),
+        false => None,
+    };
+
+    let relay_config = WakuRelayConfig {
+        mesh_n: args.relay_mesh_n,
+        mesh_n_low: args.relay_mesh_n_low,
+        mesh_n_high: args.relay_mesh_n_high,
+        heartbeat_interval: Duration::from_millis(args.relay_heartbeat_interval),
+        history_length: args.relay_history_length,
+        max_transmit_size: args.relay_max_transmit_size,
+        flood_publish: args.relay_flood_publish,
+        duplicate_cache_time: Duration::from_secs(args.relay_duplicate_cache_time),
+        max_timestamp_drift: Duration::from_secs(args.relay_max_timestamp_drift),
+        peer_scoring,
+        protected_topics: args.protected_topic.clone(),
+    };
+
+    let mut waku_node_behaviour = WakuNodeBehaviour::new(
+        local_key.public(),
+        args.relay,
+        args.store,
+        args.store_capacity,
+        args.lightpush,
+        relay_config,
+    );
+
+    if let Some(secs) = args.store_retention_time {
+        waku_node_behaviour.add_store_retention_policy(TimeRetention {
+            max_age: Duration::from_secs(secs),
+        });
+    }
+    if let Some(max_bytes) = args.store_retention_size {
+        waku_node_behaviour.add_store_retention_policy(SizeRetention { max_bytes });
+    }
+    for policy in args.store_topic_retention.clone() {
+        waku_node_behaviour.add_store_retention_policy(policy);
     }
 
-    let mut swarm = Swarm::new(transport, waku_node_behaviour, local_peer_id);
+    waku_node_behaviour.set_store_resume_window(Duration::from_secs(args.store_resume_window));
+    for a in &args.storenode {
+        waku_node_behaviour.add_store_peer(peer_id(a)?);
+    }
+    for a in &args.store_resume_node {
+        waku_node_behaviour.add_store_resume_peer(peer_id(a)?);
+    }
+    waku_node_behaviour.set_store_sync_interval(Duration::from_secs(args.store_sync_interval));
+    for a in &args.store_sync_node {
+        waku_node_behaviour.add_store_sync_peer(peer_id(a)?);
+    }
+
+    waku_node_behaviour.set_rate_limits(args.store_rate_limit, args.lightpush_rate_limit);
+
+    let mut metrics_registry = Registry::default();
+    waku_node_behaviour.register_metrics(&mut metrics_registry);
+
+    if args.rln_relay {
+        waku_node_behaviour.enable_rln(rln_relay(&args)?);
+    }
+
+    let mut relay_cache = RelayCache::new(args.rest_relay_cache_capacity);
+    relay_cache.register_metrics(&mut metrics_registry);
+    let mut rpc_relay_cache = RelayCache::new(args.rest_relay_cache_capacity);
+    let topics = match args.topics {
+        Some(topics) => topics,
+        None => vec![DEFAULT_PUBSUB_TOPIC.to_string()],
+    };
+    for t in topics {
+        waku_node_behaviour.subscribe(&t).unwrap();
+        relay_cache.subscribe(&t);
+        rpc_relay_cache.subscribe(&t);
+    }
+
+    let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_dns()?
+        .with_websocket(noise::Config::new, yamux::Config::default)
+        .await?
+        .with_behaviour(|_| waku_node_behaviour)?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
     swarm
         .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
         .unwrap();
 
-    match args.static_node {
-        Some(addresses) => {
-            for a in addresses {
-                match swarm.dial(a.clone()) {
-                    Ok(_) => info!("Dialed {:?}", a),
-                    Err(e) => panic!("Failed to dial address: {:?} {:?}", a, e),
-                }
+    for a in args
+        .storenode
+        .iter()
+        .chain(&args.store_resume_node)
+        .chain(&args.store_sync_node)
+    {
+        match swarm.dial(a.clone()) {
+            Ok(_) => info!("Dialed store node {:?}", a),
+            Err(e) => info!("Failed to dial store node: {:?} {:?}", a, e),
+        }
+    }
+
+    let mut lightpush_nodes = Vec::new();
+    for a in &args.lightpushnode {
+        lightpush_nodes.push((peer_id(a)?, a.clone()));
+        match swarm.dial(a.clone()) {
+            Ok(_) => info!("Dialed lightpush node {:?}", a),
+            Err(e) => info!("Failed to dial lightpush node: {:?} {:?}", a, e),
+        }
+    }
+
+    if let Some(addresses) = args.static_node {
+        for a in addresses {
+            match swarm.dial(a.clone()) {
+                Ok(_) => info!("Dialed {:?}", a),
+                Err(e) => panic!("Failed to dial address: {:?} {:?}", a, e),
             }
         }
-        None => {}
     }
 
-    let (relay_cache_tx, relay_cache_rx) = mpsc::channel(32);
+    let (relay_cache_tx, relay_cache_rx) = mpsc::channel(RELAY_CACHE_CHANNEL_CAPACITY);
+    let (rpc_relay_cache_tx, rpc_relay_cache_rx) = mpsc::channel(RELAY_CACHE_CHANNEL_CAPACITY);
+    let relay_cache_txs = [
+        (relay_cache_tx, relay_cache.dropped_counter()),
+        (rpc_relay_cache_tx, rpc_relay_cache.dropped_counter()),
+    ];
     let (relay_publish_tx, mut relay_publish_rx) = mpsc::channel(32);
     let (relay_subscribe_tx, mut relay_subscribe_rx) = mpsc::channel(32);
     let (relay_unsubscribe_tx, mut relay_unsubscribe_rx) = mpsc::channel(32);
+    let (peer_scores_tx, mut peer_scores_rx) = mpsc::channel(32);
+    let (admin_tx, mut admin_rx) = mpsc::channel(32);
+    let (lightpush_tx, mut lightpush_rx) = mpsc::channel(32);
+    let (store_query_tx, mut store_query_rx) = mpsc::channel(32);
+    let (store_archive_tx, mut store_archive_rx) = mpsc::channel(32);
+
+    // The senders of a disabled API are never used, but stay alive in this scope, so its
+    // channels never close. Their branches below are turned off instead: those shared by
+    // both APIs with api_enabled, those of the REST API only with args.rest
+    let api_enabled = args.rest || args.rpc;
+    if args.rpc {
+        let listener = tokio::net::TcpListener::bind((args.rpc_address, args.rpc_port)).await?;
+        info!("JSON-RPC API listening on {}", listener.local_addr()?);
+        let tls = match (&args.rpc_tls_cert, &args.rpc_tls_key) {
+            (Some(cert), Some(key)) => Some(rest_api::tls_acceptor(cert, key)?),
+            _ => None,
+        };
+        let config = RpcConfig {
+            listener,
+            tls,
+            auth_token: args.rpc_auth_token.clone(),
+        };
+        tokio::spawn(rpc_api::serve(
+            config,
+            rpc_relay_cache,
+            rpc_relay_cache_rx,
+            relay_publish_tx.clone(),
+            relay_subscribe_tx.clone(),
+            relay_unsubscribe_tx.clone(),
+            admin_tx.clone(),
+            lightpush_tx.clone(),
+            store_query_tx.clone(),
+        ));
+    }
+    if args.rest {
+        let listener = tokio::net::TcpListener::bind((args.rest_address, args.rest_port)).await?;
+        info!("REST API listening on {}", listener.local_addr()?);
+        let tls = match (&args.rest_tls_cert, &args.rest_tls_key) {
+            (Some(cert), Some(key)) => Some(rest_api::tls_acceptor(cert, key)?),
+            _ => None,
+        };
+        let config = RestConfig {
+            listener,
+            allow_origins: args.rest_allow_origin.clone(),
+            tls,
+            auth_token: args.rest_auth_token.clone(),
+        };
+        tokio::spawn(rest_api::serve(
+            config,
+            relay_cache,
+            relay_cache_rx,
+            relay_publish_tx,
+            relay_subscribe_tx,
+            relay_unsubscribe_tx,
+            peer_scores_tx,
+            admin_tx,
+            lightpush_tx,
+            store_query_tx,
+            store_archive_tx,
+            Arc::new(metrics_registry),
+        ));
+    }
 
-    tokio::spawn(rest_api::serve(
-        relay_cache_rx,
-        relay_publish_tx,
-        relay_subscribe_tx,
-        relay_unsubscribe_tx,
-    ));
+    let mut connected_peers: HashMap<PeerId, PeerInfo> = HashMap::new();
+    // Replies to the REST API for requests sent to remote peers
+    let mut store_queries: HashMap<request_response::OutboundRequestId, StoreQueryReply> =
+        HashMap::new();
+    let mut store_client_queries: HashMap<u64, StoreQueryReply> = HashMap::new();
+    let store_query_strategy = match args.storenode_fan_out {
+        true => QueryStrategy::FanOut(args.storenode.len()),
+        false => QueryStrategy::FailOver,
+    };
+    let mut lightpush_requests: HashMap<request_response::OutboundRequestId, LightPushReply> =
+        HashMap::new();
 
     loop {
         tokio::select! {
@@ -115,7 +712,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 info!("{:?}", event);
                 match event {
                     SwarmEvent::Behaviour(WakuNodeEvent::WakuRelayBehaviour(
-                        WakuRelayEvent::GossipSub(GossipsubEvent::Message {
+                        WakuRelayEvent::GossipSub(gossipsub::Event::Message {
                             propagation_source: _,
                             message_id: _,
                             message,
@@ -123,7 +720,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     ))
                     | SwarmEvent::Behaviour(WakuNodeEvent::WakuStoreBehaviour(
                         WakuStoreEvent::WakuRelayBehaviour(WakuRelayEvent::GossipSub(
-                            GossipsubEvent::Message {
+                            gossipsub::Event::Message {
                                 propagation_source: _,
                                 message_id: _,
                                 message,
@@ -132,7 +729,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     ))
                     | SwarmEvent::Behaviour(WakuNodeEvent::WakuLightPushBehaviour(
                         WakuLightPushEvent::WakuRelayBehaviour(WakuRelayEvent::GossipSub(
-                            GossipsubEvent::Message {
+                            gossipsub::Event::Message {
                                 propagation_source: _,
                                 message_id: _,
                                 message,
@@ -142,38 +739,216 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         let topic = message.topic.into_string();
                         let mut waku_message = WakuMessage::new();
                         waku_message.merge_from_bytes(&message.data).unwrap();
-                        relay_cache_tx.send((waku_message, topic)).await.unwrap();
+                        cache_relay_message(&relay_cache_txs, waku_message, topic);
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::WakuRelayBehaviour(
+                        WakuRelayEvent::ProvenMessagePublished { pubsub_topic, message },
+                    ))
+                    | SwarmEvent::Behaviour(WakuNodeEvent::WakuStoreBehaviour(
+                        WakuStoreEvent::WakuRelayBehaviour(
+                            WakuRelayEvent::ProvenMessagePublished { pubsub_topic, message },
+                        ),
+                    ))
+                    | SwarmEvent::Behaviour(WakuNodeEvent::WakuLightPushBehaviour(
+                        WakuLightPushEvent::WakuRelayBehaviour(
+                            WakuRelayEvent::ProvenMessagePublished { pubsub_topic, message },
+                        ),
+                    )) => {
+                        cache_relay_message(&relay_cache_txs, message, pubsub_topic);
+                    }
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        let info = connected_peers.entry(peer_id).or_default();
+                        if !info.addresses.contains(endpoint.get_remote_address()) {
+                            info.addresses.push(endpoint.get_remote_address().clone());
+                        }
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, endpoint, num_established, .. } => {
+                        match num_established {
+                            0 => {
+                                connected_peers.remove(&peer_id);
+                            }
+                            _ => {
+                                if let Some(info) = connected_peers.get_mut(&peer_id) {
+                                    info.addresses.retain(|a| a != endpoint.get_remote_address());
+                                }
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::Identify(event)) => {
+                        if let identify::Event::Received { peer_id, info, .. } = *event {
+                            if let Some(peer) = connected_peers.get_mut(&peer_id) {
+                                peer.protocols = info.protocols.iter().map(|p| p.to_string()).collect();
+                                peer.agent_version = Some(info.agent_version);
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::WakuStoreClientBehaviour(
+                        WakuStoreClientEvent::RequestResponseBehaviour(request_response::Event::Message {
+                            message: request_response::Message::Response { request_id, response },
+                            ..
+                        }),
+                    )) => {
+                        if let Some(reply_tx) = store_queries.remove(&request_id) {
+                            let _ = reply_tx.send(Some(Ok(response.get_response().clone())));
+                        }
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::WakuStoreClientBehaviour(
+                        WakuStoreClientEvent::RequestResponseBehaviour(
+                            request_response::Event::OutboundFailure { request_id, error, .. },
+                        ),
+                    )) => {
+                        if let Some(reply_tx) = store_queries.remove(&request_id) {
+                            let _ = reply_tx.send(Some(Err(error.to_string())));
+                        }
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::WakuStoreClientBehaviour(
+                        WakuStoreClientEvent::QueryCompleted(result),
+                    )) => {
+                        if let Some(reply_tx) = store_client_queries.remove(&result.query_id) {
+                            let _ = reply_tx.send(Some(merged_store_response(result)));
+                        }
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::WakuLightPushBehaviour(
+                        WakuLightPushEvent::RequestResponseBehaviour(request_response::Event::Message {
+                            message: request_response::Message::Response { request_id, response },
+                            ..
+                        }),
+                    )) => {
+                        if let Some(reply_tx) = lightpush_requests.remove(&request_id) {
+                            let _ = reply_tx.send(Ok(response.get_response().clone()));
+                        }
+                    }
+                    SwarmEvent::Behaviour(WakuNodeEvent::WakuLightPushBehaviour(
+                        WakuLightPushEvent::RequestResponseBehaviour(
+                            request_response::Event::OutboundFailure { request_id, error, .. },
+                        ),
+                    )) => {
+                        if let Some(reply_tx) = lightpush_requests.remove(&request_id) {
+                            let _ = reply_tx.send(Err(LightPushError::Failed(error.to_string())));
+                        }
                     }
                     _ => {}
                 }
             },
-            relay_post = relay_publish_rx.recv() => {
+            relay_post = relay_publish_rx.recv(), if api_enabled => {
                 if let Some((waku_message, topic)) = relay_post {
+                    // Deferred messages are cached once their RLN proof is published
                     match swarm.behaviour_mut().publish(&topic, waku_message.clone()) {
-                        Ok(_) => info!("Published message to Relay via REST API"),
-                        Err(e) => info!("Error publishing message to Relay via REST API: {}", e),
+                        Ok(true) => {
+                            info!("Published message to Relay via API");
+                            cache_relay_message(&relay_cache_txs, waku_message, topic);
+                        }
+                        Ok(false) => info!("Generating RLN proof for message published via API"),
+                        Err(e) => info!("Error publishing message to Relay via API: {}", e),
                     };
-                    relay_cache_tx.send((waku_message, topic)).await.unwrap()
                 }
             },
-            subscribe = relay_subscribe_rx.recv() => {
+            subscribe = relay_subscribe_rx.recv(), if api_enabled => {
                 if let Some(topics) = subscribe {
                     for t in topics {
                         match swarm.behaviour_mut().subscribe(&t) {
-                            Ok(_) => info!("Relay subscribed to PubSub Topic \"{}\" via REST API", t),
-                            Err(e) => info!("Error subscribing Relay to PubSub Topic \"{}\" via REST API: {}", t, e),
+                            Ok(_) => info!("Relay subscribed to PubSub Topic \"{}\" via API", t),
+                            Err(e) => info!("Error subscribing Relay to PubSub Topic \"{}\" via API: {}", t, e),
                         }
                     }
                 }
             },
-            unsubscribe = relay_unsubscribe_rx.recv() => {
+            unsubscribe = relay_unsubscribe_rx.recv(), if api_enabled => {
                 if let Some(topics) = unsubscribe {
                     for t in topics {
                         match swarm.behaviour_mut().unsubscribe(&t) {
-                            Ok(_) => info!("Relay unsubscribed from PubSub Topic \"{}\" via REST API", t),
-                            Err(e) => info!("Error unsubscribing Relay from PubSub Topic \"{}\" via REST API: {}", t, e),
+                            Ok(_) => info!("Relay unsubscribed from PubSub Topic \"{}\" via API", t),
+                            Err(e) => info!("Error unsubscribing Relay from PubSub Topic \"{}\" via API: {}", t, e),
+                        }
+                    }
+                }
+            },
+            peer_scores = peer_scores_rx.recv(), if args.rest => {
+                if let Some(reply_tx) = peer_scores {
+                    let _ = reply_tx.send(swarm.behaviour().peer_scores());
+                }
+            },
+            admin = admin_rx.recv(), if api_enabled => {
+                match admin {
+                    Some(AdminRequest::Info(reply_tx)) => {
+                        let _ = reply_tx.send((*swarm.local_peer_id(), swarm.listeners().cloned().collect()));
+                    }
+                    Some(AdminRequest::Peers(reply_tx)) => {
+                        let _ = reply_tx.send(connected_peers.iter().map(|(p, i)| (*p, i.clone())).collect());
+                    }
+                    Some(AdminRequest::Dial(addresses, reply_tx)) => {
+                        let dialed = addresses.into_iter().try_for_each(|a| {
+                            info!("Dialing {:?} via API", a);
+                            swarm.dial(a.clone()).map_err(|e| format!("{}: {}", a, e))
+                        });
+                        let _ = reply_tx.send(dialed);
+                    }
+                    Some(AdminRequest::Disconnect(peer_id, reply_tx)) => {
+                        let _ = reply_tx.send(swarm.disconnect_peer_id(peer_id).is_ok());
+                    }
+                    Some(AdminRequest::RelaySubscriptions(reply_tx)) => {
+                        let _ = reply_tx.send(swarm.behaviour().subscribed_topics());
+                    }
+                    None => {}
+                }
+            },
+            lightpush = lightpush_rx.recv(), if api_enabled => {
+                if let Some(LightPushRequest { request_id, pubsub_topic, message, peer, reply_tx }) = lightpush {
+                    match peer.or_else(|| lightpush_nodes.first().cloned()) {
+                        Some((peer_id, address)) => {
+                            swarm.add_peer_address(peer_id, address);
+                            match swarm.behaviour_mut().send_lightpush_request(peer_id, request_id, pubsub_topic, message) {
+                                Some(id) => {
+                                    lightpush_requests.insert(id, reply_tx);
+                                }
+                                None => {
+                                    let _ = reply_tx.send(Err(LightPushError::NotEnabled));
+                                }
+                            }
+                        }
+                        None => {
+                            let _ = reply_tx.send(Err(LightPushError::NoPeer));
+                        }
+                    }
+                }
+            },
+            store_query = store_query_rx.recv(), if api_enabled => {
+                if let Some(StoreQueryRequest { request_id, query, peer, reply_tx }) = store_query {
+                    match peer {
+                        Some((peer_id, address)) => {
+                            swarm.add_peer_address(peer_id, address);
+                            let id = swarm.behaviour_mut().send_store_query(peer_id, request_id, query);
+                            store_queries.insert(id, reply_tx);
+                        }
+                        None => match swarm.behaviour_mut().store_query(query.clone(), store_query_strategy) {
+                            Some(query_id) => {
+                                store_client_queries.insert(query_id, reply_tx);
+                            }
+                            // Without --storenode, the local archive answers
+                            None => {
+                                let _ = reply_tx.send(swarm.behaviour().store_local_query(&query).map(Ok));
+                            }
+                        },
+                    }
+                }
+            },
+            store_archive = store_archive_rx.recv(), if args.rest => {
+                match store_archive {
+                    Some(StoreArchiveRequest::Export(reply_tx)) => {
+                        let _ = reply_tx.send(swarm.behaviour().store_export());
+                    }
+                    Some(StoreArchiveRequest::Import(messages, reply_tx)) => {
+                        let counts = swarm.behaviour_mut().store_import(messages);
+                        if let Some(counts) = counts {
+                            info!(
+                                "Imported {} messages to the store archive via REST API, \
+                                rejected {}",
+                                counts.imported, counts.rejected
+                            );
                         }
+                        let _ = reply_tx.send(counts);
                     }
+                    None => {}
                 }
             }
         }
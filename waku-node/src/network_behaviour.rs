@@ -1,26 +1,48 @@
-use libp2p::gossipsub::error::{PublishError, SubscriptionError};
-use libp2p::swarm::behaviour::toggle::Toggle;
-use libp2p::NetworkBehaviour;
+use libp2p::gossipsub::{PublishError, SubscriptionError};
+use libp2p::swarm::{behaviour::toggle::Toggle, NetworkBehaviour};
+use libp2p::{identify, identity::PublicKey, request_response::OutboundRequestId, PeerId};
+use prometheus_client::registry::Registry;
+use std::time::Duration;
 use waku_protocol::{
+    rate_limit::RateLimit,
     waku_lightpush::network_behaviour::{WakuLightPushBehaviour, WakuLightPushEvent},
     waku_message::WakuMessage,
-    waku_relay::network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
-    waku_store::network_behaviour::{WakuStoreBehaviour, WakuStoreEvent},
+    waku_relay::{
+        config::WakuRelayConfig,
+        network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
+    },
+    waku_rln_relay::rln::WakuRlnRelay,
+    waku_store::{
+        client::{QueryStrategy, WakuStoreClientBehaviour, WakuStoreClientEvent},
+        message_queue::IndexedWakuMessage,
+        network_behaviour::{ArchiveImportCounts, WakuStoreBehaviour, WakuStoreEvent},
+        retention::RetentionPolicy,
+        HistoryQuery, HistoryResponse,
+    },
 };
 
+const IDENTIFY_PROTOCOL_VERSION: &str = "/vac/waku/2.0.0";
+
 #[derive(NetworkBehaviour)]
-#[behaviour(out_event = "WakuNodeEvent")]
+#[behaviour(to_swarm = "WakuNodeEvent")]
 pub struct WakuNodeBehaviour {
     relay: Toggle<WakuRelayBehaviour>,
     store: Toggle<WakuStoreBehaviour>,
     lightpush: Toggle<WakuLightPushBehaviour>,
+    // Sends history queries, with or without store enabled
+    store_client: WakuStoreClientBehaviour,
+    // Learns the protocols of connected peers, for the admin REST API
+    identify: identify::Behaviour,
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum WakuNodeEvent {
     WakuRelayBehaviour(WakuRelayEvent),
     WakuStoreBehaviour(WakuStoreEvent),
     WakuLightPushBehaviour(WakuLightPushEvent),
+    WakuStoreClientBehaviour(WakuStoreClientEvent),
+    Identify(Box<identify::Event>),
 }
 
 impl From<WakuRelayEvent> for WakuNodeEvent {
@@ -41,117 +63,251 @@ impl From<WakuLightPushEvent> for WakuNodeEvent {
     }
 }
 
+impl From<WakuStoreClientEvent> for WakuNodeEvent {
+    fn from(event: WakuStoreClientEvent) -> Self {
+        Self::WakuStoreClientBehaviour(event)
+    }
+}
+
+impl From<identify::Event> for WakuNodeEvent {
+    fn from(event: identify::Event) -> Self {
+        Self::Identify(Box::new(event))
+    }
+}
+
 impl WakuNodeBehaviour {
     pub fn new(
+        local_public_key: PublicKey,
         relay_enabled: bool,
         store_enabled: bool,
         store_capacity: usize,
         lightpush_enabled: bool,
+        relay_config: WakuRelayConfig,
     ) -> Self {
         // Store and LightPush already have Relay
         let relay = match relay_enabled && !(store_enabled || lightpush_enabled) {
-            true => Toggle::from(Some(WakuRelayBehaviour::new())),
+            true => Toggle::from(Some(WakuRelayBehaviour::new(relay_config.clone()))),
             false => Toggle::from(None),
         };
 
         let store = match store_enabled {
-            true => Toggle::from(Some(WakuStoreBehaviour::new(store_capacity))),
+            true => Toggle::from(Some(WakuStoreBehaviour::new(
+                store_capacity,
+                relay_config.clone(),
+            ))),
             false => Toggle::from(None),
         };
 
         let lightpush = match lightpush_enabled {
-            true => Toggle::from(Some(WakuLightPushBehaviour::new())),
+            true => Toggle::from(Some(WakuLightPushBehaviour::new(relay_config))),
             false => Toggle::from(None),
         };
 
+        let identify = identify::Behaviour::new(
+            identify::Config::new(IDENTIFY_PROTOCOL_VERSION.to_string(), local_public_key)
+                .with_agent_version(format!("waku-rs/{}", env!("CARGO_PKG_VERSION"))),
+        );
+
         WakuNodeBehaviour {
             relay,
             store,
             lightpush,
+            store_client: WakuStoreClientBehaviour::new(),
+            identify,
+        }
+    }
+
+    // Only one of the enabled protocols runs relay, see new()
+    pub fn enable_rln(&mut self, rln: WakuRlnRelay) {
+        if let Some(r) = self.relay.as_mut() {
+            r.enable_rln(rln);
+        } else if let Some(s) = self.store.as_mut() {
+            s.enable_rln(rln);
+        } else if let Some(l) = self.lightpush.as_mut() {
+            l.enable_rln(rln);
         }
     }
 
-    pub fn publish(&mut self, topic: &str, msg: WakuMessage) -> Result<(), PublishError> {
-        match self.relay.as_mut() {
-            Some(r) => match r.publish(topic, msg.clone()) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+    pub fn add_store_retention_policy(&mut self, policy: impl RetentionPolicy + 'static) {
+        if let Some(s) = self.store.as_mut() {
+            s.add_retention_policy(policy);
         }
+    }
+
+    pub fn add_store_peer(&mut self, peer_id: PeerId) {
+        self.store_client.add_peer(peer_id);
+    }
 
-        match self.lightpush.as_mut() {
-            Some(l) => match l.publish(topic, msg.clone()) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+    pub fn add_store_resume_peer(&mut self, peer_id: PeerId) {
+        if let Some(s) = self.store.as_mut() {
+            s.add_resume_peer(peer_id);
         }
+    }
 
-        match self.store.as_mut() {
-            Some(s) => match s.publish(topic, msg) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+    pub fn set_store_resume_window(&mut self, window: Duration) {
+        if let Some(s) = self.store.as_mut() {
+            s.set_resume_window(window);
         }
+    }
 
-        Ok(())
+    pub fn add_store_sync_peer(&mut self, peer_id: PeerId) {
+        if let Some(s) = self.store.as_mut() {
+            s.add_sync_peer(peer_id);
+        }
+    }
+
+    pub fn set_store_sync_interval(&mut self, interval: Duration) {
+        if let Some(s) = self.store.as_mut() {
+            s.set_sync_interval(interval);
+        }
+    }
+
+    // None if store is not enabled
+    pub fn store_local_query(&self, query: &HistoryQuery) -> Option<HistoryResponse> {
+        self.store.as_ref().map(|s| s.local_query(query))
+    }
+
+    // Queries the store peers added with add_store_peer. None if there are none
+    pub fn store_query(&mut self, query: HistoryQuery, strategy: QueryStrategy) -> Option<u64> {
+        self.store_client.query(query, strategy)
+    }
+
+    pub fn send_store_query(
+        &mut self,
+        peer_id: PeerId,
+        request_id: String,
+        query: HistoryQuery,
+    ) -> OutboundRequestId {
+        self.store_client
+            .send_history_query(peer_id, request_id, query)
+    }
+
+    pub fn store_export(&self) -> Option<Vec<IndexedWakuMessage>> {
+        self.store.as_ref().map(|s| s.export())
+    }
+
+    pub fn store_import(
+        &mut self,
+        messages: Vec<IndexedWakuMessage>,
+    ) -> Option<ArchiveImportCounts> {
+        self.store.as_mut().map(|s| s.import(messages))
+    }
+
+    // None if lightpush is not enabled
+    pub fn send_lightpush_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: String,
+        pubsub_topic: String,
+        msg: WakuMessage,
+    ) -> Option<OutboundRequestId> {
+        self.lightpush
+            .as_mut()
+            .map(|l| l.send_request(peer_id, request_id, pubsub_topic, msg))
+    }
+
+    pub fn set_rate_limits(
+        &mut self,
+        store_limit: Option<RateLimit>,
+        lightpush_limit: Option<RateLimit>,
+    ) {
+        if let Some(s) = self.store.as_mut() {
+            s.set_rate_limit(store_limit);
+        }
+
+        if let Some(l) = self.lightpush.as_mut() {
+            l.set_rate_limit(lightpush_limit);
+        }
+    }
+
+    pub fn register_metrics(&self, registry: &mut Registry) {
+        if let Some(s) = self.store.as_ref() {
+            s.register_metrics(registry);
+        }
+
+        if let Some(l) = self.lightpush.as_ref() {
+            l.register_metrics(registry);
+        }
+    }
+
+    // Returns false when the message waits for its RLN proof, see WakuRelayBehaviour::publish
+    pub fn publish(&mut self, topic: &str, msg: WakuMessage) -> Result<bool, PublishError> {
+        let mut published = false;
+        if let Some(r) = self.relay.as_mut() {
+            published |= r.publish(topic, msg.clone())?.is_some();
+        }
+
+        if let Some(l) = self.lightpush.as_mut() {
+            published |= l.publish(topic, msg.clone())?.is_some();
+        }
+
+        if let Some(s) = self.store.as_mut() {
+            published |= s.publish(topic, msg)?.is_some();
+        }
+
+        Ok(published)
     }
 
     pub fn subscribe(&mut self, topic: &str) -> Result<(), SubscriptionError> {
-        match self.relay.as_mut() {
-            Some(r) => match r.subscribe(topic) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
-        };
+        if let Some(r) = self.relay.as_mut() {
+            r.subscribe(topic)?;
+        }
 
-        match self.store.as_mut() {
-            Some(s) => match s.subscribe(topic) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+        if let Some(s) = self.store.as_mut() {
+            s.subscribe(topic)?;
         }
 
-        match self.lightpush.as_mut() {
-            Some(l) => match l.subscribe(topic) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+        if let Some(l) = self.lightpush.as_mut() {
+            l.subscribe(topic)?;
         }
 
         Ok(())
     }
 
     pub fn unsubscribe(&mut self, topic: &str) -> Result<(), PublishError> {
-        match self.relay.as_mut() {
-            Some(r) => match r.unsubscribe(topic) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
-        };
+        if let Some(r) = self.relay.as_mut() {
+            r.unsubscribe(topic)?;
+        }
 
-        match self.store.as_mut() {
-            Some(s) => match s.unsubscribe(topic) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+        if let Some(s) = self.store.as_mut() {
+            s.unsubscribe(topic)?;
         }
 
-        match self.lightpush.as_mut() {
-            Some(l) => match l.unsubscribe(topic) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            },
-            None => {}
+        if let Some(l) = self.lightpush.as_mut() {
+            l.unsubscribe(topic)?;
         }
 
         Ok(())
     }
+
+    pub fn peer_scores(&self) -> Vec<(PeerId, f64)> {
+        if let Some(r) = self.relay.as_ref() {
+            return r.peer_scores();
+        }
+
+        if let Some(s) = self.store.as_ref() {
+            return s.peer_scores();
+        }
+
+        if let Some(l) = self.lightpush.as_ref() {
+            return l.peer_scores();
+        }
+
+        Vec::new()
+    }
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        if let Some(r) = self.relay.as_ref() {
+            return r.subscribed_topics();
+        }
+
+        if let Some(s) = self.store.as_ref() {
+            return s.subscribed_topics();
+        }
+
+        if let Some(l) = self.lightpush.as_ref() {
+            return l.subscribed_topics();
+        }
+
+        Vec::new()
+    }
 }
@@ -0,0 +1,440 @@
+// OpenAPI 3 document of the REST API, served at /openapi.json. Routes are declared with
+// their path template from here, and the tests of rest_api.rs check that every operation
+// is routed and that replies match it. Schemas and query parameters are derived from the
+// serde types of rest_api.rs
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+use warp::http::Method;
+
+use crate::rest_api::{
+    ArchiveImportSerDe, ErrorSerDe, LightPushRequestSerDe, NodeInfoSerDe, PeerInfoSerDe,
+    PeerScoreSerDe, PubSubTopicsSerDe, RelayStreamParamsSerDe, StoreParamsSerDe,
+    StoreResponseV1SerDe, StoreResponseV3SerDe, WakuMessageSerDe,
+};
+use waku_protocol::waku_relay::network_behaviour::DEFAULT_PUBSUB_TOPIC;
+
+pub fn document() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let paths = paths(&mut generator);
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "waku-node REST API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Field names are camelCase, as in the nwaku REST API. Payloads, \
+                metas and digests are base64. Times are Unix epoch times in nanoseconds. \
+                Errors have an Error body, as do 413 replies to request bodies larger than \
+                a message of the maximum size.",
+        },
+        // Requests carry a bearer token only if the node is started with --rest-auth-token
+        "security": [{}, { "bearerAuth": [] }],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer" },
+            },
+            "schemas": schemas(generator),
+        },
+    })
+}
+
+// Whether the document has an operation, e.g. GET /admin/v1/peers/{peerId}
+pub fn has_operation(method: &Method, path: &str) -> bool {
+    document()["paths"][path]
+        .get(method.as_str().to_lowercase())
+        .is_some()
+}
+
+// Base64 strings, for #[schemars(schema_with)]
+pub fn base64(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(json!({ "type": "string", "format": "byte" })).expect("Valid schema")
+}
+
+pub fn default_pubsub_topic() -> String {
+    DEFAULT_PUBSUB_TOPIC.to_string()
+}
+
+pub fn default_ascending() -> Option<bool> {
+    Some(true)
+}
+
+pub fn waku_message_example() -> Value {
+    json!({
+        "payload": "aGVsbG8=",
+        "contentTopic": "/toy/1/chat/proto",
+        "timestamp": 1_700_000_000_000_000_000_i64,
+    })
+}
+
+pub fn pubsub_topics_example() -> Value {
+    json!({ "topics": [DEFAULT_PUBSUB_TOPIC] })
+}
+
+pub fn lightpush_request_example() -> Value {
+    json!({
+        "pubsubTopic": DEFAULT_PUBSUB_TOPIC,
+        "message": { "payload": "aGVsbG8=", "contentTopic": "/toy/1/chat/proto" },
+    })
+}
+
+// A reference to the schema of T, which is added to the components
+fn schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    json!(generator.subschema_for::<T>())
+}
+
+// The schemas referenced by the paths, made OpenAPI 3.0 compliant by the visitors of the
+// generator, as root schemas are
+fn schemas(mut generator: SchemaGenerator) -> Value {
+    let mut definitions = generator.take_definitions();
+    for visitor in generator.visitors_mut() {
+        definitions
+            .values_mut()
+            .for_each(|s| visitor.visit_schema(s));
+    }
+    json!(definitions)
+}
+
+// The query parameters of a route, from the fields of T. None are required
+fn query_parameters<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    let root = json!(generator.root_schema_for::<T>());
+    let parameters: Vec<Value> = root["properties"]
+        .as_object()
+        .expect("Parameters are fields")
+        .iter()
+        .map(|(name, schema)| {
+            // Absent parameters are None, not null
+            let mut schema = schema.clone();
+            let schema_fields = schema.as_object_mut().expect("Schemas are objects");
+            schema_fields.remove("nullable");
+            let description = schema_fields.remove("description").unwrap_or_default();
+            json!({ "name": name, "in": "query", "description": description, "schema": schema })
+        })
+        .collect();
+    json!(parameters)
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+fn with_description(mut object: Value, description: &str) -> Value {
+    object["description"] = json!(description);
+    object
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+fn empty_response(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn error_response(generator: &mut SchemaGenerator, description: &str) -> Value {
+    json_response(description, schema::<ErrorSerDe>(generator))
+}
+
+fn operation(summary: &str, responses: Value) -> Map<String, Value> {
+    let mut operation = Map::new();
+    operation.insert("summary".to_string(), json!(summary));
+    operation.insert("responses".to_string(), responses);
+    operation
+}
+
+fn with(mut operation: Map<String, Value>, key: &str, value: Value) -> Map<String, Value> {
+    operation.insert(key.to_string(), value);
+    operation
+}
+
+fn pubsub_topic_param() -> Value {
+    json!({
+        "name": "pubsubTopic",
+        "in": "path",
+        "required": true,
+        "description": "Pubsub topic, percent-encoded",
+        "schema": { "type": "string" },
+        "example": "%2Fwaku%2F2%2Fdefault-waku%2Fproto",
+    })
+}
+
+fn paths(generator: &mut SchemaGenerator) -> Value {
+    let g = generator;
+    let store_params = query_parameters::<StoreParamsSerDe>(g);
+    // Message hashes are computed with the pubsub topic of the query
+    let mut store_v3_params = store_params.clone();
+    for param in store_v3_params
+        .as_array_mut()
+        .expect("Parameters are an array")
+    {
+        if param["name"] == "pubsubTopic" {
+            param["required"] = json!(true);
+        }
+    }
+
+    let mut paths = Map::new();
+    let mut add = |path: &str, method: &str, operation: Map<String, Value>| {
+        paths
+            .entry(path.to_string())
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .expect("Paths are objects")
+            .insert(method.to_string(), Value::Object(operation));
+    };
+
+    add(
+        "/relay/v1/messages/{pubsubTopic}",
+        "get",
+        with(
+            operation(
+                "Messages relayed on a subscribed topic since the last call",
+                json!({
+                    "200": json_response("Cached messages, oldest first", schema::<Vec<WakuMessageSerDe>>(g)),
+                    "404": error_response(g, "Not subscribed to the topic"),
+                }),
+            ),
+            "parameters",
+            json!([pubsub_topic_param()]),
+        ),
+    );
+    add(
+        "/relay/v1/messages/{pubsubTopic}",
+        "post",
+        with(
+            with(
+                operation(
+                    "Publish a message on a topic",
+                    json!({
+                        "200": empty_response("Published"),
+                        "400": error_response(g, "Malformed message"),
+                    }),
+                ),
+                "parameters",
+                json!([pubsub_topic_param()]),
+            ),
+            "requestBody",
+            json_body(schema::<WakuMessageSerDe>(g)),
+        ),
+    );
+    add(
+        "/relay/v1/stream",
+        "get",
+        with(
+            operation(
+                "Server-sent events of relayed messages",
+                json!({
+                    "200": {
+                        "description": "\"message\" events carry a StoredMessage. \"lagged\" \
+                            events carry the number of messages skipped by a slow client",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                    },
+                    "503": error_response(g, "Too many stream subscribers"),
+                }),
+            ),
+            "parameters",
+            query_parameters::<RelayStreamParamsSerDe>(g),
+        ),
+    );
+    for (method, summary) in [
+        ("post", "Subscribe to pubsub topics"),
+        ("delete", "Unsubscribe from pubsub topics"),
+    ] {
+        add(
+            "/relay/v1/subscriptions",
+            method,
+            with(
+                operation(
+                    summary,
+                    json!({
+                        "200": empty_response("Done"),
+                        "400": error_response(g, "Malformed topics"),
+                    }),
+                ),
+                "requestBody",
+                json_body(schema::<PubSubTopicsSerDe>(g)),
+            ),
+        );
+    }
+    add(
+        "/relay/v1/subscriptions",
+        "get",
+        operation(
+            "Subscribed pubsub topics",
+            json!({ "200": json_response("Topics", schema::<Vec<String>>(g)) }),
+        ),
+    );
+    add(
+        "/debug/v1/info",
+        "get",
+        operation(
+            "Peer ID, listen addresses and version of the node",
+            json!({ "200": json_response("Node info", schema::<NodeInfoSerDe>(g)) }),
+        ),
+    );
+    add(
+        "/admin/v1/peers",
+        "get",
+        operation(
+            "Connected peers",
+            json!({ "200": json_response("Peers", schema::<Vec<PeerInfoSerDe>>(g)) }),
+        ),
+    );
+    add(
+        "/admin/v1/peers",
+        "post",
+        with(
+            operation(
+                "Dial peers",
+                json!({
+                    "200": empty_response("Dials started"),
+                    "400": error_response(g, "Malformed multiaddr, or the dial failed to start"),
+                }),
+            ),
+            "requestBody",
+            with_description(
+                json_body(schema::<Vec<String>>(g)),
+                "Multiaddrs of the peers",
+            ),
+        ),
+    );
+    add(
+        "/admin/v1/peers/{peerId}",
+        "delete",
+        with(
+            operation(
+                "Disconnect a peer",
+                json!({
+                    "200": empty_response("Disconnected"),
+                    "400": error_response(g, "Malformed peer ID"),
+                    "404": error_response(g, "Peer is not connected"),
+                }),
+            ),
+            "parameters",
+            json!([{
+                "name": "peerId",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            }]),
+        ),
+    );
+    add(
+        "/admin/v1/peers/scores",
+        "get",
+        operation(
+            "Relay peer scores",
+            json!({ "200": json_response("Scores", schema::<Vec<PeerScoreSerDe>>(g)) }),
+        ),
+    );
+    add(
+        "/lightpush/v1/message",
+        "post",
+        with(
+            operation(
+                "Push a message through a lightpush service peer",
+                json!({
+                    "200": empty_response("Pushed by the service peer"),
+                    "400": error_response(g, "Malformed request"),
+                    "429": error_response(g, "Rate limited by the service peer"),
+                    "502": error_response(g, "The service peer failed to push, or did not answer"),
+                    "503": error_response(g, "Lightpush is not enabled, or there is no service peer"),
+                }),
+            ),
+            "requestBody",
+            json_body(schema::<LightPushRequestSerDe>(g)),
+        ),
+    );
+    let store_responses = [
+        (
+            "v1",
+            "Query stored messages",
+            schema::<StoreResponseV1SerDe>(g),
+            store_params,
+        ),
+        (
+            "v3",
+            "Query stored messages of a pubsub topic, with their hashes",
+            schema::<StoreResponseV3SerDe>(g),
+            store_v3_params,
+        ),
+    ];
+    for (version, summary, response, params) in store_responses {
+        add(
+            &format!("/store/{}/messages", version),
+            "get",
+            with(
+                operation(
+                    summary,
+                    json!({
+                        "200": json_response("A page of messages", response),
+                        "400": error_response(g, "Malformed or incomplete query, or unknown cursor"),
+                        "429": error_response(g, "Rate limited by the store peer"),
+                        "502": error_response(g, "The store peer could not be reached"),
+                        "503": error_response(g, "Store is not enabled"),
+                    }),
+                ),
+                "parameters",
+                params,
+            ),
+        );
+    }
+    add(
+        "/store/v1/archive",
+        "get",
+        operation(
+            "Export the message archive",
+            json!({
+                "200": {
+                    "description": "One JSON object per line, see waku-node store export",
+                    "content": { "application/x-ndjson": { "schema": { "type": "string" } } },
+                },
+                "503": error_response(g, "Store is not enabled"),
+            }),
+        ),
+    );
+    add(
+        "/store/v1/archive",
+        "post",
+        with(
+            operation(
+                "Import an exported archive, skipping messages already archived. Archives are not \
+                    trusted: messages are validated like relayed ones, except for their age",
+                json!({
+                    "200": json_response("Counts of the messages", schema::<ArchiveImportSerDe>(g)),
+                    "400": error_response(g, "Malformed archive"),
+                    "503": error_response(g, "Store is not enabled"),
+                }),
+            ),
+            "requestBody",
+            json!({
+                "required": true,
+                "content": { "application/x-ndjson": { "schema": { "type": "string" } } },
+            }),
+        ),
+    );
+    add(
+        "/metrics",
+        "get",
+        operation(
+            "Prometheus metrics",
+            json!({
+                "200": {
+                    "description": "Prometheus text exposition",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            }),
+        ),
+    );
+    add(
+        "/openapi.json",
+        "get",
+        operation(
+            "This document",
+            json!({ "200": json_response("OpenAPI 3 document", json!({ "type": "object" })) }),
+        ),
+    );
+    Value::Object(paths)
+}
@@ -0,0 +1,97 @@
+// Relay messages kept for GET /relay/v1/messages/{topic}, for the topics subscribed via the
+// CLI or the REST API only. Each topic keeps its newest messages, up to the capacity
+use prometheus_client::{metrics::counter::Counter, registry::Registry};
+use std::collections::{HashMap, VecDeque};
+use waku_protocol::waku_message::WakuMessage;
+
+pub const DEFAULT_RELAY_CACHE_CAPACITY: usize = 30;
+
+pub struct RelayCache {
+    capacity: usize,
+    topics: HashMap<String, VecDeque<WakuMessage>>,
+    // Messages evicted to make room before anyone polled them
+    dropped: Counter,
+}
+
+impl RelayCache {
+    pub fn new(capacity: usize) -> Self {
+        RelayCache {
+            capacity,
+            topics: HashMap::new(),
+            dropped: Counter::default(),
+        }
+    }
+
+    pub fn register_metrics(&self, registry: &mut Registry) {
+        registry.register(
+            "waku_rest_relay_cache_dropped",
+            "Relay messages dropped by the REST cache, evicted before being polled or not received as its channel was full",
+            self.dropped.clone(),
+        );
+    }
+
+    // Also counts the messages that never reached the cache, see cache_relay_message
+    pub fn dropped_counter(&self) -> Counter {
+        self.dropped.clone()
+    }
+
+    pub fn subscribe(&mut self, topic: &str) {
+        self.topics.entry(topic.to_string()).or_default();
+    }
+
+    pub fn unsubscribe(&mut self, topic: &str) {
+        self.topics.remove(topic);
+    }
+
+    // Messages of topics that are not subscribed are ignored
+    pub fn push(&mut self, topic: &str, message: WakuMessage) {
+        if let Some(messages) = self.topics.get_mut(topic) {
+            if messages.len() == self.capacity {
+                messages.pop_front();
+                self.dropped.inc();
+            }
+            if self.capacity > 0 {
+                messages.push_back(message);
+            }
+        }
+    }
+
+    // Returns the cached messages of a topic, oldest first, and clears them. None if the
+    // topic is not subscribed
+    pub fn take(&mut self, topic: &str) -> Option<Vec<WakuMessage>> {
+        self.topics
+            .get_mut(topic)
+            .map(|messages| messages.drain(..).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::relay_cache::RelayCache;
+    use waku_protocol::waku_message::WakuMessage;
+
+    fn message(i: u8) -> WakuMessage {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(vec![i]);
+        msg
+    }
+
+    #[test]
+    fn test_relay_cache() {
+        let mut cache = RelayCache::new(2);
+        cache.push("/a", message(0));
+        assert_eq!(None, cache.take("/a"));
+
+        cache.subscribe("/a");
+        for i in 1..=3 {
+            cache.push("/a", message(i));
+        }
+        assert_eq!(Some(vec![message(2), message(3)]), cache.take("/a"));
+        assert_eq!(1, cache.dropped.get());
+        assert_eq!(Some(vec![]), cache.take("/a"));
+
+        cache.push("/a", message(4));
+        cache.unsubscribe("/a");
+        assert_eq!(None, cache.take("/a"));
+    }
+}
@@ -1,136 +1,2156 @@
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, str, sync::Arc};
-use tokio::sync::{
-    mpsc::{Receiver, Sender},
-    Mutex,
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::{stream, Stream};
+use futures_rustls::{server::TlsStream, TlsAcceptor};
+use libp2p::{Multiaddr, PeerId};
+use log::info;
+use percent_encoding::percent_decode_str;
+use prometheus_client::{encoding::text::encode, registry::Registry};
+use protobuf::RepeatedField;
+use rustls::ServerConfig;
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    error::Error,
+    io,
+    path::Path,
+    pin::Pin,
+    str::{self, FromStr},
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
 };
-use waku_protocol::waku_message::WakuMessage;
-use warp::{http::StatusCode, path::Tail, reply, Filter, Rejection, Reply};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{self, Receiver, Sender},
+        oneshot, Mutex, OwnedSemaphorePermit, Semaphore,
+    },
+    time::timeout,
+};
+use tokio_util::{
+    compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt},
+    either::Either,
+};
+use waku_protocol::{
+    waku_lightpush::PushResponse,
+    waku_message::{WakuMessage, MAX_MESSAGE_SIZE},
+    waku_relay::network_behaviour::DEFAULT_PUBSUB_TOPIC,
+    waku_store::{
+        message_queue::IndexedWakuMessage,
+        network_behaviour::{compute_digest, ArchiveImportCounts},
+        ContentFilter, HistoryQuery, HistoryResponse, HistoryResponse_Error, Index,
+        PagingInfo_Direction,
+    },
+};
+use warp::{
+    filters::BoxedFilter,
+    http::{Method, StatusCode, Uri},
+    hyper::body::Bytes,
+    path::Tail,
+    reject, reply,
+    sse::{self, Event},
+    Filter, Rejection, Reply,
+};
+
+use crate::{archive, openapi, peer_id, relay_cache::RelayCache};
 
 type Result<T> = std::result::Result<T, Rejection>;
-type RelayCache = Arc<Mutex<HashMap<String, Vec<WakuMessageSerDe>>>>;
+type SharedRelayCache = Arc<Mutex<RelayCache>>;
+
+// Connections to the REST or JSON-RPC API open at once. Further clients wait in the listen
+// backlog until one closes
+const MAX_CONNECTIONS: usize = 256;
+// Clients that have not completed their TLS handshake by then are disconnected
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A connection to the REST or JSON-RPC API, over TLS or not, holding one of the
+// MAX_CONNECTIONS until it is dropped
+pub struct RestConnection {
+    stream: Either<TcpStream, Box<Compat<TlsStream<Compat<TcpStream>>>>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for RestConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RestConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+// Relay messages as they arrive, with their pubsub topic, for /relay/v1/stream
+type RelayStream = broadcast::Sender<(String, WakuMessage)>;
+
+// Stream subscribers at once, fewer than MAX_CONNECTIONS since each holds its connection for
+// as long as it listens. Further subscribers get a 503 rather than locking out the other routes
+const MAX_RELAY_STREAMS: usize = 64;
 
+// Messages buffered for each stream subscriber. Subscribers that fall further behind skip
+// the oldest ones and are told how many with a "lagged" event, so that a slow subscriber
+// never holds back relay or the other subscribers
+const RELAY_STREAM_CAPACITY: usize = 1024;
+
+// Payload and meta are base64, as in the nwaku REST API. The schemas of the OpenAPI document
+// are derived from the types of the bodies
 #[allow(non_snake_case)]
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct WakuMessageSerDe {
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[schemars(rename = "WakuMessage", example = "openapi::waku_message_example")]
+pub struct WakuMessageSerDe {
+    #[schemars(schema_with = "openapi::base64")]
     payload: String,
     contentTopic: String,
+    #[serde(default)]
     version: u32,
+    #[serde(default)]
     timestamp: i64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    #[schemars(schema_with = "openapi::base64")]
+    meta: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    ephemeral: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl From<&WakuMessage> for WakuMessageSerDe {
+    fn from(waku_message: &WakuMessage) -> Self {
+        WakuMessageSerDe {
+            payload: BASE64.encode(waku_message.get_payload()),
+            contentTopic: waku_message.get_content_topic().to_string(),
+            version: waku_message.get_version(),
+            timestamp: waku_message.get_timestamp(),
+            meta: BASE64.encode(waku_message.get_meta()),
+            ephemeral: waku_message.get_ephemeral(),
+        }
+    }
+}
+
+impl TryFrom<WakuMessageSerDe> for WakuMessage {
+    type Error = String;
+
+    fn try_from(msg: WakuMessageSerDe) -> std::result::Result<Self, Self::Error> {
+        if msg.contentTopic.is_empty() {
+            return Err("contentTopic is empty".to_string());
+        }
+        let mut waku_message = WakuMessage::new();
+        waku_message.set_payload(
+            BASE64
+                .decode(msg.payload)
+                .map_err(|e| format!("payload is not base64: {}", e))?,
+        );
+        waku_message.set_meta(
+            BASE64
+                .decode(msg.meta)
+                .map_err(|e| format!("meta is not base64: {}", e))?,
+        );
+        waku_message.set_content_topic(msg.contentTopic);
+        waku_message.set_timestamp(msg.timestamp);
+        waku_message.set_version(msg.version);
+        waku_message.set_ephemeral(msg.ephemeral);
+        Ok(waku_message)
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct PubSubTopicsSerDe {
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[schemars(rename = "PubSubTopics", example = "openapi::pubsub_topics_example")]
+pub struct PubSubTopicsSerDe {
     topics: Vec<String>,
 }
 
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[schemars(rename = "PeerScore")]
+pub struct PeerScoreSerDe {
+    peerId: String,
+    score: f64,
+}
+
+type PeerScoresRequest = oneshot::Sender<Vec<(PeerId, f64)>>;
+
+#[allow(non_snake_case)]
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "NodeInfo")]
+pub struct NodeInfoSerDe {
+    peerId: String,
+    listenAddresses: Vec<String>,
+    // There is no ENR until the node publishes EIP-778 records for discovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enrUri: Option<String>,
+    version: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "PeerInfo")]
+pub struct PeerInfoSerDe {
+    peerId: String,
+    multiaddrs: Vec<String>,
+    protocols: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agentVersion: Option<String>,
+}
+
+// A connected peer, with what identify reported about it
+#[derive(Clone, Debug, Default)]
+pub struct PeerInfo {
+    pub addresses: Vec<Multiaddr>,
+    pub protocols: Vec<String>,
+    pub agent_version: Option<String>,
+}
+
+// Requests of the admin and debug routes, answered by the swarm loop
+pub enum AdminRequest {
+    // The peer ID and listen addresses
+    Info(oneshot::Sender<(PeerId, Vec<Multiaddr>)>),
+    Peers(oneshot::Sender<Vec<(PeerId, PeerInfo)>>),
+    Dial(
+        Vec<Multiaddr>,
+        oneshot::Sender<std::result::Result<(), String>>,
+    ),
+    // Replies false if the peer is not connected
+    Disconnect(PeerId, oneshot::Sender<bool>),
+    RelaySubscriptions(oneshot::Sender<Vec<String>>),
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "StoreCursor")]
+pub struct StoreCursorSerDe {
+    pubsubTopic: String,
+    senderTime: i64,
+    storeTime: i64,
+    #[schemars(schema_with = "openapi::base64")]
+    digest: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "StoreResponseV1")]
+pub struct StoreResponseV1SerDe {
+    messages: Vec<WakuMessageSerDe>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<StoreCursorSerDe>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "StoredMessage")]
+pub struct StoredMessageSerDe {
+    #[schemars(schema_with = "openapi::base64")]
+    messageHash: String,
+    message: WakuMessageSerDe,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pubsubTopic: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "StoreResponseV3")]
+pub struct StoreResponseV3SerDe {
+    requestId: String,
+    statusCode: u16,
+    statusDesc: String,
+    messages: Vec<StoredMessageSerDe>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paginationCursor: Option<String>,
+}
+
+// A history query from the REST or JSON-RPC API. Remote store peers are dialed if needed.
+// Without a peer, the query goes to the --storenode peers, or to the local archive if there
+// are none. Replies are None if store is not enabled
+pub struct StoreQueryRequest {
+    pub request_id: String,
+    pub query: HistoryQuery,
+    pub peer: Option<(PeerId, Multiaddr)>,
+    pub reply_tx: StoreQueryReply,
+}
+
+pub type StoreQueryReply = oneshot::Sender<Option<std::result::Result<HistoryResponse, String>>>;
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[schemars(
+    rename = "LightPushRequest",
+    example = "openapi::lightpush_request_example"
+)]
+pub struct LightPushRequestSerDe {
+    #[serde(default)]
+    #[schemars(default = "openapi::default_pubsub_topic")]
+    pubsubTopic: String,
+    message: WakuMessageSerDe,
+    // Service peer to push through, instead of the configured one
+    #[schemars(
+        description = "Multiaddr, including /p2p/<peer id>, of the service peer. The first --lightpushnode if not set"
+    )]
+    peerAddr: Option<String>,
+}
+
+// A message to push through a lightpush service peer, the first configured one if there
+// is no peer
+pub struct LightPushRequest {
+    pub request_id: String,
+    pub pubsub_topic: String,
+    pub message: WakuMessage,
+    pub peer: Option<(PeerId, Multiaddr)>,
+    pub reply_tx: LightPushReply,
+}
+
+pub type LightPushReply = oneshot::Sender<std::result::Result<PushResponse, LightPushError>>;
+
+pub enum LightPushError {
+    NotEnabled,
+    NoPeer,
+    // The request did not reach the service peer, or got no response
+    Failed(String),
+}
+
+// Store archives are exchanged whole, see archive.rs for their format. Replies are None if
+// store is not enabled
+pub enum StoreArchiveRequest {
+    Export(oneshot::Sender<Option<Vec<IndexedWakuMessage>>>),
+    Import(
+        Vec<IndexedWakuMessage>,
+        oneshot::Sender<Option<ArchiveImportCounts>>,
+    ),
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "ArchiveImport")]
+pub struct ArchiveImportSerDe {
+    imported: usize,
+    duplicates: usize,
+    // Failed validation, as if they had been relayed, or are ephemeral
+    #[schemars(
+        description = "Messages that fail the validation of relayed messages, except for their age, or are ephemeral"
+    )]
+    rejected: usize,
+}
+
+impl From<ArchiveImportCounts> for ArchiveImportSerDe {
+    fn from(counts: ArchiveImportCounts) -> Self {
+        ArchiveImportSerDe {
+            imported: counts.imported,
+            duplicates: counts.duplicates,
+            rejected: counts.rejected,
+        }
+    }
+}
+
+// Body of error replies
+#[derive(Serialize, JsonSchema, Debug)]
+#[schemars(rename = "Error")]
+pub struct ErrorSerDe {
+    error: String,
+}
+
+// Query parameters of /relay/v1/stream
+#[allow(non_snake_case)]
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct RelayStreamParamsSerDe {
+    #[schemars(description = "Only messages of this pubsub topic")]
+    pubsubTopic: Option<String>,
+    #[schemars(description = "Only messages of these comma separated content topics")]
+    contentTopics: Option<String>,
+}
+
+// Query parameters of the store endpoints. They are read as strings and parsed by
+// store_query_from_params, so that malformed ones get an error naming them
+#[allow(non_snake_case)]
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct StoreParamsSerDe {
+    #[schemars(description = "Pubsub topic of the messages")]
+    pubsubTopic: Option<String>,
+    #[schemars(description = "Comma separated content topics of the messages")]
+    contentTopics: Option<String>,
+    #[schemars(
+        with = "Option<i64>",
+        description = "Earliest timestamp of the messages"
+    )]
+    startTime: Option<String>,
+    #[schemars(with = "Option<i64>", description = "Latest timestamp of the messages")]
+    endTime: Option<String>,
+    #[schemars(with = "Option<u64>", description = "Maximum number of messages")]
+    pageSize: Option<String>,
+    #[schemars(
+        with = "Option<bool>",
+        default = "openapi::default_ascending",
+        description = "Page direction"
+    )]
+    ascending: Option<String>,
+    #[schemars(
+        schema_with = "openapi::base64",
+        description = "Base64 digest of the last message of the previous page"
+    )]
+    cursor: Option<String>,
+    #[schemars(
+        description = "Multiaddr, including /p2p/<peer id>, of the store peer to query. If not set, the --storenode peers are queried and their merged results returned in one page, or the local archive if there are none"
+    )]
+    peerAddr: Option<String>,
+}
+
+// Largest archive accepted by POST /store/v1/archive
+const MAX_ARCHIVE_SIZE: u64 = 256 * 1024 * 1024;
+// Largest body accepted by the other POST and DELETE routes: a message of MAX_MESSAGE_SIZE,
+// grown by 4/3 with base64, and its JSON envelope
+const MAX_BODY_SIZE: u64 = (MAX_MESSAGE_SIZE * 4 / 3 + 64 * 1024) as u64;
+
+// How the REST API is served. The listener is bound by the caller, so that a busy port
+// fails the node at startup
+pub struct RestConfig {
+    pub listener: TcpListener,
+    // Origins allowed to make cross-origin requests. CORS is not enabled if there are none
+    pub allow_origins: Vec<AllowedOrigin>,
+    // Serves HTTPS instead of HTTP
+    pub tls: Option<TlsAcceptor>,
+    // Bearer token required in the Authorization header of every request
+    pub auth_token: Option<String>,
+}
+
+// An origin as in the Origin header, <scheme>://<host>[:<port>], or * for any
+#[derive(Clone, Debug)]
+pub struct AllowedOrigin(String);
+
+impl FromStr for AllowedOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(AllowedOrigin(s.to_string()));
+        }
+        let uri: Uri = s.parse().map_err(|e| format!("{}: {}", s, e))?;
+        match (uri.scheme_str(), uri.authority(), uri.path()) {
+            (Some(scheme @ ("http" | "https")), Some(authority), "" | "/") => {
+                Ok(AllowedOrigin(format!("{}://{}", scheme, authority)))
+            }
+            _ => Err(format!("{} is not an origin like http://localhost:3000", s)),
+        }
+    }
+}
+
+// Loads the PEM encoded certificate chain and private key of the REST or JSON-RPC API
+pub fn tls_acceptor(cert: &Path, key: &Path) -> std::result::Result<TlsAcceptor, Box<dyn Error>> {
+    let certs = CertificateDer::pem_file_iter(cert)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key)?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl reject::Reject for Unauthorized {}
+
+// Rejects requests without the bearer token, if there is one
+pub fn authorize(
+    auth_token: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let expected = auth_token.map(|t| format!("Bearer {}", t));
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let authorized = match (&expected, header) {
+                (None, _) => true,
+                (Some(expected), Some(header)) => constant_time_eq(expected, &header),
+                (Some(_), None) => false,
+            };
+            async move {
+                match authorized {
+                    true => Ok(()),
+                    false => Err(reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Compares tokens in a time that does not depend on where they differ
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+// Replies with a JSON error, like the routes do, for the rejections of authorize and of the
+// body size limits
+pub async fn recover_rejection(
+    rejection: Rejection,
+) -> std::result::Result<reply::Response, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        return Ok(reply::with_header(
+            error_reply("missing or wrong bearer token", StatusCode::UNAUTHORIZED),
+            "www-authenticate",
+            "Bearer",
+        )
+        .into_response());
+    }
+    if rejection.find::<reject::PayloadTooLarge>().is_some() {
+        return Ok(error_reply(
+            "request body is too large",
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+    }
+    Err(rejection)
+}
+
+// Accepts up to MAX_CONNECTIONS connections, running TLS handshakes concurrently so that a
+// slow client does not hold back the others. Failed connections are logged rather than
+// reported, as an error would stop the server
+pub fn incoming(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+) -> impl Stream<Item = std::result::Result<RestConnection, Infallible>> {
+    let (connection_tx, connection_rx) = mpsc::channel(32);
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    tokio::spawn(async move {
+        loop {
+            let permit = connections
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("Semaphore is never closed");
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    info!("REST API: failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let connection_tx = connection_tx.clone();
+            match &tls {
+                Some(acceptor) => {
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let handshake = acceptor.accept(stream.compat());
+                        match timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                            Ok(Ok(stream)) => {
+                                let connection = RestConnection {
+                                    stream: Either::Right(Box::new(stream.compat())),
+                                    _permit: permit,
+                                };
+                                let _ = connection_tx.send(connection).await;
+                            }
+                            Ok(Err(e)) => info!("REST API: TLS handshake failed: {}", e),
+                            Err(_) => info!("REST API: TLS handshake timed out"),
+                        }
+                    });
+                }
+                None => {
+                    let connection = RestConnection {
+                        stream: Either::Left(stream),
+                        _permit: permit,
+                    };
+                    let _ = connection_tx.send(connection).await;
+                }
+            }
+        }
+    });
+
+    stream::unfold(connection_rx, |mut connection_rx| async move {
+        connection_rx.recv().await.map(|c| (Ok(c), connection_rx))
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn serve(
+    config: RestConfig,
+    relay_cache: RelayCache,
     mut relay_cache_rx: Receiver<(WakuMessage, String)>,
     relay_publish_tx: Sender<(WakuMessage, String)>,
     relay_subscribe_tx: Sender<Vec<String>>,
     relay_unsubscribe_tx: Sender<Vec<String>>,
+    peer_scores_tx: Sender<PeerScoresRequest>,
+    admin_tx: Sender<AdminRequest>,
+    lightpush_tx: Sender<LightPushRequest>,
+    store_query_tx: Sender<StoreQueryRequest>,
+    store_archive_tx: Sender<StoreArchiveRequest>,
+    metrics_registry: Arc<Registry>,
 ) {
-    let relay_cache: RelayCache = Arc::new(Mutex::new(HashMap::new()));
+    let relay_cache: SharedRelayCache = Arc::new(Mutex::new(relay_cache));
+    let (relay_stream, _) = broadcast::channel(RELAY_STREAM_CAPACITY);
+    let routes = routes(
+        relay_cache.clone(),
+        relay_stream.clone(),
+        relay_publish_tx,
+        relay_subscribe_tx,
+        relay_unsubscribe_tx,
+        peer_scores_tx,
+        admin_tx,
+        lightpush_tx,
+        store_query_tx,
+        store_archive_tx,
+        metrics_registry,
+    );
+
+    let routes = authorize(config.auth_token)
+        .and(routes)
+        .map(Reply::into_response)
+        .recover(recover_rejection)
+        .map(Reply::into_response)
+        .boxed();
+    let routes = match config.allow_origins.is_empty() {
+        true => routes,
+        false => {
+            let cors = warp::cors()
+                .allow_methods(vec!["GET", "POST", "DELETE"])
+                .allow_headers(vec!["authorization", "content-type"]);
+            let cors = match config.allow_origins.iter().any(|o| o.0 == "*") {
+                true => cors.allow_any_origin(),
+                false => cors.allow_origins(config.allow_origins.iter().map(|o| o.0.as_str())),
+            };
+            routes.with(cors).map(Reply::into_response).boxed()
+        }
+    };
+    tokio::spawn(warp::serve(routes).run_incoming(incoming(config.listener, config.tls)));
+
+    while let Some((waku_message, topic)) = relay_cache_rx.recv().await {
+        // Fails only when there are no subscribers
+        let _ = relay_stream.send((topic.clone(), waku_message.clone()));
+        relay_cache.lock().await.push(&topic, waku_message);
+    }
+}
+
+// Matches a method and a path of the OpenAPI document, given as its template, e.g.
+// /admin/v1/peers/{peerId}. Segments from the first parameter on are left to the route
+fn endpoint(method: Method, path: &'static str) -> BoxedFilter<()> {
+    debug_assert!(
+        openapi::has_operation(&method, path),
+        "{} {} is not in the OpenAPI document",
+        method,
+        path
+    );
+    let filter = match method {
+        Method::GET => warp::get().boxed(),
+        Method::POST => warp::post().boxed(),
+        Method::DELETE => warp::delete().boxed(),
+        _ => panic!("{} is not used by the REST API", method),
+    };
+    let filter = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .take_while(|s| !s.starts_with('{'))
+        .fold(filter, |filter, segment| {
+            filter.and(warp::path(segment)).boxed()
+        });
+    match path.contains('{') {
+        true => filter,
+        false => filter.and(warp::path::end()).boxed(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn routes(
+    relay_cache: SharedRelayCache,
+    relay_stream: RelayStream,
+    relay_publish_tx: Sender<(WakuMessage, String)>,
+    relay_subscribe_tx: Sender<Vec<String>>,
+    relay_unsubscribe_tx: Sender<Vec<String>>,
+    peer_scores_tx: Sender<PeerScoresRequest>,
+    admin_tx: Sender<AdminRequest>,
+    lightpush_tx: Sender<LightPushRequest>,
+    store_query_tx: Sender<StoreQueryRequest>,
+    store_archive_tx: Sender<StoreArchiveRequest>,
+    metrics_registry: Arc<Registry>,
+) -> BoxedFilter<(reply::Response,)> {
     let relay_cache_ref = relay_cache.clone();
 
-    let get_relay_v1_messages_topic_route = warp::get()
-        .and(warp::path("relay"))
-        .and(warp::path("v1"))
-        .and(warp::path("messages"))
-        .and(warp::path::tail().map(|tail: Tail| tail.as_str().to_string()))
-        .and(warp::any().map(move || relay_cache_ref.clone()))
-        .and_then(get_relay_v1_messages_topic);
-
-    let post_relay_v1_messages_topic_route = warp::post()
-        .and(warp::path("relay"))
-        .and(warp::path("v1"))
-        .and(warp::path("messages"))
-        .and(warp::path::tail().map(|tail: Tail| tail.as_str().to_string()))
-        .and(warp::body::content_length_limit(1024 * 16).and(warp::body::json()))
-        .and(warp::any().map(move || relay_publish_tx.clone()))
-        .and_then(post_relay_v1_messages_topic);
-
-    let post_relay_v1_subscriptions = warp::post()
-        .and(warp::path("relay"))
-        .and(warp::path("v1"))
-        .and(warp::path("subscriptions"))
-        .and(warp::body::content_length_limit(1024 * 16).and(warp::body::json()))
+    let get_relay_v1_messages_topic_route =
+        endpoint(Method::GET, "/relay/v1/messages/{pubsubTopic}")
+            .and(warp::path::tail().map(decode_topic))
+            .and(warp::any().map(move || relay_cache_ref.clone()))
+            .and_then(get_relay_v1_messages_topic);
+
+    let relay_streams = Arc::new(Semaphore::new(MAX_RELAY_STREAMS));
+    let get_relay_v1_stream = endpoint(Method::GET, "/relay/v1/stream")
+        .and(warp::query::<RelayStreamParamsSerDe>())
+        .and(warp::any().map(move || relay_stream.clone()))
+        .and(warp::any().map(move || relay_streams.clone()))
+        .map(get_relay_v1_stream);
+
+    let post_relay_v1_messages_topic_route =
+        endpoint(Method::POST, "/relay/v1/messages/{pubsubTopic}")
+            .and(warp::path::tail().map(decode_topic))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::bytes()))
+            .and(warp::any().map(move || relay_publish_tx.clone()))
+            .and_then(post_relay_v1_messages_topic);
+
+    let relay_cache_subscribe = relay_cache.clone();
+    let post_relay_v1_subscriptions = endpoint(Method::POST, "/relay/v1/subscriptions")
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::bytes()))
         .and(warp::any().map(move || relay_subscribe_tx.clone()))
+        .and(warp::any().map(move || relay_cache_subscribe.clone()))
         .and_then(post_relay_v1_subscriptions);
 
-    let delete_relay_v1_subscriptions = warp::delete()
-        .and(warp::path("relay"))
-        .and(warp::path("v1"))
-        .and(warp::path("subscriptions"))
-        .and(warp::body::content_length_limit(1024 * 16).and(warp::body::json()))
+    let relay_cache_unsubscribe = relay_cache.clone();
+    let delete_relay_v1_subscriptions = endpoint(Method::DELETE, "/relay/v1/subscriptions")
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::bytes()))
         .and(warp::any().map(move || relay_unsubscribe_tx.clone()))
+        .and(warp::any().map(move || relay_cache_unsubscribe.clone()))
         .and_then(delete_relay_v1_subscriptions);
 
+    let admin_tx_ref = admin_tx.clone();
+    let get_relay_v1_subscriptions = endpoint(Method::GET, "/relay/v1/subscriptions")
+        .and(warp::any().map(move || admin_tx_ref.clone()))
+        .and_then(get_relay_v1_subscriptions);
+
+    let admin_tx_ref = admin_tx.clone();
+    let get_debug_v1_info = endpoint(Method::GET, "/debug/v1/info")
+        .and(warp::any().map(move || admin_tx_ref.clone()))
+        .and_then(get_debug_v1_info);
+
+    let admin_tx_ref = admin_tx.clone();
+    let get_admin_v1_peers = endpoint(Method::GET, "/admin/v1/peers")
+        .and(warp::any().map(move || admin_tx_ref.clone()))
+        .and_then(get_admin_v1_peers);
+
+    let admin_tx_ref = admin_tx.clone();
+    let post_admin_v1_peers = endpoint(Method::POST, "/admin/v1/peers")
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::bytes()))
+        .and(warp::any().map(move || admin_tx_ref.clone()))
+        .and_then(post_admin_v1_peers);
+
+    let delete_admin_v1_peers = endpoint(Method::DELETE, "/admin/v1/peers/{peerId}")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::any().map(move || admin_tx.clone()))
+        .and_then(delete_admin_v1_peers);
+
+    let get_admin_v1_peers_scores = endpoint(Method::GET, "/admin/v1/peers/scores")
+        .and(warp::any().map(move || peer_scores_tx.clone()))
+        .and_then(get_admin_v1_peers_scores);
+
+    let post_lightpush_v1_message = endpoint(Method::POST, "/lightpush/v1/message")
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::bytes()))
+        .and(warp::any().map(move || lightpush_tx.clone()))
+        .and_then(post_lightpush_v1_message);
+
+    let store_query_tx_ref = store_query_tx.clone();
+    let get_store_v1_messages = endpoint(Method::GET, "/store/v1/messages")
+        .and(warp::query::<StoreParamsSerDe>())
+        .and(warp::any().map(move || store_query_tx_ref.clone()))
+        .and_then(get_store_v1_messages);
+
+    let get_store_v3_messages = endpoint(Method::GET, "/store/v3/messages")
+        .and(warp::query::<StoreParamsSerDe>())
+        .and(warp::any().map(move || store_query_tx.clone()))
+        .and_then(get_store_v3_messages);
+
+    let store_archive_tx_ref = store_archive_tx.clone();
+    let get_store_v1_archive = endpoint(Method::GET, "/store/v1/archive")
+        .and(warp::any().map(move || store_archive_tx_ref.clone()))
+        .and_then(get_store_v1_archive);
+
+    let post_store_v1_archive = endpoint(Method::POST, "/store/v1/archive")
+        .and(warp::body::content_length_limit(MAX_ARCHIVE_SIZE).and(warp::body::bytes()))
+        .and(warp::any().map(move || store_archive_tx.clone()))
+        .and_then(post_store_v1_archive);
+
+    let get_metrics = endpoint(Method::GET, "/metrics")
+        .and(warp::any().map(move || metrics_registry.clone()))
+        .and_then(get_metrics);
+
+    let openapi_document = openapi::document();
+    let get_openapi = endpoint(Method::GET, "/openapi.json")
+        .map(move || reply::json(&openapi_document).into_response());
+
     let routes = get_relay_v1_messages_topic_route
+        .or(get_relay_v1_stream)
         .or(post_relay_v1_messages_topic_route)
         .or(post_relay_v1_subscriptions)
-        .or(delete_relay_v1_subscriptions);
-    tokio::spawn(warp::serve(routes).run(([127, 0, 0, 1], 5000)));
+        .or(delete_relay_v1_subscriptions)
+        .or(get_relay_v1_subscriptions)
+        .or(get_debug_v1_info)
+        .or(get_admin_v1_peers)
+        .or(post_admin_v1_peers)
+        .or(delete_admin_v1_peers)
+        .or(get_admin_v1_peers_scores)
+        .or(post_lightpush_v1_message)
+        .or(get_store_v1_messages)
+        .or(get_store_v3_messages)
+        .or(get_store_v1_archive)
+        .or(post_store_v1_archive)
+        .or(get_metrics)
+        .or(get_openapi);
 
-    while let Some((waku_message, topic)) = relay_cache_rx.recv().await {
-        let mut unlock_relay_cache = relay_cache.lock().await;
-        unlock_relay_cache
-            .entry(topic)
-            .or_insert_with(Vec::new)
-            .push(WakuMessageSerDe {
-                payload: str::from_utf8(waku_message.get_payload())
-                    .unwrap()
-                    .to_string(),
-                contentTopic: waku_message.get_content_topic().to_string(),
-                version: waku_message.get_version(),
-                timestamp: waku_message.get_timestamp(),
-            });
+    routes.map(Reply::into_response).boxed()
+}
+
+// Returns the cached messages of a subscribed topic and clears them
+async fn get_relay_v1_messages_topic(
+    topic: String,
+    relay_cache: SharedRelayCache,
+) -> Result<reply::Response> {
+    match relay_cache.lock().await.take(&topic) {
+        Some(messages) => {
+            let messages: Vec<WakuMessageSerDe> =
+                messages.iter().map(WakuMessageSerDe::from).collect();
+            Ok(reply::json(&messages).into_response())
+        }
+        None => Ok(error_reply(
+            &format!("not subscribed to {}", topic),
+            StatusCode::NOT_FOUND,
+        )),
     }
 }
 
-async fn get_relay_v1_messages_topic(topic: String, relay_cache: RelayCache) -> Result<impl Reply> {
-    let mut local_relay_cache = relay_cache.lock().await;
-    let messages = match local_relay_cache.contains_key(&topic) {
-        true => local_relay_cache[&topic].clone(),
-        false => vec![],
+// Bodies are parsed here rather than with warp::body::json, so that malformed ones get a
+// 400 with an error body instead of a plain text rejection
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> std::result::Result<T, String> {
+    serde_json::from_slice(body).map_err(|e| e.to_string())
+}
+
+// Pubsub topics contain slashes, so they are percent-encoded in paths
+fn decode_topic(tail: Tail) -> String {
+    percent_decode_str(tail.as_str())
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+// Server-sent events of relay messages, optionally only those of a pubsub topic and of a
+// comma separated list of content topics. Every subscriber gets every matching message
+fn get_relay_v1_stream(
+    params: RelayStreamParamsSerDe,
+    relay_stream: RelayStream,
+    relay_streams: Arc<Semaphore>,
+) -> reply::Response {
+    let permit = match relay_streams.try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return error_reply(
+                "too many relay stream subscribers",
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+        }
+    };
+    let pubsub_topic = params.pubsubTopic;
+    let content_topics: HashSet<String> = params
+        .contentTopics
+        .map(|t| {
+            t.split(',')
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    let matches = move |topic: &String, message: &WakuMessage| {
+        pubsub_topic.as_ref().is_none_or(|t| t == topic)
+            && (content_topics.is_empty() || content_topics.contains(message.get_content_topic()))
     };
-    local_relay_cache.remove(&topic);
-    Ok(reply::with_status(reply::json(&messages), StatusCode::OK))
+
+    let events = relay_events(relay_stream.subscribe(), matches, permit);
+    sse::reply(sse::keep_alive().stream(events)).into_response()
+}
+
+// The permit is one of the MAX_RELAY_STREAMS, released once the client disconnects
+fn relay_events(
+    rx: broadcast::Receiver<(String, WakuMessage)>,
+    matches: impl Fn(&String, &WakuMessage) -> bool + Send + 'static,
+    permit: OwnedSemaphorePermit,
+) -> impl Stream<Item = std::result::Result<Event, Infallible>> + Send + 'static {
+    stream::unfold(
+        (rx, matches, permit),
+        |(mut rx, matches, permit)| async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok((topic, message)) if matches(&topic, &message) => {
+                        let message = StoredMessageSerDe {
+                            messageHash: BASE64.encode(compute_digest(&topic, &message)),
+                            message: WakuMessageSerDe::from(&message),
+                            pubsubTopic: topic,
+                        };
+                        Event::default()
+                            .event("message")
+                            .data(serde_json::to_string(&message).expect("Messages serialize"))
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        Event::default().event("lagged").data(skipped.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok(event), (rx, matches, permit)));
+            }
+        },
+    )
 }
 
 async fn post_relay_v1_messages_topic(
     topic: String,
-    msg: WakuMessageSerDe,
+    body: Bytes,
     relay_post_tx: Sender<(WakuMessage, String)>,
-) -> Result<impl Reply> {
-    let mut waku_message = WakuMessage::new();
-    waku_message.set_payload(msg.payload.as_bytes().to_vec());
-    waku_message.set_content_topic(msg.contentTopic);
-    waku_message.set_timestamp(msg.timestamp);
-    waku_message.set_version(msg.version);
+) -> Result<reply::Response> {
+    let waku_message = match parse_body::<WakuMessageSerDe>(&body).and_then(WakuMessage::try_from) {
+        Ok(waku_message) => waku_message,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
 
     match relay_post_tx.send((waku_message, topic)).await {
-        Ok(_) => Ok(reply::with_status("", StatusCode::OK)),
-        Err(_) => Ok(reply::with_status("", StatusCode::INTERNAL_SERVER_ERROR)),
+        Ok(_) => Ok(reply::with_status("", StatusCode::OK).into_response()),
+        Err(_) => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
     }
 }
 
 async fn post_relay_v1_subscriptions(
-    topics: PubSubTopicsSerDe,
+    body: Bytes,
     relay_subscribe_tx: Sender<Vec<String>>,
-) -> Result<impl Reply> {
+    relay_cache: SharedRelayCache,
+) -> Result<reply::Response> {
+    let topics = match parse_body::<PubSubTopicsSerDe>(&body) {
+        Ok(topics) => topics,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
+    let mut unlock_relay_cache = relay_cache.lock().await;
+    for t in &topics.topics {
+        unlock_relay_cache.subscribe(t);
+    }
+    drop(unlock_relay_cache);
+
     match relay_subscribe_tx.send(topics.topics).await {
-        Ok(_) => Ok(reply::with_status("", StatusCode::OK)),
-        Err(_) => Ok(reply::with_status("", StatusCode::INTERNAL_SERVER_ERROR)),
+        Ok(_) => Ok(reply::with_status("", StatusCode::OK).into_response()),
+        Err(_) => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
     }
 }
 
 async fn delete_relay_v1_subscriptions(
-    topics: PubSubTopicsSerDe,
+    body: Bytes,
     relay_subscribe_tx: Sender<Vec<String>>,
-) -> Result<impl Reply> {
+    relay_cache: SharedRelayCache,
+) -> Result<reply::Response> {
+    let topics = match parse_body::<PubSubTopicsSerDe>(&body) {
+        Ok(topics) => topics,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
+    let mut unlock_relay_cache = relay_cache.lock().await;
+    for t in &topics.topics {
+        unlock_relay_cache.unsubscribe(t);
+    }
+    drop(unlock_relay_cache);
+
     match relay_subscribe_tx.send(topics.topics).await {
-        Ok(_) => Ok(reply::with_status("", StatusCode::OK)),
-        Err(_) => Ok(reply::with_status("", StatusCode::INTERNAL_SERVER_ERROR)),
+        Ok(_) => Ok(reply::with_status("", StatusCode::OK).into_response()),
+        Err(_) => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+// Sends a request to the swarm loop and waits for its reply
+pub async fn admin_request<T>(
+    admin_tx: &Sender<AdminRequest>,
+    request: impl FnOnce(oneshot::Sender<T>) -> AdminRequest,
+) -> Option<T> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    admin_tx.send(request(reply_tx)).await.ok()?;
+    reply_rx.await.ok()
+}
+
+async fn get_relay_v1_subscriptions(admin_tx: Sender<AdminRequest>) -> Result<reply::Response> {
+    match admin_request(&admin_tx, AdminRequest::RelaySubscriptions).await {
+        Some(topics) => Ok(reply::json(&topics).into_response()),
+        None => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+async fn get_debug_v1_info(admin_tx: Sender<AdminRequest>) -> Result<reply::Response> {
+    let (peer_id, addresses) = match admin_request(&admin_tx, AdminRequest::Info).await {
+        Some(info) => info,
+        None => return Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    };
+    let info = NodeInfoSerDe {
+        peerId: peer_id.to_string(),
+        listenAddresses: addresses
+            .into_iter()
+            .map(|a| format!("{}/p2p/{}", a, peer_id))
+            .collect(),
+        enrUri: None,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    Ok(reply::json(&info).into_response())
+}
+
+async fn get_admin_v1_peers(admin_tx: Sender<AdminRequest>) -> Result<reply::Response> {
+    let peers = match admin_request(&admin_tx, AdminRequest::Peers).await {
+        Some(peers) => peers,
+        None => return Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    };
+    let peers: Vec<PeerInfoSerDe> = peers
+        .into_iter()
+        .map(|(peer_id, info)| PeerInfoSerDe {
+            peerId: peer_id.to_string(),
+            multiaddrs: info.addresses.iter().map(|a| a.to_string()).collect(),
+            protocols: info.protocols,
+            agentVersion: info.agent_version,
+        })
+        .collect();
+    Ok(reply::json(&peers).into_response())
+}
+
+// Dials a list of multiaddrs, replying once the dials are started
+async fn post_admin_v1_peers(
+    body: Bytes,
+    admin_tx: Sender<AdminRequest>,
+) -> Result<reply::Response> {
+    let addresses = match parse_body::<Vec<String>>(&body).and_then(|addresses| {
+        addresses
+            .iter()
+            .map(|a| a.parse::<Multiaddr>().map_err(|e| format!("{}: {}", a, e)))
+            .collect::<std::result::Result<Vec<_>, _>>()
+    }) {
+        Ok(addresses) => addresses,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
+
+    match admin_request(&admin_tx, |reply_tx| {
+        AdminRequest::Dial(addresses, reply_tx)
+    })
+    .await
+    {
+        Some(Ok(())) => Ok(reply::with_status("", StatusCode::OK).into_response()),
+        Some(Err(e)) => Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+        None => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+async fn delete_admin_v1_peers(
+    peer_id: String,
+    admin_tx: Sender<AdminRequest>,
+) -> Result<reply::Response> {
+    let peer_id = match peer_id.parse::<PeerId>() {
+        Ok(peer_id) => peer_id,
+        Err(e) => return Ok(error_reply(&e.to_string(), StatusCode::BAD_REQUEST)),
+    };
+
+    match admin_request(&admin_tx, |reply_tx| {
+        AdminRequest::Disconnect(peer_id, reply_tx)
+    })
+    .await
+    {
+        Some(true) => Ok(reply::with_status("", StatusCode::OK).into_response()),
+        Some(false) => Ok(error_reply("peer is not connected", StatusCode::NOT_FOUND)),
+        None => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+async fn get_admin_v1_peers_scores(
+    peer_scores_tx: Sender<PeerScoresRequest>,
+) -> Result<impl Reply> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if peer_scores_tx.send(reply_tx).await.is_err() {
+        return Ok(reply::with_status(
+            reply::json(&Vec::<PeerScoreSerDe>::new()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ));
+    }
+
+    match reply_rx.await {
+        Ok(scores) => {
+            let scores: Vec<PeerScoreSerDe> = scores
+                .into_iter()
+                .map(|(peer_id, score)| PeerScoreSerDe {
+                    peerId: peer_id.to_string(),
+                    score,
+                })
+                .collect();
+            Ok(reply::with_status(reply::json(&scores), StatusCode::OK))
+        }
+        Err(_) => Ok(reply::with_status(
+            reply::json(&Vec::<PeerScoreSerDe>::new()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
+
+fn error_reply(error: &str, status: StatusCode) -> reply::Response {
+    let error = ErrorSerDe {
+        error: error.to_string(),
+    };
+    reply::with_status(reply::json(&error), status).into_response()
+}
+
+// Replies once the service peer has answered, with its own verdict on the push
+async fn post_lightpush_v1_message(
+    body: Bytes,
+    lightpush_tx: Sender<LightPushRequest>,
+) -> Result<reply::Response> {
+    let request = match parse_body::<LightPushRequestSerDe>(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
+    let message = match WakuMessage::try_from(request.message) {
+        Ok(message) => message,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
+    let peer = match request.peerAddr.map(|a| a.parse::<Multiaddr>()).transpose() {
+        Ok(Some(address)) => match peer_id(&address) {
+            Ok(peer_id) => Some((peer_id, address)),
+            Err(e) => {
+                return Ok(error_reply(
+                    &format!("peerAddr: {}", e),
+                    StatusCode::BAD_REQUEST,
+                ))
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            return Ok(error_reply(
+                &format!("peerAddr: {}", e),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+    let pubsub_topic = match request.pubsubTopic.is_empty() {
+        true => DEFAULT_PUBSUB_TOPIC.to_string(),
+        false => request.pubsubTopic,
+    };
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request = LightPushRequest {
+        request_id: format!("{:016x}", rand::random::<u64>()),
+        pubsub_topic,
+        message,
+        peer,
+        reply_tx,
+    };
+    if lightpush_tx.send(request).await.is_err() {
+        return Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    match reply_rx.await {
+        Ok(Ok(response)) if response.get_is_success() => {
+            Ok(reply::with_status("", StatusCode::OK).into_response())
+        }
+        Ok(Ok(response)) => {
+            let status = match response.get_info() {
+                "TOO_MANY_REQUESTS" => StatusCode::TOO_MANY_REQUESTS,
+                _ => StatusCode::BAD_GATEWAY,
+            };
+            Ok(error_reply(
+                &format!("service peer failed to push: {}", response.get_info()),
+                status,
+            ))
+        }
+        Ok(Err(LightPushError::NotEnabled)) => Ok(error_reply(
+            "lightpush is not enabled",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        Ok(Err(LightPushError::NoPeer)) => Ok(error_reply(
+            "no lightpush service peer, pass peerAddr or start with --lightpushnode",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        Ok(Err(LightPushError::Failed(e))) => Ok(error_reply(&e, StatusCode::BAD_GATEWAY)),
+        Err(_) => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+fn parse_param<T: str::FromStr>(
+    param: &Option<String>,
+    name: &str,
+) -> std::result::Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    param
+        .as_ref()
+        .map(|v| v.parse().map_err(|e| format!("{}: {}", name, e)))
+        .transpose()
+}
+
+// Builds the query of the store endpoints, shared by both versions. The cursor is the
+// base64 digest of the last message of the previous page
+fn store_query_from_params(
+    params: &StoreParamsSerDe,
+) -> std::result::Result<(HistoryQuery, Option<(PeerId, Multiaddr)>), String> {
+    let mut query = HistoryQuery::new();
+    if let Some(pubsub_topic) = &params.pubsubTopic {
+        query.set_pubsub_topic(pubsub_topic.clone());
+    }
+    if let Some(content_topics) = &params.contentTopics {
+        query.set_content_filters(RepeatedField::from_vec(
+            content_topics
+                .split(',')
+                .filter(|t| !t.is_empty())
+                .map(|t| {
+                    let mut filter = ContentFilter::new();
+                    filter.set_contentTopic(t.to_string());
+                    filter
+                })
+                .collect(),
+        ));
+    }
+    if let Some(start_time) = parse_param(&params.startTime, "startTime")? {
+        query.set_start_time(start_time);
+    }
+    if let Some(end_time) = parse_param(&params.endTime, "endTime")? {
+        query.set_end_time(end_time);
+    }
+
+    let paging_info = query.mut_paging_info();
+    if let Some(page_size) = parse_param(&params.pageSize, "pageSize")? {
+        paging_info.set_page_size(page_size);
+    }
+    paging_info.set_direction(
+        match parse_param(&params.ascending, "ascending")?.unwrap_or(true) {
+            true => PagingInfo_Direction::FORWARD,
+            false => PagingInfo_Direction::BACKWARD,
+        },
+    );
+    if let Some(cursor) = &params.cursor {
+        let mut index = Index::new();
+        index.set_digest(
+            BASE64
+                .decode(cursor)
+                .map_err(|e| format!("cursor is not base64: {}", e))?,
+        );
+        paging_info.set_cursor(index);
+    }
+
+    let peer = match parse_param::<Multiaddr>(&params.peerAddr, "peerAddr")? {
+        Some(address) => Some((
+            peer_id(&address).map_err(|e| format!("peerAddr: {}", e))?,
+            address,
+        )),
+        None => None,
+    };
+    Ok((query, peer))
+}
+
+// Runs the query of a store endpoint, returning the request ID and the response
+async fn store_query(
+    params: StoreParamsSerDe,
+    store_query_tx: Sender<StoreQueryRequest>,
+) -> std::result::Result<(String, HistoryQuery, HistoryResponse), (String, StatusCode)> {
+    let (query, peer) =
+        store_query_from_params(&params).map_err(|e| (e, StatusCode::BAD_REQUEST))?;
+    let request_id = format!("{:016x}", rand::random::<u64>());
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request = StoreQueryRequest {
+        request_id: request_id.clone(),
+        query: query.clone(),
+        peer,
+        reply_tx,
+    };
+    let internal_error = || (String::new(), StatusCode::INTERNAL_SERVER_ERROR);
+    store_query_tx
+        .send(request)
+        .await
+        .map_err(|_| internal_error())?;
+
+    let response = match reply_rx.await.map_err(|_| internal_error())? {
+        Some(Ok(response)) => response,
+        Some(Err(e)) => return Err((e, StatusCode::BAD_GATEWAY)),
+        None => {
+            return Err((
+                "store is not enabled".to_string(),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    };
+    match response.get_error() {
+        HistoryResponse_Error::NONE => Ok((request_id, query, response)),
+        HistoryResponse_Error::INVALID_CURSOR => {
+            Err(("cursor is unknown".to_string(), StatusCode::BAD_REQUEST))
+        }
+        HistoryResponse_Error::BAD_REQUEST => {
+            Err(("query is malformed".to_string(), StatusCode::BAD_REQUEST))
+        }
+        HistoryResponse_Error::TOO_MANY_REQUESTS => Err((
+            "query was rate limited".to_string(),
+            StatusCode::TOO_MANY_REQUESTS,
+        )),
+    }
+}
+
+async fn get_store_v1_messages(
+    params: StoreParamsSerDe,
+    store_query_tx: Sender<StoreQueryRequest>,
+) -> Result<reply::Response> {
+    let (_, _, response) = match store_query(params, store_query_tx).await {
+        Ok(result) => result,
+        Err((e, status)) => return Ok(error_reply(&e, status)),
+    };
+
+    let paging_info = response.get_paging_info();
+    let cursor = paging_info.has_cursor().then(|| {
+        let index = paging_info.get_cursor();
+        StoreCursorSerDe {
+            pubsubTopic: index.get_pubsub_topic().to_string(),
+            senderTime: index.get_sender_time(),
+            storeTime: index.get_receiver_time(),
+            digest: BASE64.encode(index.get_digest()),
+        }
+    });
+    let page = StoreResponseV1SerDe {
+        messages: response
+            .get_messages()
+            .iter()
+            .map(WakuMessageSerDe::from)
+            .collect(),
+        cursor,
+    };
+    Ok(reply::json(&page).into_response())
+}
+
+// Messages are hashed with the pubsub topic of the query, which is therefore required: store
+// peers answer with the messages only, and would answer for every topic without one
+async fn get_store_v3_messages(
+    params: StoreParamsSerDe,
+    store_query_tx: Sender<StoreQueryRequest>,
+) -> Result<reply::Response> {
+    if params.pubsubTopic.as_deref().unwrap_or_default().is_empty() {
+        return Ok(error_reply(
+            "pubsubTopic is required",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let (request_id, query, response) = match store_query(params, store_query_tx).await {
+        Ok(result) => result,
+        Err((e, status)) => return Ok(error_reply(&e, status)),
+    };
+
+    let paging_info = response.get_paging_info();
+    let page = StoreResponseV3SerDe {
+        requestId: request_id,
+        statusCode: StatusCode::OK.as_u16(),
+        statusDesc: "OK".to_string(),
+        messages: response
+            .get_messages()
+            .iter()
+            .map(|m| StoredMessageSerDe {
+                messageHash: BASE64.encode(compute_digest(query.get_pubsub_topic(), m)),
+                message: WakuMessageSerDe::from(m),
+                pubsubTopic: query.get_pubsub_topic().to_string(),
+            })
+            .collect(),
+        paginationCursor: paging_info
+            .has_cursor()
+            .then(|| BASE64.encode(paging_info.get_cursor().get_digest())),
+    };
+    Ok(reply::json(&page).into_response())
+}
+
+async fn get_store_v1_archive(
+    store_archive_tx: Sender<StoreArchiveRequest>,
+) -> Result<reply::Response> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if store_archive_tx
+        .send(StoreArchiveRequest::Export(reply_tx))
+        .await
+        .is_err()
+    {
+        return Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    match reply_rx.await {
+        Ok(Some(messages)) => {
+            let archive: String = messages
+                .iter()
+                .map(|m| archive::to_json_line(m) + "\n")
+                .collect();
+            Ok(reply::with_header(archive, "content-type", "application/x-ndjson").into_response())
+        }
+        Ok(None) => Ok(error_reply(
+            "store is not enabled",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        Err(_) => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+// Archives the messages of an exported archive, skipping the ones already archived
+async fn post_store_v1_archive(
+    body: Bytes,
+    store_archive_tx: Sender<StoreArchiveRequest>,
+) -> Result<reply::Response> {
+    let messages = match str::from_utf8(&body)
+        .map_err(|e| e.to_string())
+        .and_then(archive::parse)
+    {
+        Ok(messages) => messages,
+        Err(e) => return Ok(error_reply(&e, StatusCode::BAD_REQUEST)),
+    };
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if store_archive_tx
+        .send(StoreArchiveRequest::Import(messages, reply_tx))
+        .await
+        .is_err()
+    {
+        return Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    match reply_rx.await {
+        Ok(Some(counts)) => Ok(reply::json(&ArchiveImportSerDe::from(counts)).into_response()),
+        Ok(None) => Ok(error_reply(
+            "store is not enabled",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        Err(_) => Ok(error_reply("", StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+// Prometheus text exposition of the node's metrics
+async fn get_metrics(metrics_registry: Arc<Registry>) -> Result<impl Reply> {
+    let mut body = String::new();
+    match encode(&mut body, &metrics_registry) {
+        Ok(_) => Ok(reply::with_status(body, StatusCode::OK)),
+        Err(_) => Ok(reply::with_status(
+            String::new(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        openapi,
+        relay_cache::RelayCache,
+        rest_api::{
+            recover_rejection, routes, AdminRequest, AllowedOrigin, LightPushError,
+            LightPushRequest, LightPushRequestSerDe, PeerInfo, PubSubTopicsSerDe, RelayStream,
+            StoreQueryRequest, WakuMessageSerDe, MAX_BODY_SIZE, MAX_RELAY_STREAMS,
+        },
+    };
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use libp2p::{Multiaddr, PeerId};
+    use prometheus_client::registry::Registry;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc, Mutex};
+    use waku_protocol::{
+        waku_lightpush::PushResponse,
+        waku_message::{WakuMessage, MAX_MESSAGE_SIZE},
+        waku_relay::network_behaviour::DEFAULT_PUBSUB_TOPIC,
+        waku_store::{
+            network_behaviour::compute_digest, HistoryResponse, HistoryResponse_Error,
+            PagingInfo_Direction,
+        },
+    };
+    use warp::{
+        filters::BoxedFilter,
+        http::{Response, StatusCode},
+        hyper::body::{self, Bytes, HttpBody},
+        reply::{self, Reply},
+        Filter,
+    };
+
+    // The routes as they are served, and the ends of their channels to the swarm loop that
+    // tests answer on
+    type Routes = BoxedFilter<(reply::Response,)>;
+
+    struct TestApi {
+        routes: Routes,
+        relay_stream: RelayStream,
+        relay_publish_rx: mpsc::Receiver<(WakuMessage, String)>,
+        admin_rx: mpsc::Receiver<AdminRequest>,
+        lightpush_rx: mpsc::Receiver<LightPushRequest>,
+        store_query_rx: mpsc::Receiver<StoreQueryRequest>,
+    }
+
+    fn test_api() -> TestApi {
+        let (relay_stream, _) = broadcast::channel(8);
+        let (relay_publish_tx, relay_publish_rx) = mpsc::channel(8);
+        let (admin_tx, admin_rx) = mpsc::channel(8);
+        let (lightpush_tx, lightpush_rx) = mpsc::channel(8);
+        let (store_query_tx, store_query_rx) = mpsc::channel(8);
+        let routes = routes(
+            Arc::new(Mutex::new(RelayCache::new(8))),
+            relay_stream.clone(),
+            relay_publish_tx,
+            mpsc::channel(8).0,
+            mpsc::channel(8).0,
+            mpsc::channel(8).0,
+            admin_tx,
+            lightpush_tx,
+            store_query_tx,
+            mpsc::channel(8).0,
+            Arc::new(Registry::default()),
+        )
+        .recover(recover_rejection)
+        .map(Reply::into_response)
+        .boxed();
+        TestApi {
+            routes,
+            relay_stream,
+            relay_publish_rx,
+            admin_rx,
+            lightpush_rx,
+            store_query_rx,
+        }
+    }
+
+    // The status and JSON body of a reply, null if it is empty. The reply must be one that
+    // the OpenAPI document gives for the request
+    fn json_reply(method: &str, path: &str, response: Response<Bytes>) -> (StatusCode, Value) {
+        let body = match response.body().is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(response.body()).unwrap(),
+        };
+
+        let document = openapi::document();
+        let operation = documented_operation(&document, method, path);
+        let documented = &operation["responses"][response.status().as_str()];
+        let at = format!("{} {} {}", method, path, response.status());
+        assert!(documented.is_object(), "{} is not documented", at);
+        match documented["content"]["application/json"]["schema"] {
+            Value::Null => assert_eq!(Value::Null, body, "{} has a body", at),
+            ref schema => validate(&document, schema, &body, &at),
+        }
+        (response.status(), body)
+    }
+
+    // The operation of the OpenAPI document for a request, whose path may have a query
+    fn documented_operation<'a>(document: &'a Value, method: &str, path: &str) -> &'a Value {
+        let path = path.split('?').next().unwrap();
+        let segments: Vec<&str> = path.split('/').collect();
+        document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .filter(|(template, _)| {
+                let template: Vec<&str> = template.split('/').collect();
+                template.len() == segments.len()
+                    && template
+                        .iter()
+                        .zip(&segments)
+                        .all(|(t, s)| t.starts_with('{') || t == s)
+            })
+            .find_map(|(_, operations)| operations.get(method.to_lowercase()))
+            .unwrap_or_else(|| panic!("{} {} is not documented", method, path))
+    }
+
+    // Checks a value against a schema of the OpenAPI document, as far as the schemas of the
+    // REST API go: references, allOf, types, base64 strings, and required and known properties
+    fn validate(document: &Value, schema: &Value, value: &Value, at: &str) {
+        if value.is_null() && schema["nullable"] == json!(true) {
+            return;
+        }
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            let schema = &document["components"]["schemas"][name];
+            assert!(schema.is_object(), "{}: {} is not a schema", at, reference);
+            return validate(document, schema, value, at);
+        }
+        if let Some(schemas) = schema["allOf"].as_array() {
+            return schemas
+                .iter()
+                .for_each(|s| validate(document, s, value, at));
+        }
+        let mismatch = format!("{}: {} does not match {}", at, value, schema);
+        match schema["type"].as_str() {
+            Some("object") => {
+                let fields = value.as_object().expect(&mismatch);
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    let required = required.as_str().unwrap();
+                    assert!(fields.contains_key(required), "{}: no {}", at, required);
+                }
+                if let Some(properties) = schema["properties"].as_object() {
+                    for (name, field) in fields {
+                        let property = properties
+                            .get(name)
+                            .unwrap_or_else(|| panic!("{}: {} is not documented", at, name));
+                        validate(document, property, field, &format!("{}.{}", at, name));
+                    }
+                }
+            }
+            Some("array") => {
+                for (i, item) in value.as_array().expect(&mismatch).iter().enumerate() {
+                    validate(document, &schema["items"], item, &format!("{}[{}]", at, i));
+                }
+            }
+            Some("string") => {
+                let string = value.as_str().expect(&mismatch);
+                if schema["format"] == json!("byte") {
+                    assert!(BASE64.decode(string).is_ok(), "{}: not base64", at);
+                }
+            }
+            Some("integer") => assert!(value.is_i64() || value.is_u64(), "{}", mismatch),
+            Some("number") => assert!(value.is_number(), "{}", mismatch),
+            Some("boolean") => assert!(value.is_boolean(), "{}", mismatch),
+            _ => panic!("{}", mismatch),
+        }
+    }
+
+    async fn get(routes: &Routes, path: &str) -> (StatusCode, Value) {
+        let request = warp::test::request().method("GET").path(path);
+        json_reply("GET", path, request.reply(routes).await)
+    }
+
+    async fn post(routes: &Routes, path: &str, body: &Value) -> (StatusCode, Value) {
+        let request = warp::test::request()
+            .method("POST")
+            .path(path)
+            .body(serde_json::to_vec(body).unwrap());
+        json_reply("POST", path, request.reply(routes).await)
+    }
+
+    async fn delete(routes: &Routes, path: &str) -> (StatusCode, Value) {
+        let request = warp::test::request().method("DELETE").path(path);
+        json_reply("DELETE", path, request.reply(routes).await)
+    }
+
+    #[test]
+    fn test_waku_message_serde() {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(vec![0, 159, 146, 150]);
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        msg.set_ephemeral(true);
+        let json = serde_json::to_string(&WakuMessageSerDe::from(&msg)).unwrap();
+        assert_eq!(
+            r#"{"payload":"AJ+Slg==","contentTopic":"/toy/1/chat/proto","version":0,"timestamp":0,"ephemeral":true}"#,
+            json
+        );
+        let parsed: WakuMessageSerDe = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, WakuMessage::try_from(parsed).unwrap());
+
+        let malformed: WakuMessageSerDe =
+            serde_json::from_str(r#"{"payload":"not base64!","contentTopic":"/toy/1/chat/proto"}"#)
+                .unwrap();
+        assert!(WakuMessage::try_from(malformed)
+            .unwrap_err()
+            .starts_with("payload"));
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let origin: AllowedOrigin = "http://localhost:3000/".parse().unwrap();
+        assert_eq!(origin.0, "http://localhost:3000");
+        assert!("*".parse::<AllowedOrigin>().is_ok());
+        assert!("localhost:3000".parse::<AllowedOrigin>().is_err());
+        assert!("http://localhost:3000/app"
+            .parse::<AllowedOrigin>()
+            .is_err());
+        assert!("ftp://localhost".parse::<AllowedOrigin>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit() {
+        let mut api = test_api();
+        let path = "/relay/v1/messages/%2Fwaku%2F2%2Fdefault-waku%2Fproto";
+        // A message of MAX_MESSAGE_SIZE still fits once its payload is base64 encoded
+        let message = json!({
+            "payload": BASE64.encode(vec![0; MAX_MESSAGE_SIZE]),
+            "contentTopic": "/toy/1/chat/proto",
+        });
+        let response = warp::test::request()
+            .method("POST")
+            .path(path)
+            .body(serde_json::to_vec(&message).unwrap())
+            .reply(&api.routes)
+            .await;
+        assert_eq!(StatusCode::OK, response.status());
+        let (published, _) = api.relay_publish_rx.recv().await.unwrap();
+        assert_eq!(MAX_MESSAGE_SIZE, published.get_payload().len());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(path)
+            .body(vec![b' '; MAX_BODY_SIZE as usize + 1])
+            .reply(&api.routes)
+            .await;
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        let error: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(json!({ "error": "request body is too large" }), error);
+    }
+
+    // The event name and data of the next server-sent event of a stream
+    async fn next_event(stream: &mut body::Body) -> (String, Value) {
+        let mut frame = String::new();
+        while !frame.ends_with("\n\n") {
+            let chunk = stream.data().await.unwrap().unwrap();
+            frame.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let field = |name: &str| {
+            frame
+                .lines()
+                .find_map(|l| l.strip_prefix(name))
+                .unwrap()
+                .to_string()
+        };
+        (
+            field("event:"),
+            serde_json::from_str(&field("data:")).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_relay_stream_route() {
+        let api = test_api();
+        let subscribe = |query: &str| {
+            warp::test::request()
+                .method("GET")
+                .path(&format!("/relay/v1/stream{}", query))
+                .filter(&api.routes)
+        };
+        let filtered = subscribe(
+            "?pubsubTopic=%2Fwaku%2F2%2Fdefault-waku%2Fproto&contentTopics=%2Ftoy%2F1%2Fchat%2Fproto",
+        )
+        .await
+        .unwrap();
+        assert_eq!("text/event-stream", filtered.headers()["content-type"]);
+        let unfiltered = subscribe("").await.unwrap();
+
+        let message = |content_topic: &str| {
+            let mut message = WakuMessage::new();
+            message.set_payload(vec![1]);
+            message.set_content_topic(content_topic.to_string());
+            message
+        };
+        let topic = "/waku/2/default-waku/proto";
+        let other_topic = "/waku/2/other/proto";
+        for (pubsub_topic, content_topic) in [
+            (other_topic, "/toy/1/chat/proto"),
+            (topic, "/toy/1/other/proto"),
+            (topic, "/toy/1/chat/proto"),
+        ] {
+            api.relay_stream
+                .send((pubsub_topic.to_string(), message(content_topic)))
+                .unwrap();
+        }
+
+        // Only the last message matches both topics of the filtered stream
+        let (event, data) = next_event(&mut filtered.into_body()).await;
+        assert_eq!("message", event);
+        assert_eq!(json!(topic), data["pubsubTopic"]);
+        assert_eq!(json!("/toy/1/chat/proto"), data["message"]["contentTopic"]);
+        assert_eq!(json!("AQ=="), data["message"]["payload"]);
+        let hash = compute_digest(topic, &message("/toy/1/chat/proto"));
+        assert_eq!(json!(BASE64.encode(hash)), data["messageHash"]);
+
+        let mut unfiltered = unfiltered.into_body();
+        for pubsub_topic in [other_topic, topic, topic] {
+            let (_, data) = next_event(&mut unfiltered).await;
+            assert_eq!(json!(pubsub_topic), data["pubsubTopic"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_stream_limit() {
+        let api = test_api();
+        let subscribe = || {
+            warp::test::request()
+                .method("GET")
+                .path("/relay/v1/stream")
+                .filter(&api.routes)
+        };
+        let mut streams = Vec::new();
+        for _ in 0..MAX_RELAY_STREAMS {
+            let stream = subscribe().await.unwrap();
+            assert_eq!(StatusCode::OK, stream.status());
+            streams.push(stream);
+        }
+
+        // Further subscribers are turned away, while the other routes still answer
+        let (status, error) = get(&api.routes, "/relay/v1/stream").await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(
+            json!({ "error": "too many relay stream subscribers" }),
+            error
+        );
+        let (status, _) = get(&api.routes, "/openapi.json").await;
+        assert_eq!(StatusCode::OK, status);
+
+        // A subscriber that disconnects makes room for another
+        streams.pop();
+        assert_eq!(StatusCode::OK, subscribe().await.unwrap().status());
+    }
+
+    #[tokio::test]
+    async fn test_store_routes() {
+        let TestApi {
+            routes,
+            mut store_query_rx,
+            ..
+        } = test_api();
+        let topic = "/waku/2/default-waku/proto";
+        let mut message = WakuMessage::new();
+        message.set_payload(vec![1]);
+        message.set_content_topic("/toy/1/chat/proto".to_string());
+        let store_peer = PeerId::random();
+
+        // Answers as the swarm loop would: a page of the local archive without peerAddr,
+        // unless the cursor is unknown, and a rate limited query with it
+        let archived = message.clone();
+        tokio::spawn(async move {
+            while let Some(request) = store_query_rx.recv().await {
+                let query = request.query;
+                let mut response = HistoryResponse::new();
+                match request.peer {
+                    Some((peer_id, _)) => {
+                        assert_eq!(store_peer, peer_id);
+                        response.set_error(HistoryResponse_Error::TOO_MANY_REQUESTS);
+                    }
+                    None if query.get_paging_info().get_cursor().get_digest() == [9] => {
+                        response.set_error(HistoryResponse_Error::INVALID_CURSOR);
+                    }
+                    None => {
+                        assert_eq!(topic, query.get_pubsub_topic());
+                        let content_topics: Vec<&str> = query
+                            .get_content_filters()
+                            .iter()
+                            .map(|f| f.get_contentTopic())
+                            .collect();
+                        assert_eq!(
+                            vec!["/toy/1/chat/proto", "/toy/1/other/proto"],
+                            content_topics
+                        );
+                        let paging_info = query.get_paging_info();
+                        assert_eq!(5, paging_info.get_page_size());
+                        assert_eq!(PagingInfo_Direction::BACKWARD, paging_info.get_direction());
+                        response.mut_messages().push(archived.clone());
+                        response
+                            .mut_paging_info()
+                            .mut_cursor()
+                            .set_digest(vec![1, 2]);
+                    }
+                }
+                let _ = request.reply_tx.send(Some(Ok(response)));
+            }
+        });
+
+        let query = "pubsubTopic=%2Fwaku%2F2%2Fdefault-waku%2Fproto\
+            &contentTopics=%2Ftoy%2F1%2Fchat%2Fproto,%2Ftoy%2F1%2Fother%2Fproto\
+            &pageSize=5&ascending=false";
+        let (status, page) = get(&routes, &format!("/store/v1/messages?{}", query)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!("AQ=="), page["messages"][0]["payload"]);
+        assert_eq!(json!("AQI="), page["cursor"]["digest"]);
+
+        let (status, page) = get(&routes, &format!("/store/v3/messages?{}", query)).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(200), page["statusCode"]);
+        assert_eq!(
+            json!(BASE64.encode(compute_digest(topic, &message))),
+            page["messages"][0]["messageHash"]
+        );
+        assert_eq!(json!(topic), page["messages"][0]["pubsubTopic"]);
+        assert_eq!(json!("AQI="), page["paginationCursor"]);
+
+        // Malformed parameters are rejected before any query is sent
+        for params in [
+            "cursor=%21%21",
+            "pageSize=many",
+            "ascending=maybe",
+            "peerAddr=%2Fip4%2F127.0.0.1%2Ftcp%2F60000",
+        ] {
+            for version in ["v1", "v3"] {
+                let path = format!("/store/{}/messages?{}", version, params);
+                let (status, error) = get(&routes, &path).await;
+                assert_eq!(StatusCode::BAD_REQUEST, status, "{}", path);
+                assert!(error["error"].is_string());
+            }
+        }
+        let path = "/store/v3/messages?pubsubTopic=%2Fwaku%2F2%2Fdefault-waku%2Fproto\
+            &cursor=CQ%3D%3D";
+        let (status, error) = get(&routes, path).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(json!("cursor is unknown"), error["error"]);
+
+        // Hashes depend on the pubsub topic, which v3 queries must therefore give
+        for params in [
+            "",
+            "?contentTopics=%2Ftoy%2F1%2Fchat%2Fproto",
+            "?pubsubTopic=",
+        ] {
+            let path = format!("/store/v3/messages{}", params);
+            let (status, error) = get(&routes, &path).await;
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{}", path);
+            assert_eq!(json!("pubsubTopic is required"), error["error"]);
+        }
+
+        let peer_addr = format!("%2Fip4%2F127.0.0.1%2Ftcp%2F60000%2Fp2p%2F{}", store_peer);
+        for version in ["v1", "v3"] {
+            let path = format!(
+                "/store/{}/messages?pubsubTopic=%2Fwaku%2F2%2Fdefault-waku%2Fproto&peerAddr={}",
+                version, peer_addr
+            );
+            let (status, error) = get(&routes, &path).await;
+            assert_eq!(StatusCode::TOO_MANY_REQUESTS, status);
+            assert_eq!(json!("query was rate limited"), error["error"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lightpush_route() {
+        let TestApi {
+            routes,
+            mut lightpush_rx,
+            ..
+        } = test_api();
+        let service_peer = PeerId::random();
+
+        // Answers as the swarm loop would, depending on the content topic of the message
+        tokio::spawn(async move {
+            while let Some(request) = lightpush_rx.recv().await {
+                let mut response = PushResponse::new();
+                let reply = match request.message.get_content_topic() {
+                    "/toy/1/pushed/proto" => {
+                        assert_eq!(DEFAULT_PUBSUB_TOPIC, request.pubsub_topic);
+                        assert_eq!(vec![1], request.message.get_payload());
+                        assert_eq!(Some(service_peer), request.peer.map(|(p, _)| p));
+                        response.set_is_success(true);
+                        Ok(response)
+                    }
+                    "/toy/1/refused/proto" => {
+                        response.set_info("no relay peers".to_string());
+                        Ok(response)
+                    }
+                    "/toy/1/limited/proto" => {
+                        response.set_info("TOO_MANY_REQUESTS".to_string());
+                        Ok(response)
+                    }
+                    "/toy/1/disabled/proto" => Err(LightPushError::NotEnabled),
+                    "/toy/1/unpeered/proto" => Err(LightPushError::NoPeer),
+                    _ => Err(LightPushError::Failed("dial failed".to_string())),
+                };
+                let _ = request.reply_tx.send(reply);
+            }
+        });
+
+        let peer_addr = format!("/ip4/127.0.0.1/tcp/60000/p2p/{}", service_peer);
+        let push = |content_topic: &str| {
+            json!({
+                "message": { "payload": "AQ==", "contentTopic": content_topic },
+                "peerAddr": peer_addr,
+            })
+        };
+        let path = "/lightpush/v1/message";
+        let (status, body) = post(&routes, path, &push("/toy/1/pushed/proto")).await;
+        assert_eq!((StatusCode::OK, Value::Null), (status, body));
+
+        // The verdict of the service peer, and failures to reach it, are errors
+        for (content_topic, expected) in [
+            ("/toy/1/refused/proto", StatusCode::BAD_GATEWAY),
+            ("/toy/1/limited/proto", StatusCode::TOO_MANY_REQUESTS),
+            ("/toy/1/disabled/proto", StatusCode::SERVICE_UNAVAILABLE),
+            ("/toy/1/unpeered/proto", StatusCode::SERVICE_UNAVAILABLE),
+            ("/toy/1/unreachable/proto", StatusCode::BAD_GATEWAY),
+        ] {
+            let (status, error) = post(&routes, path, &push(content_topic)).await;
+            assert_eq!(expected, status, "{}", content_topic);
+            assert!(error["error"].is_string());
+        }
+        let (_, error) = post(&routes, path, &push("/toy/1/refused/proto")).await;
+        assert_eq!(
+            json!("service peer failed to push: no relay peers"),
+            error["error"]
+        );
+        let (_, error) = post(&routes, path, &push("/toy/1/disabled/proto")).await;
+        assert_eq!(json!("lightpush is not enabled"), error["error"]);
+
+        // Malformed requests are rejected before anything is pushed
+        let mut malformed = push("/toy/1/pushed/proto");
+        malformed["peerAddr"] = json!("/ip4/127.0.0.1/tcp/60000");
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            post(&routes, path, &malformed).await.0
+        );
+        let malformed =
+            json!({ "message": { "payload": "!!", "contentTopic": "/toy/1/pushed/proto" } });
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            post(&routes, path, &malformed).await.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes() {
+        let TestApi {
+            routes,
+            mut admin_rx,
+            ..
+        } = test_api();
+        let (local_peer, remote_peer) = (PeerId::random(), PeerId::random());
+        let listen_address: Multiaddr = "/ip4/127.0.0.1/tcp/60000".parse().unwrap();
+        let remote_address: Multiaddr = "/ip4/127.0.0.1/tcp/60001".parse().unwrap();
+        let unreachable = "/ip4/127.0.0.1/tcp/60002";
+
+        // Answers as the swarm loop would, and reports the dialed addresses
+        let (dialed_tx, mut dialed_rx) = mpsc::unbounded_channel();
+        let peer = PeerInfo {
+            addresses: vec![remote_address.clone()],
+            protocols: vec!["/vac/waku/relay/2.0.0".to_string()],
+            agent_version: Some("nwaku".to_string()),
+        };
+        let listen_addresses = vec![listen_address.clone()];
+        tokio::spawn(async move {
+            while let Some(request) = admin_rx.recv().await {
+                match request {
+                    AdminRequest::Info(reply_tx) => {
+                        let _ = reply_tx.send((local_peer, listen_addresses.clone()));
+                    }
+                    AdminRequest::Peers(reply_tx) => {
+                        let _ = reply_tx.send(vec![(remote_peer, peer.clone())]);
+                    }
+                    AdminRequest::Dial(addresses, reply_tx) => {
+                        let dialed = match addresses.iter().any(|a| a.to_string() == unreachable) {
+                            true => Err(format!("{}: no route", unreachable)),
+                            false => Ok(()),
+                        };
+                        let _ = dialed_tx.send(addresses);
+                        let _ = reply_tx.send(dialed);
+                    }
+                    AdminRequest::Disconnect(peer_id, reply_tx) => {
+                        let _ = reply_tx.send(peer_id == remote_peer);
+                    }
+                    AdminRequest::RelaySubscriptions(reply_tx) => {
+                        let _ = reply_tx.send(Vec::new());
+                    }
+                }
+            }
+        });
+
+        let (status, info) = get(&routes, "/debug/v1/info").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(local_peer.to_string()), info["peerId"]);
+        assert_eq!(
+            json!([format!("{}/p2p/{}", listen_address, local_peer)]),
+            info["listenAddresses"]
+        );
+        assert_eq!(json!(env!("CARGO_PKG_VERSION")), info["version"]);
+
+        let (status, peers) = get(&routes, "/admin/v1/peers").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            json!([{
+                "peerId": remote_peer.to_string(),
+                "multiaddrs": [remote_address.to_string()],
+                "protocols": ["/vac/waku/relay/2.0.0"],
+                "agentVersion": "nwaku",
+            }]),
+            peers
+        );
+
+        let (status, _) = post(&routes, "/admin/v1/peers", &json!([remote_address])).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(vec![remote_address], dialed_rx.recv().await.unwrap());
+        let (status, error) = post(&routes, "/admin/v1/peers", &json!([unreachable])).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(json!(format!("{}: no route", unreachable)), error["error"]);
+        dialed_rx.recv().await.unwrap();
+
+        // Invalid multiaddrs are rejected before anything is dialed
+        for body in [
+            json!(["/ip4/127.0.0.1/tcp/60000", "not a multiaddr"]),
+            json!("/ip4/127.0.0.1"),
+        ] {
+            let (status, error) = post(&routes, "/admin/v1/peers", &body).await;
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{}", body);
+            assert!(error["error"].is_string());
+        }
+        assert!(dialed_rx.try_recv().is_err());
+
+        let path = format!("/admin/v1/peers/{}", remote_peer);
+        assert_eq!(StatusCode::OK, delete(&routes, &path).await.0);
+        let path = format!("/admin/v1/peers/{}", PeerId::random());
+        assert_eq!(StatusCode::NOT_FOUND, delete(&routes, &path).await.0);
+        let (status, _) = delete(&routes, "/admin/v1/peers/not-a-peer-id").await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+    }
+
+    #[tokio::test]
+    async fn test_openapi_routes() {
+        // Building the routes checks that the document has each of them. The channels are
+        // closed, so that handlers answer right away
+        let routes = routes(
+            Arc::new(Mutex::new(RelayCache::new(1))),
+            broadcast::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            mpsc::channel(1).0,
+            Arc::new(Registry::default()),
+        );
+
+        let document = openapi::document();
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            let request_path: Vec<&str> = path
+                .split('/')
+                .map(|s| match s.starts_with('{') {
+                    true => "x",
+                    false => s,
+                })
+                .collect();
+            for method in operations.as_object().unwrap().keys() {
+                let response = warp::test::request()
+                    .method(&method.to_uppercase())
+                    .path(&request_path.join("/"))
+                    .body("{}")
+                    .filter(&routes)
+                    .await;
+                assert!(response.is_ok(), "{} {} is not routed", method, path);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openapi_examples() {
+        let document = openapi::document();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+
+        // Examples match their schemas, and are read by the routes they document
+        for (name, schema) in schemas {
+            if let Some(example) = schema.get("example") {
+                validate(&document, schema, example, name);
+            }
+        }
+        let example = |name: &str| schemas[name]["example"].clone();
+        serde_json::from_value::<WakuMessageSerDe>(example("WakuMessage")).unwrap();
+        serde_json::from_value::<PubSubTopicsSerDe>(example("PubSubTopics")).unwrap();
+        serde_json::from_value::<LightPushRequestSerDe>(example("LightPushRequest")).unwrap();
+
+        let mut api = test_api();
+        let path = "/relay/v1/messages/%2Fwaku%2F2%2Fdefault-waku%2Fproto";
+        let (status, _) = post(&api.routes, path, &example("WakuMessage")).await;
+        assert_eq!(StatusCode::OK, status);
+        let (message, _) = api.relay_publish_rx.recv().await.unwrap();
+        assert_eq!(b"hello", message.get_payload());
+
+        // Every schema that a path refers to is in the components
+        let mut references = vec![document["paths"].clone()];
+        while let Some(value) = references.pop() {
+            match value {
+                Value::Object(fields) => {
+                    if let Some(Value::String(reference)) = fields.get("$ref") {
+                        let name = reference.strip_prefix("#/components/schemas/").unwrap();
+                        assert!(schemas.contains_key(name), "{} is missing", reference);
+                    }
+                    references.extend(fields.into_iter().map(|(_, v)| v));
+                }
+                Value::Array(items) => references.extend(items),
+                _ => {}
+            }
+        }
     }
 }
@@ -0,0 +1,893 @@
+// JSON-RPC 2.0 API of 16/WAKU2-RPC, over HTTP POST. Relay, store, lightpush and admin
+// methods are served through the same channels to the swarm loop as the REST API. Filter
+// and private (encrypted payload) methods are not implemented
+use futures::future::join_all;
+use futures_rustls::TlsAcceptor;
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use protobuf::RepeatedField;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{fmt::Display, sync::Arc};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot, Mutex,
+    },
+};
+use waku_protocol::{
+    waku_message::{WakuMessage, MAX_MESSAGE_SIZE},
+    waku_store::{
+        ContentFilter, HistoryQuery, HistoryResponse, HistoryResponse_Error, Index,
+        PagingInfo_Direction,
+    },
+};
+use warp::{filters::BoxedFilter, http::StatusCode, hyper::body::Bytes, reply, Filter, Reply};
+
+use crate::{
+    relay_cache::RelayCache,
+    rest_api::{
+        admin_request, authorize, incoming, recover_rejection, AdminRequest, LightPushError,
+        LightPushRequest, StoreQueryRequest,
+    },
+};
+
+// Largest request body accepted: a message of MAX_MESSAGE_SIZE, doubled by its hex payload,
+// with room for the JSON envelope and small batches
+const MAX_REQUEST_SIZE: u64 = (MAX_MESSAGE_SIZE * 2 + 64 * 1024) as u64;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+// Failures of the method itself, e.g. a store peer that cannot be reached
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Serialize, Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Display) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn internal() -> Self {
+        RpcError::new(INTERNAL_ERROR, "internal error")
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+#[derive(Deserialize, Debug)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+// Messages as returned by the node: the payload is an array of bytes
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+struct WakuMessageSerDe {
+    payload: Vec<u8>,
+    contentTopic: String,
+    version: u32,
+    timestamp: i64,
+}
+
+impl From<&WakuMessage> for WakuMessageSerDe {
+    fn from(waku_message: &WakuMessage) -> Self {
+        WakuMessageSerDe {
+            payload: waku_message.get_payload().to_vec(),
+            contentTopic: waku_message.get_content_topic().to_string(),
+            version: waku_message.get_version(),
+            timestamp: waku_message.get_timestamp(),
+        }
+    }
+}
+
+// Messages as posted to the node: the payload is a hex string, with or without 0x. They are
+// validated as the REST API validates its messages
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct WakuRelayMessageSerDe {
+    payload: String,
+    #[serde(default)]
+    contentTopic: String,
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    timestamp: i64,
+}
+
+impl TryFrom<WakuRelayMessageSerDe> for WakuMessage {
+    type Error = RpcError;
+
+    fn try_from(message: WakuRelayMessageSerDe) -> Result<Self, Self::Error> {
+        if message.contentTopic.is_empty() {
+            return Err(RpcError::new(INVALID_PARAMS, "contentTopic is empty"));
+        }
+        let payload = message
+            .payload
+            .strip_prefix("0x")
+            .unwrap_or(&message.payload);
+        let payload = hex::decode(payload)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("payload is not hex: {}", e)))?;
+        let mut waku_message = WakuMessage::new();
+        waku_message.set_payload(payload);
+        waku_message.set_content_topic(message.contentTopic);
+        waku_message.set_version(message.version);
+        waku_message.set_timestamp(message.timestamp);
+        Ok(waku_message)
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+struct WakuInfoSerDe {
+    listenAddresses: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct WakuPeerSerDe {
+    multiaddr: String,
+    protocol: String,
+    connected: bool,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+struct ContentFilterSerDe {
+    contentTopic: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexSerDe {
+    digest: Vec<u8>,
+    receiverTime: i64,
+    senderTime: i64,
+    pubsubTopic: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct PagingOptionsSerDe {
+    pageSize: u64,
+    cursor: Option<IndexSerDe>,
+    forward: bool,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+struct StoreResponseSerDe {
+    messages: Vec<WakuMessageSerDe>,
+    // Only set if there are more pages, with the cursor to the next one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pagingOptions: Option<PagingOptionsSerDe>,
+}
+
+// Channels to the swarm loop, and the relay cache of get_waku_v2_relay_v1_messages. The
+// cache is separate from the REST API's, as messages are taken from it when read
+#[derive(Clone)]
+struct RpcState {
+    relay_cache: Arc<Mutex<RelayCache>>,
+    relay_publish_tx: Sender<(WakuMessage, String)>,
+    relay_subscribe_tx: Sender<Vec<String>>,
+    relay_unsubscribe_tx: Sender<Vec<String>>,
+    admin_tx: Sender<AdminRequest>,
+    lightpush_tx: Sender<LightPushRequest>,
+    // Store queries go to the --storenode peers, or to the local archive if there are none
+    store_query_tx: Sender<StoreQueryRequest>,
+}
+
+// How the JSON-RPC API is served. As for the REST API, the listener is bound by the caller
+pub struct RpcConfig {
+    pub listener: TcpListener,
+    // Serves HTTPS instead of HTTP
+    pub tls: Option<TlsAcceptor>,
+    // Bearer token required in the Authorization header of every request
+    pub auth_token: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    config: RpcConfig,
+    relay_cache: RelayCache,
+    mut relay_cache_rx: Receiver<(WakuMessage, String)>,
+    relay_publish_tx: Sender<(WakuMessage, String)>,
+    relay_subscribe_tx: Sender<Vec<String>>,
+    relay_unsubscribe_tx: Sender<Vec<String>>,
+    admin_tx: Sender<AdminRequest>,
+    lightpush_tx: Sender<LightPushRequest>,
+    store_query_tx: Sender<StoreQueryRequest>,
+) {
+    let state = RpcState {
+        relay_cache: Arc::new(Mutex::new(relay_cache)),
+        relay_publish_tx,
+        relay_subscribe_tx,
+        relay_unsubscribe_tx,
+        admin_tx,
+        lightpush_tx,
+        store_query_tx,
+    };
+    let relay_cache = state.relay_cache.clone();
+
+    let route = route(state, config.auth_token);
+    tokio::spawn(warp::serve(route).run_incoming(incoming(config.listener, config.tls)));
+
+    while let Some((waku_message, topic)) = relay_cache_rx.recv().await {
+        relay_cache.lock().await.push(&topic, waku_message);
+    }
+}
+
+// Rejects requests without the bearer token with 401, like the REST API does
+fn route(state: RpcState, auth_token: Option<String>) -> BoxedFilter<(reply::Response,)> {
+    authorize(auth_token)
+        .and(warp::post())
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(MAX_REQUEST_SIZE).and(warp::body::bytes()))
+        .and(warp::any().map(move || state.clone()))
+        .then(handle_body)
+        .recover(recover_rejection)
+        .map(Reply::into_response)
+        .boxed()
+}
+
+// Answers a single request or a batch of them. Notifications, requests without an id, are
+// run but get no response
+async fn handle_body(body: Bytes, state: RpcState) -> reply::Response {
+    let responses = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(requests)) if !requests.is_empty() => {
+            let responses: Vec<Value> = join_all(requests.into_iter().map(|r| handle(r, &state)))
+                .await
+                .into_iter()
+                .flatten()
+                .collect();
+            match responses.is_empty() {
+                true => None,
+                false => Some(Value::Array(responses)),
+            }
+        }
+        Ok(request) => handle(request, &state).await,
+        Err(e) => Some(response(Value::Null, Err(RpcError::new(PARSE_ERROR, e)))),
+    };
+    match responses {
+        Some(responses) => reply::json(&responses).into_response(),
+        None => reply::with_status("", StatusCode::NO_CONTENT).into_response(),
+    }
+}
+
+async fn handle(request: Value, state: &RpcState) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let request = match serde_json::from_value::<RpcRequest>(request) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => {
+            let error = RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+            return Some(response(id.unwrap_or(Value::Null), Err(error)));
+        }
+        Err(e) => {
+            let error = RpcError::new(INVALID_REQUEST, e);
+            return Some(response(id.unwrap_or(Value::Null), Err(error)));
+        }
+    };
+    let result = call(&request.method, request.params, state).await;
+    id.map(|id| response(id, result))
+}
+
+fn response(id: Value, result: RpcResult) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+// Positional params, as in the spec. Missing trailing ones are null, so that optional
+// params can be left out
+fn params<T: DeserializeOwned>(params: Value, count: usize) -> Result<T, RpcError> {
+    let mut params = match params {
+        Value::Array(params) => params,
+        Value::Null => Vec::new(),
+        _ => return Err(RpcError::new(INVALID_PARAMS, "params must be an array")),
+    };
+    if params.len() > count {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("expected at most {} params", count),
+        ));
+    }
+    params.resize(count, Value::Null);
+    serde_json::from_value(Value::Array(params)).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+fn no_params(params: Value) -> Result<(), RpcError> {
+    match params {
+        Value::Null => Ok(()),
+        Value::Array(params) if params.is_empty() => Ok(()),
+        _ => Err(RpcError::new(INVALID_PARAMS, "expected no params")),
+    }
+}
+
+fn to_value(result: impl Serialize) -> RpcResult {
+    serde_json::to_value(result).map_err(|_| RpcError::internal())
+}
+
+async fn call(method: &str, params_value: Value, state: &RpcState) -> RpcResult {
+    match method {
+        "get_waku_v2_debug_v1_info" => {
+            no_params(params_value)?;
+            get_debug_v1_info(state).await
+        }
+        "get_waku_v2_debug_v1_version" => {
+            no_params(params_value)?;
+            to_value(env!("CARGO_PKG_VERSION"))
+        }
+        "post_waku_v2_relay_v1_message" => {
+            let (topic, message) = params::<(String, WakuRelayMessageSerDe)>(params_value, 2)?;
+            let message = WakuMessage::try_from(message)?;
+            state
+                .relay_publish_tx
+                .send((message, topic))
+                .await
+                .map_err(|_| RpcError::internal())?;
+            to_value(true)
+        }
+        "get_waku_v2_relay_v1_messages" => {
+            let (topic,) = params::<(String,)>(params_value, 1)?;
+            match state.relay_cache.lock().await.take(&topic) {
+                Some(messages) => to_value(
+                    messages
+                        .iter()
+                        .map(WakuMessageSerDe::from)
+                        .collect::<Vec<_>>(),
+                ),
+                None => Err(RpcError::new(
+                    SERVER_ERROR,
+                    format!("not subscribed to {}", topic),
+                )),
+            }
+        }
+        "post_waku_v2_relay_v1_subscriptions" => {
+            let (topics,) = params::<(Vec<String>,)>(params_value, 1)?;
+            let mut relay_cache = state.relay_cache.lock().await;
+            for t in &topics {
+                relay_cache.subscribe(t);
+            }
+            drop(relay_cache);
+            state
+                .relay_subscribe_tx
+                .send(topics)
+                .await
+                .map_err(|_| RpcError::internal())?;
+            to_value(true)
+        }
+        "delete_waku_v2_relay_v1_subscriptions" => {
+            let (topics,) = params::<(Vec<String>,)>(params_value, 1)?;
+            let mut relay_cache = state.relay_cache.lock().await;
+            for t in &topics {
+                relay_cache.unsubscribe(t);
+            }
+            drop(relay_cache);
+            state
+                .relay_unsubscribe_tx
+                .send(topics)
+                .await
+                .map_err(|_| RpcError::internal())?;
+            to_value(true)
+        }
+        "get_waku_v2_store_v1_messages" => {
+            let query = params::<StoreQueryParams>(params_value, 5)?;
+            get_store_v1_messages(query, state).await
+        }
+        "post_waku_v2_lightpush_v1_message" => {
+            let (topic, message) = params::<(String, WakuRelayMessageSerDe)>(params_value, 2)?;
+            post_lightpush_v1_message(topic, WakuMessage::try_from(message)?, state).await
+        }
+        "get_waku_v2_admin_v1_peers" => {
+            no_params(params_value)?;
+            get_admin_v1_peers(state).await
+        }
+        "post_waku_v2_admin_v1_peers" => {
+            let (addresses,) = params::<(Vec<String>,)>(params_value, 1)?;
+            let addresses = addresses
+                .iter()
+                .map(|a| {
+                    a.parse::<Multiaddr>()
+                        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{}: {}", a, e)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            match admin_request(&state.admin_tx, |reply_tx| {
+                AdminRequest::Dial(addresses, reply_tx)
+            })
+            .await
+            {
+                Some(Ok(())) => to_value(true),
+                Some(Err(e)) => Err(RpcError::new(SERVER_ERROR, e)),
+                None => Err(RpcError::internal()),
+            }
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method not found: {}", method),
+        )),
+    }
+}
+
+async fn get_debug_v1_info(state: &RpcState) -> RpcResult {
+    let (peer_id, addresses) = admin_request(&state.admin_tx, AdminRequest::Info)
+        .await
+        .ok_or_else(RpcError::internal)?;
+    to_value(WakuInfoSerDe {
+        listenAddresses: addresses
+            .into_iter()
+            .map(|a| format!("{}/p2p/{}", a, peer_id))
+            .collect(),
+    })
+}
+
+// One entry per connected peer and Waku protocol it supports, as reported by identify
+async fn get_admin_v1_peers(state: &RpcState) -> RpcResult {
+    let peers = admin_request(&state.admin_tx, AdminRequest::Peers)
+        .await
+        .ok_or_else(RpcError::internal)?;
+    let peers: Vec<WakuPeerSerDe> = peers
+        .iter()
+        .flat_map(|(peer_id, info)| {
+            let mut multiaddr = info
+                .addresses
+                .first()
+                .cloned()
+                .unwrap_or_else(Multiaddr::empty);
+            if !matches!(multiaddr.iter().last(), Some(Protocol::P2p(_))) {
+                multiaddr.push(Protocol::P2p(*peer_id));
+            }
+            let multiaddr = multiaddr.to_string();
+            info.protocols
+                .iter()
+                .filter(|p| p.starts_with("/vac/waku/"))
+                .map(move |protocol| WakuPeerSerDe {
+                    multiaddr: multiaddr.clone(),
+                    protocol: protocol.clone(),
+                    connected: true,
+                })
+        })
+        .collect();
+    to_value(peers)
+}
+
+async fn post_lightpush_v1_message(
+    topic: String,
+    message: WakuMessage,
+    state: &RpcState,
+) -> RpcResult {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request = LightPushRequest {
+        request_id: format!("{:016x}", rand::random::<u64>()),
+        pubsub_topic: topic,
+        message,
+        peer: None,
+        reply_tx,
+    };
+    state
+        .lightpush_tx
+        .send(request)
+        .await
+        .map_err(|_| RpcError::internal())?;
+
+    match reply_rx.await.map_err(|_| RpcError::internal())? {
+        Ok(response) if response.get_is_success() => to_value(true),
+        Ok(response) => Err(RpcError::new(
+            SERVER_ERROR,
+            format!("service peer failed to push: {}", response.get_info()),
+        )),
+        Err(LightPushError::NotEnabled) => {
+            Err(RpcError::new(SERVER_ERROR, "lightpush is not enabled"))
+        }
+        Err(LightPushError::NoPeer) => Err(RpcError::new(
+            SERVER_ERROR,
+            "no lightpush service peer, start with --lightpushnode",
+        )),
+        Err(LightPushError::Failed(e)) => Err(RpcError::new(SERVER_ERROR, e)),
+    }
+}
+
+type StoreQueryParams = (
+    Option<String>,
+    Option<Vec<ContentFilterSerDe>>,
+    Option<i64>,
+    Option<i64>,
+    Option<PagingOptionsSerDe>,
+);
+
+// Queries the store peer, or the local archive if there is none
+async fn get_store_v1_messages(
+    (pubsub_topic, content_filters, start_time, end_time, paging_options): StoreQueryParams,
+    state: &RpcState,
+) -> RpcResult {
+    let mut query = HistoryQuery::new();
+    if let Some(pubsub_topic) = pubsub_topic {
+        query.set_pubsub_topic(pubsub_topic);
+    }
+    query.set_content_filters(RepeatedField::from_vec(
+        content_filters
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| {
+                let mut filter = ContentFilter::new();
+                filter.set_contentTopic(f.contentTopic);
+                filter
+            })
+            .collect(),
+    ));
+    if let Some(start_time) = start_time {
+        query.set_start_time(start_time);
+    }
+    if let Some(end_time) = end_time {
+        query.set_end_time(end_time);
+    }
+    if let Some(paging_options) = paging_options {
+        let paging_info = query.mut_paging_info();
+        paging_info.set_page_size(paging_options.pageSize);
+        paging_info.set_direction(match paging_options.forward {
+            true => PagingInfo_Direction::FORWARD,
+            false => PagingInfo_Direction::BACKWARD,
+        });
+        if let Some(cursor) = paging_options.cursor {
+            let mut index = Index::new();
+            index.set_digest(cursor.digest);
+            index.set_receiver_time(cursor.receiverTime);
+            index.set_sender_time(cursor.senderTime);
+            index.set_pubsub_topic(cursor.pubsubTopic);
+            paging_info.set_cursor(index);
+        }
+    }
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request = StoreQueryRequest {
+        request_id: format!("{:016x}", rand::random::<u64>()),
+        query,
+        peer: None,
+        reply_tx,
+    };
+    state
+        .store_query_tx
+        .send(request)
+        .await
+        .map_err(|_| RpcError::internal())?;
+    let response = match reply_rx.await.map_err(|_| RpcError::internal())? {
+        Some(Ok(response)) => response,
+        Some(Err(e)) => return Err(RpcError::new(SERVER_ERROR, e)),
+        None => return Err(RpcError::new(SERVER_ERROR, "store is not enabled")),
+    };
+    store_response(response)
+}
+
+fn store_response(response: HistoryResponse) -> RpcResult {
+    let error = match response.get_error() {
+        HistoryResponse_Error::NONE => None,
+        HistoryResponse_Error::INVALID_CURSOR => Some("cursor is unknown"),
+        HistoryResponse_Error::BAD_REQUEST => Some("query is malformed"),
+        HistoryResponse_Error::TOO_MANY_REQUESTS => Some("query was rate limited"),
+    };
+    if let Some(error) = error {
+        return Err(RpcError::new(SERVER_ERROR, error));
+    }
+
+    let paging_info = response.get_paging_info();
+    let paging_options = paging_info.has_cursor().then(|| {
+        let index = paging_info.get_cursor();
+        PagingOptionsSerDe {
+            pageSize: paging_info.get_page_size(),
+            cursor: Some(IndexSerDe {
+                digest: index.get_digest().to_vec(),
+                receiverTime: index.get_receiver_time(),
+                senderTime: index.get_sender_time(),
+                pubsubTopic: index.get_pubsub_topic().to_string(),
+            }),
+            forward: paging_info.get_direction() == PagingInfo_Direction::FORWARD,
+        }
+    });
+    to_value(StoreResponseSerDe {
+        messages: response
+            .get_messages()
+            .iter()
+            .map(WakuMessageSerDe::from)
+            .collect(),
+        pagingOptions: paging_options,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        relay_cache::RelayCache,
+        rest_api::{AdminRequest, PeerInfo},
+        rpc_api::{
+            handle, route, RpcState, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND,
+            SERVER_ERROR,
+        },
+    };
+    use libp2p::{Multiaddr, PeerId};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, Mutex};
+    use waku_protocol::{
+        waku_lightpush::PushResponse,
+        waku_message::WakuMessage,
+        waku_store::{HistoryResponse, Index, PagingInfo_Direction},
+    };
+    use warp::http::StatusCode;
+
+    // Channels of which the tests do not read the other end
+    fn test_state() -> RpcState {
+        RpcState {
+            relay_cache: Arc::new(Mutex::new(RelayCache::new(10))),
+            relay_publish_tx: mpsc::channel(1).0,
+            relay_subscribe_tx: mpsc::channel(1).0,
+            relay_unsubscribe_tx: mpsc::channel(1).0,
+            admin_tx: mpsc::channel(1).0,
+            lightpush_tx: mpsc::channel(1).0,
+            store_query_tx: mpsc::channel(1).0,
+        }
+    }
+
+    fn request(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params })
+    }
+
+    fn error_code(response: Option<Value>) -> Value {
+        response.unwrap()["error"]["code"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_rpc_relay() {
+        let (relay_publish_tx, mut relay_publish_rx) = mpsc::channel(1);
+        let state = RpcState {
+            relay_publish_tx,
+            ..test_state()
+        };
+
+        let post = |payload: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "post_waku_v2_relay_v1_message",
+                "params": ["/waku/2/default-waku/proto", { "payload": payload, "contentTopic": "/toy/1/chat/proto" }],
+            })
+        };
+        let response = handle(post("0x0a0b"), &state).await.unwrap();
+        assert_eq!(response["result"], json!(true));
+        let (message, topic) = relay_publish_rx.recv().await.unwrap();
+        assert_eq!(message.get_payload(), [10, 11]);
+        assert_eq!(topic, "/waku/2/default-waku/proto");
+        assert_eq!(error_code(handle(post("zz"), &state).await), INVALID_PARAMS);
+
+        // Messages without a content topic are not published
+        let malformed = request(
+            "post_waku_v2_relay_v1_message",
+            json!(["/waku/2/default-waku/proto", { "payload": "0x0a0b" }]),
+        );
+        assert_eq!(error_code(handle(malformed, &state).await), INVALID_PARAMS);
+        assert!(relay_publish_rx.try_recv().is_err());
+
+        // Messages are read as arrays of bytes, once subscribed
+        let get = json!({
+            "jsonrpc": "2.0",
+            "id": "get",
+            "method": "get_waku_v2_relay_v1_messages",
+            "params": ["/waku/2/default-waku/proto"],
+        });
+        assert!(handle(get.clone(), &state).await.unwrap()["error"].is_object());
+        state
+            .relay_cache
+            .lock()
+            .await
+            .subscribe("/waku/2/default-waku/proto");
+        state
+            .relay_cache
+            .lock()
+            .await
+            .push("/waku/2/default-waku/proto", message);
+        let response = handle(get, &state).await.unwrap();
+        assert_eq!(response["id"], json!("get"));
+        assert_eq!(response["result"][0]["payload"], json!([10, 11]));
+
+        let unknown =
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "get_waku_v2_filter_v1_messages" });
+        assert_eq!(error_code(handle(unknown, &state).await), METHOD_NOT_FOUND);
+        let invalid = json!({ "id": 3, "method": "get_waku_v2_debug_v1_version" });
+        assert_eq!(error_code(handle(invalid, &state).await), INVALID_REQUEST);
+        let notification = json!({ "jsonrpc": "2.0", "method": "get_waku_v2_debug_v1_version" });
+        assert!(handle(notification, &state).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rpc_store() {
+        let (store_query_tx, mut store_query_rx) = mpsc::channel(1);
+        let state = RpcState {
+            store_query_tx,
+            ..test_state()
+        };
+        tokio::spawn(async move {
+            let request = store_query_rx.recv().await.unwrap();
+            let query = request.query;
+            assert_eq!("/waku/2/default-waku/proto", query.get_pubsub_topic());
+            assert_eq!(
+                "/toy/1/chat/proto",
+                query.get_content_filters()[0].get_contentTopic()
+            );
+            assert_eq!(2, query.get_paging_info().get_page_size());
+            assert_eq!(
+                PagingInfo_Direction::FORWARD,
+                query.get_paging_info().get_direction()
+            );
+            let mut message = WakuMessage::new();
+            message.set_payload(vec![1]);
+            message.set_content_topic("/toy/1/chat/proto".to_string());
+            let mut cursor = Index::new();
+            cursor.set_digest(vec![1, 2]);
+            cursor.set_pubsub_topic("/waku/2/default-waku/proto".to_string());
+            let mut response = HistoryResponse::new();
+            response.mut_messages().push(message);
+            response.mut_paging_info().set_page_size(2);
+            response.mut_paging_info().set_cursor(cursor);
+            response
+                .mut_paging_info()
+                .set_direction(PagingInfo_Direction::FORWARD);
+            let _ = request.reply_tx.send(Some(Ok(response)));
+
+            // As if store were not enabled
+            let request = store_query_rx.recv().await.unwrap();
+            let _ = request.reply_tx.send(None);
+        });
+
+        let query = request(
+            "get_waku_v2_store_v1_messages",
+            json!([
+                "/waku/2/default-waku/proto",
+                [{ "contentTopic": "/toy/1/chat/proto" }],
+                null,
+                null,
+                { "pageSize": 2, "forward": true },
+            ]),
+        );
+        let response = handle(query, &state).await.unwrap();
+        let result = &response["result"];
+        assert_eq!(json!([1]), result["messages"][0]["payload"]);
+        assert_eq!(json!([1, 2]), result["pagingOptions"]["cursor"]["digest"]);
+        assert_eq!(json!(true), result["pagingOptions"]["forward"]);
+
+        let query = request("get_waku_v2_store_v1_messages", json!([]));
+        assert_eq!(error_code(handle(query, &state).await), SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_rpc_lightpush() {
+        let (lightpush_tx, mut lightpush_rx) = mpsc::channel(1);
+        let state = RpcState {
+            lightpush_tx,
+            ..test_state()
+        };
+        tokio::spawn(async move {
+            for is_success in [true, false] {
+                let request = lightpush_rx.recv().await.unwrap();
+                assert_eq!("/waku/2/default-waku/proto", request.pubsub_topic);
+                assert_eq!([10, 11], request.message.get_payload());
+                let mut response = PushResponse::new();
+                response.set_is_success(is_success);
+                let _ = request.reply_tx.send(Ok(response));
+            }
+        });
+
+        let push = |message: Value| {
+            request(
+                "post_waku_v2_lightpush_v1_message",
+                json!(["/waku/2/default-waku/proto", message]),
+            )
+        };
+        let message = json!({ "payload": "0a0b", "contentTopic": "/toy/1/chat/proto" });
+        let response = handle(push(message.clone()), &state).await.unwrap();
+        assert_eq!(json!(true), response["result"]);
+        // The service peer refuses the second push
+        assert_eq!(
+            error_code(handle(push(message), &state).await),
+            SERVER_ERROR
+        );
+        let malformed = json!({ "payload": "0a0b" });
+        assert_eq!(
+            error_code(handle(push(malformed), &state).await),
+            INVALID_PARAMS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rpc_admin() {
+        let (admin_tx, mut admin_rx) = mpsc::channel(1);
+        let state = RpcState {
+            admin_tx,
+            ..test_state()
+        };
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/60000".parse().unwrap();
+        let peer_address = address.clone();
+        tokio::spawn(async move {
+            match admin_rx.recv().await.unwrap() {
+                AdminRequest::Peers(reply_tx) => {
+                    let info = PeerInfo {
+                        addresses: vec![peer_address.clone()],
+                        protocols: vec![
+                            "/ipfs/id/1.0.0".to_string(),
+                            "/vac/waku/relay/2.0.0".to_string(),
+                        ],
+                        agent_version: None,
+                    };
+                    let _ = reply_tx.send(vec![(peer_id, info)]);
+                }
+                _ => panic!("expected a peers request"),
+            }
+            match admin_rx.recv().await.unwrap() {
+                AdminRequest::Dial(addresses, reply_tx) => {
+                    assert_eq!(vec![peer_address], addresses);
+                    let _ = reply_tx.send(Ok(()));
+                }
+                _ => panic!("expected a dial request"),
+            }
+        });
+
+        // Only the Waku protocols of the peer are listed
+        let response = handle(request("get_waku_v2_admin_v1_peers", json!([])), &state)
+            .await
+            .unwrap();
+        assert_eq!(
+            json!([{
+                "multiaddr": format!("{}/p2p/{}", address, peer_id),
+                "protocol": "/vac/waku/relay/2.0.0",
+                "connected": true,
+            }]),
+            response["result"]
+        );
+
+        let dial = |address: &str| request("post_waku_v2_admin_v1_peers", json!([[address]]));
+        let response = handle(dial(&address.to_string()), &state).await.unwrap();
+        assert_eq!(json!(true), response["result"]);
+        assert_eq!(
+            error_code(handle(dial("not a multiaddr"), &state).await),
+            INVALID_PARAMS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rpc_auth() {
+        let route = route(test_state(), Some("secret".to_string()));
+        let version =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "get_waku_v2_debug_v1_version" });
+        let request = |authorization: Option<&str>| {
+            let request = warp::test::request()
+                .method("POST")
+                .path("/")
+                .json(&version);
+            match authorization {
+                Some(authorization) => request.header("authorization", authorization),
+                None => request,
+            }
+        };
+
+        for authorization in [None, Some("Bearer wrong")] {
+            let response = request(authorization).reply(&route).await;
+            assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+            assert_eq!("Bearer", response.headers()["www-authenticate"]);
+        }
+        let response = request(Some("Bearer secret")).reply(&route).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let response: Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(response["result"].is_string());
+    }
+}
@@ -0,0 +1,74 @@
+// Store query latency against store size. Run with `cargo bench -p waku-protocol`.
+use std::time::{Duration, Instant};
+use waku_protocol::{
+    waku_message::WakuMessage,
+    waku_store::{
+        message_queue::{IndexedWakuMessage, WakuMessageQueue},
+        network_behaviour::compute_index,
+        ContentFilter, HistoryQuery, PagingInfo, PagingInfo_Direction,
+    },
+};
+
+const STORE_SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+const CONTENT_TOPICS: usize = 100;
+const ITERATIONS: u32 = 1_000;
+
+fn fill_store(size: usize) -> (WakuMessageQueue, Vec<IndexedWakuMessage>) {
+    let mut queue = WakuMessageQueue::new(size);
+    let mut messages = Vec::with_capacity(size);
+    for i in 0..size {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(i.to_le_bytes().to_vec());
+        msg.set_content_topic(format!("/bench/1/topic-{}/proto", i % CONTENT_TOPICS));
+        msg.set_timestamp(i as i64 + 1);
+        let indexed =
+            IndexedWakuMessage::new(msg.clone(), compute_index("pubsub", msg), "pubsub".into());
+        queue.push(indexed.clone()).unwrap();
+        messages.push(indexed);
+    }
+    (queue, messages)
+}
+
+fn history_query(content_topics: &[usize], cursor: Option<&IndexedWakuMessage>) -> HistoryQuery {
+    let mut query = HistoryQuery::new();
+    query.set_pubsub_topic("pubsub".to_string());
+    for t in content_topics {
+        let mut filter = ContentFilter::new();
+        filter.set_contentTopic(format!("/bench/1/topic-{}/proto", t));
+        query.mut_content_filters().push(filter);
+    }
+    let mut paging_info = PagingInfo::new();
+    paging_info.set_page_size(20);
+    paging_info.set_direction(PagingInfo_Direction::FORWARD);
+    if let Some(cursor) = cursor {
+        paging_info.set_cursor(cursor.index().clone());
+    }
+    query.set_paging_info(paging_info);
+    query
+}
+
+fn time(queue: &WakuMessageQueue, query: &HistoryQuery) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(!queue.query(query).get_messages().is_empty());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    println!(
+        "{:>10} {:>14} {:>14} {:>14}",
+        "messages", "whole topic", "cursor", "3 topics"
+    );
+    for size in STORE_SIZES {
+        let (queue, messages) = fill_store(size);
+        let middle = &messages[size / 2];
+        println!(
+            "{:>10} {:>14?} {:>14?} {:>14?}",
+            size,
+            time(&queue, &history_query(&[], None)),
+            time(&queue, &history_query(&[], Some(middle))),
+            time(&queue, &history_query(&[1, 2, 3], Some(middle))),
+        );
+    }
+}
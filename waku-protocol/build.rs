@@ -1,4 +1,3 @@
-extern crate protoc_rust;
 use std::env;
 
 fn main() {
@@ -21,15 +20,27 @@ fn main() {
         "waku_lightpush.pb.proto".to_string(),
     ]
     .join("/");
+    let waku_store_sync_proto_path = [
+        protos_path.display().to_string(),
+        "waku_store_sync.pb.proto".to_string(),
+    ]
+    .join("/");
+    let waku_store_v3_proto_path = [
+        protos_path.display().to_string(),
+        "waku_store_v3.pb.proto".to_string(),
+    ]
+    .join("/");
 
-    protoc_rust::Codegen::new()
+    protobuf_codegen_pure::Codegen::new()
         .out_dir(protos_path.display().to_string())
         .inputs(&[
             waku_message_proto_path,
             waku_store_proto_path,
             waku_lightpush_proto_path,
+            waku_store_sync_proto_path,
+            waku_store_v3_proto_path,
         ])
         .include(protos_path.display().to_string())
         .run()
-        .expect("Running protoc failed.");
+        .expect("Running protobuf codegen failed.");
 }
@@ -0,0 +1,39 @@
+use futures::prelude::*;
+use std::io;
+
+// Reads a varint length prefix followed by that many bytes, rejecting anything larger than max_size.
+pub(crate) async fn read_length_prefixed<T>(io: &mut T, max_size: usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let len = unsigned_varint::aio::read_usize(&mut *io)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "message of {} bytes exceeds limit of {} bytes",
+                len, max_size
+            ),
+        ));
+    }
+
+    let mut buf = vec![0; len];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+// Writes data prefixed with its length as a varint, then flushes the stream.
+pub(crate) async fn write_length_prefixed<T>(io: &mut T, data: impl AsRef<[u8]>) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    let data = data.as_ref();
+    let mut len_buf = unsigned_varint::encode::usize_buffer();
+    io.write_all(unsigned_varint::encode::usize(data.len(), &mut len_buf))
+        .await?;
+    io.write_all(data).await?;
+    io.flush().await?;
+    Ok(())
+}
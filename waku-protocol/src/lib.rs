@@ -1,5 +1,8 @@
+mod length_prefixed;
 mod pb;
+pub mod rate_limit;
 pub mod waku_lightpush;
 pub mod waku_message;
 pub mod waku_relay;
+pub mod waku_rln_relay;
 pub mod waku_store;
@@ -1,3 +1,8 @@
+// Generated by build.rs from the .proto files in this directory.
+#![allow(mismatched_lifetime_syntaxes, renamed_and_removed_lints, unused_parens)]
+
 pub mod waku_lightpush_pb;
 pub mod waku_message_pb;
 pub mod waku_store_pb;
+pub mod waku_store_sync_pb;
+pub mod waku_store_v3_pb;
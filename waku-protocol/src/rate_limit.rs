@@ -0,0 +1,191 @@
+use libp2p::PeerId;
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family},
+    registry::Registry,
+};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// Buckets of peers idle for a whole period are full again, so they are dropped past this size
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+// Allows a volume of requests per period, refilled continuously.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub volume: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub fn new(volume: u32, period: Duration) -> Self {
+        RateLimit { volume, period }
+    }
+}
+
+// Parses <volume>/<period>, with the period in s, m or h, e.g. 100/1s or 30/1m
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (volume, period) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected <volume>/<period>, got {}", s))?;
+        let volume = volume.parse::<u32>().map_err(|e| e.to_string())?;
+        let unit = match period.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            _ => return Err(format!("period must end in s, m or h, got {}", period)),
+        };
+        let amount = period[..period.len() - 1]
+            .parse::<u64>()
+            .map_err(|e| e.to_string())?;
+        if volume == 0 || amount == 0 {
+            return Err("volume and period must be positive".to_string());
+        }
+        Ok(RateLimit::new(volume, Duration::from_secs(amount * unit)))
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+// One token bucket per peer, all sharing the same limit.
+pub struct PeerRateLimiter {
+    limit: Option<RateLimit>,
+    buckets: HashMap<PeerId, TokenBucket>,
+}
+
+impl PeerRateLimiter {
+    // None lets every request through
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        PeerRateLimiter {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    // Takes a token from the peer's bucket, returning false if it is empty
+    pub fn try_acquire(&mut self, peer_id: &PeerId) -> bool {
+        self.try_acquire_at(peer_id, Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, peer_id: &PeerId, now: Instant) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let volume = limit.volume as f64;
+
+        if self.buckets.len() > MAX_IDLE_BUCKETS {
+            self.buckets
+                .retain(|_, b| now.duration_since(b.last_refill) < limit.period);
+        }
+
+        let bucket = self.buckets.entry(*peer_id).or_insert(TokenBucket {
+            tokens: volume,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens
+            + volume * elapsed.as_secs_f64() / limit.period.as_secs_f64())
+        .min(volume);
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    protocol: String,
+    outcome: String,
+}
+
+// Counts requests served and rejected by a request-response protocol.
+#[derive(Clone, Debug, Default)]
+pub struct RequestMetrics {
+    requests: Family<RequestLabels, Counter>,
+}
+
+impl RequestMetrics {
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "waku_service_requests",
+            "Requests received by Waku request-response protocols",
+            self.requests.clone(),
+        );
+    }
+
+    pub fn inc_served(&self, protocol: &str) {
+        self.inc(protocol, "served");
+    }
+
+    pub fn inc_rate_limited(&self, protocol: &str) {
+        self.inc(protocol, "rate_limited");
+    }
+
+    // The response couldn't be sent, e.g. because the peer disconnected or timed out
+    pub fn inc_response_failed(&self, protocol: &str) {
+        self.inc(protocol, "response_failed");
+    }
+
+    pub fn get(&self, protocol: &str, outcome: &str) -> u64 {
+        self.requests
+            .get_or_create(&RequestLabels {
+                protocol: protocol.to_string(),
+                outcome: outcome.to_string(),
+            })
+            .get()
+    }
+
+    fn inc(&self, protocol: &str, outcome: &str) {
+        self.requests
+            .get_or_create(&RequestLabels {
+                protocol: protocol.to_string(),
+                outcome: outcome.to_string(),
+            })
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::{PeerRateLimiter, RateLimit};
+    use libp2p::PeerId;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_peer_rate_limiter() {
+        let limit: RateLimit = "2/1s".parse().unwrap();
+        assert_eq!(RateLimit::new(2, Duration::from_secs(1)), limit);
+        assert!("2/1".parse::<RateLimit>().is_err());
+        assert!("0/1s".parse::<RateLimit>().is_err());
+
+        let mut limiter = PeerRateLimiter::new(Some(limit));
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire_at(&alice, now));
+        assert!(limiter.try_acquire_at(&alice, now));
+        assert!(!limiter.try_acquire_at(&alice, now));
+        // Buckets are per peer
+        assert!(limiter.try_acquire_at(&bob, now));
+        // Half a period refills half the volume
+        assert!(limiter.try_acquire_at(&alice, now + Duration::from_millis(500)));
+        assert!(!limiter.try_acquire_at(&alice, now + Duration::from_millis(500)));
+
+        let mut unlimited = PeerRateLimiter::new(None);
+        assert!((0..100).all(|_| unlimited.try_acquire_at(&alice, now)));
+    }
+}
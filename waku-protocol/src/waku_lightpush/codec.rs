@@ -1,11 +1,9 @@
+use crate::length_prefixed::{read_length_prefixed, write_length_prefixed};
 use crate::pb::waku_lightpush_pb::PushRPC;
 use crate::waku_message::MAX_MESSAGE_SIZE;
 use async_trait::async_trait;
 use futures::prelude::*;
-use libp2p::{
-    core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName},
-    request_response::RequestResponseCodec,
-};
+use libp2p::request_response::Codec;
 use protobuf::Message;
 use std::io;
 
@@ -17,15 +15,15 @@ pub struct WakuLightPushProtocol();
 
 const LIGHTPUSH_PROTOCOL_ID: &str = "/vac/waku/lightpush/2.0.0-beta1";
 
-impl ProtocolName for WakuLightPushProtocol {
-    fn protocol_name(&self) -> &[u8] {
-        LIGHTPUSH_PROTOCOL_ID.as_bytes()
+impl AsRef<str> for WakuLightPushProtocol {
+    fn as_ref(&self) -> &str {
+        LIGHTPUSH_PROTOCOL_ID
     }
 }
 const MAX_LIGHTPUSH_RPC_SIZE: usize = MAX_MESSAGE_SIZE + 64 * 1024; // We add a 64kB safety buffer for protocol overhead
 
 #[async_trait]
-impl RequestResponseCodec for WakuLightPushCodec {
+impl Codec for WakuLightPushCodec {
     type Protocol = WakuLightPushProtocol;
     type Request = PushRPC;
     type Response = PushRPC;
@@ -35,8 +33,8 @@ impl RequestResponseCodec for WakuLightPushCodec {
         T: AsyncRead + Unpin + Send,
     {
         let rpc_bytes = read_length_prefixed(io, MAX_LIGHTPUSH_RPC_SIZE).await?;
-        let rpc: PushRPC = protobuf::Message::parse_from_bytes(&rpc_bytes).unwrap();
-        Ok(rpc)
+        PushRPC::parse_from_bytes(&rpc_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     async fn read_response<T>(
@@ -48,8 +46,8 @@ impl RequestResponseCodec for WakuLightPushCodec {
         T: AsyncRead + Unpin + Send,
     {
         let rpc_bytes = read_length_prefixed(io, MAX_LIGHTPUSH_RPC_SIZE).await?;
-        let rpc: PushRPC = protobuf::Message::parse_from_bytes(&rpc_bytes).unwrap();
-        Ok(rpc)
+        PushRPC::parse_from_bytes(&rpc_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     async fn write_request<T>(
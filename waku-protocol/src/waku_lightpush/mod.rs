@@ -1,2 +1,4 @@
 mod codec;
 pub mod network_behaviour;
+
+pub use crate::pb::waku_lightpush_pb::PushResponse;
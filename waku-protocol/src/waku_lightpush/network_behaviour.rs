@@ -3,46 +3,59 @@ use crate::{
         waku_lightpush_pb::{PushRPC, PushRequest, PushResponse},
         waku_message_pb::WakuMessage,
     },
+    rate_limit::{PeerRateLimiter, RateLimit, RequestMetrics},
     waku_lightpush::codec::{WakuLightPushCodec, WakuLightPushProtocol},
-    waku_relay::network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
+    waku_relay::{
+        config::WakuRelayConfig,
+        network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
+        validation::WakuMessageValidator,
+    },
+    waku_rln_relay::rln::WakuRlnRelay,
 };
 use libp2p::{
-    gossipsub::{
-        error::{PublishError, SubscriptionError},
-        GossipsubEvent, MessageId,
-    },
-    request_response::{
-        ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
-        RequestResponseMessage,
-    },
+    core::{transport::PortUse, Endpoint},
+    gossipsub::{self, MessageId, PublishError, SubscriptionError},
+    request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel},
     swarm::{
-        NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters,
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
     },
-    Multiaddr, NetworkBehaviour, PeerId,
+    Multiaddr, PeerId,
 };
 use log::info;
+use prometheus_client::registry::Registry;
 use std::{
+    collections::VecDeque,
     iter::once,
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
 
+// Label of this protocol in the request metrics
+const PROTOCOL_LABEL: &str = "lightpush";
+// Info of the response to rate limited requests, as sent by nwaku
+const TOO_MANY_REQUESTS: &str = "TOO_MANY_REQUESTS";
+
+// Sub-behaviours of WakuLightPushBehaviour. Their events are intercepted and handled by
+// WakuLightPushBehaviour::poll before anything is reported to the Swarm.
 #[derive(NetworkBehaviour)]
-#[behaviour(
-    event_process = true,
-    out_event = "WakuLightPushEvent",
-    poll_method = "poll"
-)]
-pub struct WakuLightPushBehaviour {
+#[behaviour(to_swarm = "WakuLightPushEvent")]
+pub struct WakuLightPushInner {
     relay: WakuRelayBehaviour,
-    req_res: RequestResponse<WakuLightPushCodec>,
-    #[behaviour(ignore)]
-    events: Vec<WakuLightPushEvent>,
+    req_res: request_response::Behaviour<WakuLightPushCodec>,
+}
+
+pub struct WakuLightPushBehaviour {
+    inner: WakuLightPushInner,
+    rate_limiter: PeerRateLimiter,
+    metrics: RequestMetrics,
+    events: VecDeque<WakuLightPushEvent>,
+    waker: Option<Waker>,
 }
 
 #[derive(Debug)]
 pub enum WakuLightPushEvent {
     WakuRelayBehaviour(WakuRelayEvent),
-    RequestResponseBehaviour(RequestResponseEvent<PushRPC, PushRPC>),
+    RequestResponseBehaviour(request_response::Event<PushRPC, PushRPC>),
 }
 
 impl From<WakuRelayEvent> for WakuLightPushEvent {
@@ -51,127 +64,181 @@ impl From<WakuRelayEvent> for WakuLightPushEvent {
     }
 }
 
-impl From<RequestResponseEvent<PushRPC, PushRPC>> for WakuLightPushEvent {
-    fn from(event: RequestResponseEvent<PushRPC, PushRPC>) -> Self {
+impl From<request_response::Event<PushRPC, PushRPC>> for WakuLightPushEvent {
+    fn from(event: request_response::Event<PushRPC, PushRPC>) -> Self {
         Self::RequestResponseBehaviour(event)
     }
 }
 
-impl NetworkBehaviourEventProcess<WakuRelayEvent> for WakuLightPushBehaviour {
-    fn inject_event(&mut self, event: WakuRelayEvent) {
-        if let WakuRelayEvent::GossipSub(GossipsubEvent::Message {
-            propagation_source,
-            message_id,
-            message,
-        }) = event
-        {
-            self.events.push(WakuLightPushEvent::WakuRelayBehaviour(
-                WakuRelayEvent::GossipSub(GossipsubEvent::Message {
-                    propagation_source,
-                    message_id,
-                    message,
-                }),
-            ));
-        }
+impl NetworkBehaviour for WakuLightPushBehaviour {
+    type ConnectionHandler = THandler<WakuLightPushInner>;
+    type ToSwarm = WakuLightPushEvent;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
     }
-}
 
-impl NetworkBehaviourEventProcess<RequestResponseEvent<PushRPC, PushRPC>>
-    for WakuLightPushBehaviour
-{
-    fn inject_event(&mut self, event: RequestResponseEvent<PushRPC, PushRPC>) {
-        if let RequestResponseEvent::Message {
-            peer: _,
-            message:
-                RequestResponseMessage::Request {
-                    channel, request, ..
-                },
-        } = event
-        {
-            // when WakuLightPushBehaviour receives a Request,
-            // it forwards the WakuMessage to WakuRelayBehaviour
-
-            let req = request.get_query().clone();
-            let req_id = request.get_request_id();
-            let req_topic = req.get_pubsub_topic();
-            let req_msg = req.get_message().clone();
-            let mut res = PushResponse::new();
-
-            info!("WakuLightPush: pushing request: {:?}", req);
-
-            match self.relay.publish(req_topic, req_msg) {
-                Ok(_) => {
-                    res.set_is_success(true);
-                    // todo: res.set_info() ?
-                }
-                Err(e) => {
-                    res.set_is_success(false);
-                    res.set_info(e.to_string());
-                }
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(ToSwarm::GenerateEvent(event));
             }
 
-            let mut res_rpc = PushRPC::new();
-            res_rpc.set_query(req);
-            res_rpc.set_response(res);
-            res_rpc.set_request_id(req_id.to_string());
-
-            self.req_res.send_response(channel, res_rpc).unwrap();
-        } else if let RequestResponseEvent::Message {
-            peer: _,
-            message: RequestResponseMessage::Response { response, .. },
-        } = event
-        {
-            if response.get_response().get_is_success() {
-                info!(
-                    "WakuLightPush: successful response: {:?}",
-                    response.get_response()
-                );
-            } else {
-                info!(
-                    "WakuLightPush: unsuccessful response: {:?}",
-                    response.get_response()
-                );
+            match self.inner.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => self.handle_event(event),
+                Poll::Ready(action) => return Poll::Ready(action),
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
             }
         }
     }
 }
 
+impl Default for WakuLightPushBehaviour {
+    fn default() -> Self {
+        Self::new(WakuRelayConfig::default())
+    }
+}
+
 impl WakuLightPushBehaviour {
-    pub fn new() -> Self {
+    pub fn new(relay_config: WakuRelayConfig) -> Self {
         Self {
-            relay: WakuRelayBehaviour::new(),
-            req_res: RequestResponse::new(
-                WakuLightPushCodec,
-                once((WakuLightPushProtocol(), ProtocolSupport::Full)),
-                RequestResponseConfig::default(),
-            ),
-            events: Vec::new(),
+            inner: WakuLightPushInner {
+                relay: WakuRelayBehaviour::new(relay_config),
+                req_res: request_response::Behaviour::with_codec(
+                    WakuLightPushCodec,
+                    once((WakuLightPushProtocol(), ProtocolSupport::Full)),
+                    request_response::Config::default(),
+                ),
+            },
+            rate_limiter: PeerRateLimiter::new(None),
+            metrics: RequestMetrics::default(),
+            events: VecDeque::new(),
+            waker: None,
         }
     }
 
-    pub fn publish(&mut self, topic: &str, msg: WakuMessage) -> Result<MessageId, PublishError> {
-        self.relay.publish(topic, msg)
+    pub fn publish(
+        &mut self,
+        topic: &str,
+        msg: WakuMessage,
+    ) -> Result<Option<MessageId>, PublishError> {
+        self.inner.relay.publish(topic, msg)
     }
 
     pub fn subscribe(&mut self, topic: &str) -> Result<bool, SubscriptionError> {
-        self.relay.subscribe(topic)
+        self.inner.relay.subscribe(topic)
     }
 
     pub fn unsubscribe(&mut self, topic: &str) -> Result<bool, PublishError> {
-        self.relay.unsubscribe(topic)
+        self.inner.relay.unsubscribe(topic)
+    }
+
+    pub fn peer_scores(&self) -> Vec<(PeerId, f64)> {
+        self.inner.relay.peer_scores()
+    }
+
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.inner.relay.subscribed_topics()
+    }
+
+    pub fn add_validator(&mut self, validator: impl WakuMessageValidator + 'static) {
+        self.inner.relay.add_validator(validator)
+    }
+
+    pub fn add_topic_validator(
+        &mut self,
+        topic: &str,
+        validator: impl WakuMessageValidator + 'static,
+    ) {
+        self.inner.relay.add_topic_validator(topic, validator)
     }
 
-    pub fn add_lightpush_peer(&mut self, peer_id: PeerId, peer_addr: Multiaddr) {
-        self.req_res.add_address(&peer_id, peer_addr);
+    pub fn enable_rln(&mut self, rln: WakuRlnRelay) {
+        self.inner.relay.enable_rln(rln)
     }
 
+    // Asks a lightpush service peer to publish a message. Its response, or failure, is
+    // reported with a RequestResponseBehaviour event carrying the returned request ID
     pub fn send_request(
         &mut self,
         peer_id: PeerId,
         request_id: String,
         pubsub_topic: String,
         msg: WakuMessage,
-    ) {
+    ) -> OutboundRequestId {
         let mut req = PushRequest::new();
         req.set_pubsub_topic(pubsub_topic);
         req.set_message(msg);
@@ -179,22 +246,145 @@ impl WakuLightPushBehaviour {
         let mut req_rpc = PushRPC::new();
         req_rpc.set_request_id(request_id);
         req_rpc.set_query(req);
-        self.req_res.send_request(&peer_id, req_rpc);
+        self.inner.req_res.send_request(&peer_id, req_rpc)
     }
 
-    fn poll(
-        &mut self,
-        _: &mut Context,
-        _: &mut impl PollParameters,
-    ) -> Poll<
-        NetworkBehaviourAction<
-            <Self as NetworkBehaviour>::OutEvent,
-            <Self as NetworkBehaviour>::ConnectionHandler,
-        >,
-    > {
-        if !self.events.is_empty() {
-            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(self.events.remove(0)));
+    // Limits the requests each peer can make, None to serve every request
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limiter = PeerRateLimiter::new(limit);
+    }
+
+    pub fn register_metrics(&self, registry: &mut Registry) {
+        self.metrics.register(registry);
+    }
+
+    fn push_event(&mut self, event: WakuLightPushEvent) {
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn handle_event(&mut self, event: WakuLightPushEvent) {
+        match event {
+            WakuLightPushEvent::WakuRelayBehaviour(e) => self.handle_relay_event(e),
+            WakuLightPushEvent::RequestResponseBehaviour(e) => {
+                self.handle_request_response_event(e)
+            }
+        }
+    }
+
+    fn handle_relay_event(&mut self, event: WakuRelayEvent) {
+        match event {
+            e @ WakuRelayEvent::GossipSub(gossipsub::Event::Message { .. })
+            | e @ WakuRelayEvent::ProvenMessagePublished { .. } => {
+                self.push_event(WakuLightPushEvent::WakuRelayBehaviour(e))
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request_response_event(&mut self, event: request_response::Event<PushRPC, PushRPC>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        channel, request, ..
+                    },
+            } => match self.rate_limiter.try_acquire(&peer) {
+                true => {
+                    self.metrics.inc_served(PROTOCOL_LABEL);
+                    self.handle_request(channel, request)
+                }
+                false => {
+                    info!("WakuLightPush: rate limiting request from {}", peer);
+                    self.metrics.inc_rate_limited(PROTOCOL_LABEL);
+                    self.reject_request(channel, request)
+                }
+            },
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                if response.get_response().get_is_success() {
+                    info!(
+                        "WakuLightPush: successful response: {:?}",
+                        response.get_response()
+                    );
+                } else {
+                    info!(
+                        "WakuLightPush: unsuccessful response: {:?}",
+                        response.get_response()
+                    );
+                }
+                // Responses are reported so that clients can act on them
+                self.push_event(WakuLightPushEvent::RequestResponseBehaviour(
+                    request_response::Event::Message {
+                        peer,
+                        message: request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                    },
+                ));
+            }
+            event @ request_response::Event::OutboundFailure { .. } => {
+                self.push_event(WakuLightPushEvent::RequestResponseBehaviour(event))
+            }
+            _ => {}
+        }
+    }
+
+    // when WakuLightPushBehaviour receives a Request,
+    // it forwards the WakuMessage to WakuRelayBehaviour
+    fn handle_request(&mut self, channel: ResponseChannel<PushRPC>, request: PushRPC) {
+        let req = request.get_query().clone();
+        let req_id = request.get_request_id();
+        let req_topic = req.get_pubsub_topic();
+        let req_msg = req.get_message().clone();
+        let mut res = PushResponse::new();
+
+        info!("WakuLightPush: pushing request: {:?}", req);
+
+        match self.inner.relay.publish(req_topic, req_msg) {
+            Ok(_) => {
+                res.set_is_success(true);
+                // todo: res.set_info() ?
+            }
+            Err(e) => {
+                res.set_is_success(false);
+                res.set_info(e.to_string());
+            }
+        }
+
+        let mut res_rpc = PushRPC::new();
+        res_rpc.set_query(req);
+        res_rpc.set_response(res);
+        res_rpc.set_request_id(req_id.to_string());
+
+        if self.inner.req_res.send_response(channel, res_rpc).is_err() {
+            info!("WakuLightPush: failed to send push response");
+            self.metrics.inc_response_failed(PROTOCOL_LABEL);
+        }
+    }
+
+    fn reject_request(&mut self, channel: ResponseChannel<PushRPC>, request: PushRPC) {
+        let mut res = PushResponse::new();
+        res.set_is_success(false);
+        res.set_info(TOO_MANY_REQUESTS.to_string());
+
+        let mut res_rpc = PushRPC::new();
+        res_rpc.set_request_id(request.get_request_id().to_string());
+        res_rpc.set_query(request.get_query().clone());
+        res_rpc.set_response(res);
+
+        if self.inner.req_res.send_response(channel, res_rpc).is_err() {
+            info!("WakuLightPush: failed to send rate limit response");
         }
-        Poll::Pending
     }
 }
@@ -1,3 +1,3 @@
-pub use crate::pb::waku_message_pb::WakuMessage;
+pub use crate::pb::waku_message_pb::{RateLimitProof, WakuMessage};
 
 pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024; // In bytes. Corresponds to PubSub default
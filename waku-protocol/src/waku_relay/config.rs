@@ -0,0 +1,134 @@
+use crate::{waku_message::MAX_MESSAGE_SIZE, waku_relay::protected_topics::ProtectedTopic};
+use libp2p::gossipsub::{PeerScoreParams, PeerScoreThresholds, TopicScoreParams};
+use std::{collections::HashSet, time::Duration};
+
+// Defaults follow the gossipsub parameters recommended by 11/WAKU2-RELAY and used by nwaku
+pub const DEFAULT_MESH_N: usize = 6;
+pub const DEFAULT_MESH_N_LOW: usize = 4;
+pub const DEFAULT_MESH_N_HIGH: usize = 8;
+pub const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_HISTORY_LENGTH: usize = 6;
+pub const DEFAULT_MAX_TRANSMIT_SIZE: usize = MAX_MESSAGE_SIZE;
+pub const DEFAULT_FLOOD_PUBLISH: bool = true;
+pub const DEFAULT_DUPLICATE_CACHE_TIME_SECS: u64 = 120;
+pub const DEFAULT_MAX_TIMESTAMP_DRIFT_SECS: u64 = 20;
+pub const DEFAULT_GOSSIP_THRESHOLD: f64 = -100.0;
+pub const DEFAULT_PUBLISH_THRESHOLD: f64 = -1000.0;
+pub const DEFAULT_GRAYLIST_THRESHOLD: f64 = -10000.0;
+
+// Gossipsub tuning used by WakuRelayBehaviour.
+#[derive(Clone, Debug)]
+pub struct WakuRelayConfig {
+    // Target number of peers in the mesh of each topic (D)
+    pub mesh_n: usize,
+    // Minimum number of peers in the mesh before grafting more (D_lo)
+    pub mesh_n_low: usize,
+    // Maximum number of peers in the mesh before pruning (D_hi)
+    pub mesh_n_high: usize,
+    // Time between heartbeats
+    pub heartbeat_interval: Duration,
+    // Number of heartbeats a message stays in the message cache
+    pub history_length: usize,
+    // Maximum size of a gossipsub RPC, in bytes
+    pub max_transmit_size: usize,
+    // Publish own messages to every known peer of the topic, not only to the mesh
+    pub flood_publish: bool,
+    // How long message ids are remembered to filter out duplicates
+    pub duplicate_cache_time: Duration,
+    // Maximum difference between a received message's timestamp and the local clock
+    pub max_timestamp_drift: Duration,
+    // Gossipsub v1.1 peer scoring, or None to run the relay without it
+    pub peer_scoring: Option<WakuRelayScoringConfig>,
+    // Topics that only accept messages signed by a given key
+    pub protected_topics: Vec<ProtectedTopic>,
+}
+
+impl Default for WakuRelayConfig {
+    fn default() -> Self {
+        WakuRelayConfig {
+            mesh_n: DEFAULT_MESH_N,
+            mesh_n_low: DEFAULT_MESH_N_LOW,
+            mesh_n_high: DEFAULT_MESH_N_HIGH,
+            heartbeat_interval: Duration::from_millis(DEFAULT_HEARTBEAT_INTERVAL_MS),
+            history_length: DEFAULT_HISTORY_LENGTH,
+            max_transmit_size: DEFAULT_MAX_TRANSMIT_SIZE,
+            flood_publish: DEFAULT_FLOOD_PUBLISH,
+            duplicate_cache_time: Duration::from_secs(DEFAULT_DUPLICATE_CACHE_TIME_SECS),
+            max_timestamp_drift: Duration::from_secs(DEFAULT_MAX_TIMESTAMP_DRIFT_SECS),
+            peer_scoring: Some(WakuRelayScoringConfig::default()),
+            protected_topics: Vec::new(),
+        }
+    }
+}
+
+// Gossipsub v1.1 peer scoring parameters used by WakuRelayBehaviour.
+#[derive(Clone, Debug)]
+pub struct WakuRelayScoringConfig {
+    // Peer-level parameters. Topic parameters are filled in on subscription
+    pub peer_score_params: PeerScoreParams,
+    // Parameters applied to every pubsub topic the relay subscribes to
+    pub topic_score_params: TopicScoreParams,
+    // Scores below which peers stop receiving gossip, publishes or get graylisted
+    pub thresholds: PeerScoreThresholds,
+}
+
+// Values match the scoring parameters used by nwaku
+impl Default for WakuRelayScoringConfig {
+    fn default() -> Self {
+        WakuRelayScoringConfig {
+            peer_score_params: PeerScoreParams {
+                topics: Default::default(),
+                topic_score_cap: 0.0,
+                app_specific_weight: 1.0,
+                ip_colocation_factor_weight: -50.0,
+                ip_colocation_factor_threshold: 5.0,
+                ip_colocation_factor_whitelist: HashSet::new(),
+                behaviour_penalty_weight: -10.0,
+                behaviour_penalty_threshold: 0.0,
+                behaviour_penalty_decay: 0.986,
+                decay_interval: Duration::from_secs(12),
+                decay_to_zero: 0.01,
+                retain_score: Duration::from_secs(6 * 60 * 60),
+            },
+            topic_score_params: TopicScoreParams {
+                topic_weight: 1.0,
+                time_in_mesh_weight: 0.01,
+                time_in_mesh_quantum: Duration::from_secs(1),
+                time_in_mesh_cap: 10.0,
+                first_message_deliveries_weight: 1.0,
+                first_message_deliveries_decay: 0.5,
+                first_message_deliveries_cap: 10.0,
+                mesh_message_deliveries_weight: 0.0,
+                mesh_message_deliveries_decay: 0.0,
+                mesh_message_deliveries_cap: 0.0,
+                mesh_message_deliveries_threshold: 0.0,
+                mesh_message_deliveries_window: Duration::from_millis(0),
+                mesh_message_deliveries_activation: Duration::from_secs(0),
+                mesh_failure_penalty_weight: 0.0,
+                mesh_failure_penalty_decay: 0.0,
+                invalid_message_deliveries_weight: -10.0,
+                invalid_message_deliveries_decay: 0.5,
+            },
+            thresholds: PeerScoreThresholds {
+                gossip_threshold: DEFAULT_GOSSIP_THRESHOLD,
+                publish_threshold: DEFAULT_PUBLISH_THRESHOLD,
+                graylist_threshold: DEFAULT_GRAYLIST_THRESHOLD,
+                opportunistic_graft_threshold: 0.0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waku_relay::config::WakuRelayScoringConfig;
+
+    #[test]
+    fn test_default_scoring_config_is_valid() {
+        let scoring = WakuRelayScoringConfig::default();
+        assert!(scoring.peer_score_params.validate().is_ok());
+        assert!(scoring.topic_score_params.validate().is_ok());
+        assert!(scoring.thresholds.validate().is_ok());
+    }
+}
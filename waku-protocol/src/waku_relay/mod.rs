@@ -1 +1,4 @@
+pub mod config;
 pub mod network_behaviour;
+pub mod protected_topics;
+pub mod validation;
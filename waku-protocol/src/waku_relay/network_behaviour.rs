@@ -1,61 +1,375 @@
-use crate::pb::waku_message_pb::WakuMessage;
+use crate::{
+    pb::waku_message_pb::{RateLimitProof, WakuMessage},
+    waku_message::MAX_MESSAGE_SIZE,
+    waku_relay::{
+        config::WakuRelayConfig,
+        protected_topics::{sign_message, ProtectedTopicValidator},
+        validation::{
+            decode_waku_message, TimestampValidator, ValidationResult, WakuMessageValidator,
+        },
+    },
+    waku_rln_relay::rln::WakuRlnRelay,
+};
+use futures::{
+    channel::oneshot,
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
+use k256::ecdsa::SigningKey;
 use libp2p::{
+    core::{transport::PortUse, Endpoint},
     gossipsub::{
-        error::{PublishError, SubscriptionError},
-        Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, GossipsubVersion,
-        IdentTopic, MessageAuthenticity, MessageId, ValidationMode,
+        self, IdentTopic, MessageAuthenticity, MessageId, PublishError, SubscriptionError,
+        TopicScoreParams, ValidationMode, Version,
     },
-    NetworkBehaviour, PeerId,
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
 };
+use log::info;
 use protobuf::Message;
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashMap},
     hash::{Hash, Hasher},
+    io,
+    task::{Context, Poll},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 pub const DEFAULT_PUBSUB_TOPIC: &str = "/waku/2/default-waku/proto";
 const RELAY_PROTOCOL_ID: &str = "/vac/waku/relay/2.0.0";
 
-#[derive(NetworkBehaviour)]
-#[behaviour(out_event = "WakuRelayEvent")]
 pub struct WakuRelayBehaviour {
-    gossipsub: Gossipsub,
+    gossipsub: gossipsub::Behaviour,
+    // Applied to every subscribed topic when peer scoring is enabled
+    topic_score_params: Option<TopicScoreParams>,
+    // Run on messages of every pubsub topic, after the timestamp check
+    timestamp_validator: TimestampValidator,
+    validators: Vec<Box<dyn WakuMessageValidator>>,
+    // Run only on messages of the pubsub topic they are registered for
+    topic_validators: HashMap<String, Vec<Box<dyn WakuMessageValidator>>>,
+    // Keys own messages are signed with, for protected topics this node publishes on
+    signing_keys: HashMap<String, SigningKey>,
+    // Proves outgoing and checks incoming messages against the RLN rate limit when enabled
+    rln: Option<WakuRlnRelay>,
+    // Messages waiting for their proof, which is generated on its own thread
+    proofs: FuturesUnordered<BoxFuture<'static, ProvenMessage>>,
 }
 
+type ProvenMessage = (IdentTopic, WakuMessage, io::Result<RateLimitProof>);
+
 #[derive(Debug)]
 pub enum WakuRelayEvent {
-    GossipSub(GossipsubEvent),
+    GossipSub(gossipsub::Event),
+    // A message whose publish waited for its RLN proof, see publish()
+    ProvenMessagePublished {
+        pubsub_topic: String,
+        message: WakuMessage,
+    },
 }
 
-impl From<GossipsubEvent> for WakuRelayEvent {
-    fn from(event: GossipsubEvent) -> Self {
+impl From<gossipsub::Event> for WakuRelayEvent {
+    fn from(event: gossipsub::Event) -> Self {
         Self::GossipSub(event)
     }
 }
 
+impl NetworkBehaviour for WakuRelayBehaviour {
+    type ConnectionHandler = THandler<gossipsub::Behaviour>;
+    type ToSwarm = WakuRelayEvent;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.gossipsub
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.gossipsub.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.gossipsub.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.gossipsub.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.gossipsub.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.gossipsub
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        while let Poll::Ready(Some((topic, mut msg, proof))) = self.proofs.poll_next_unpin(cx) {
+            let result = proof.and_then(|proof| {
+                msg.set_rate_limit_proof(proof);
+                self.publish_now(&topic, msg.clone())
+                    .map_err(io::Error::other)
+            });
+            match result {
+                Ok(message_id) => {
+                    info!("WakuRelay: published proven message {}", message_id);
+                    return Poll::Ready(ToSwarm::GenerateEvent(
+                        WakuRelayEvent::ProvenMessagePublished {
+                            pubsub_topic: topic.to_string(),
+                            message: msg,
+                        },
+                    ));
+                }
+                Err(e) => info!("WakuRelay: failed to publish proven message: {}", e),
+            }
+        }
+
+        loop {
+            match self.gossipsub.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(gossipsub::Event::Message {
+                    propagation_source,
+                    message_id,
+                    message,
+                })) => {
+                    let result = self.validate(message.topic.as_str(), &message.data);
+                    if let Err(e) = self.gossipsub.report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        result.into(),
+                    ) {
+                        info!("WakuRelay: failed to report validation result: {}", e);
+                    }
+
+                    if result == ValidationResult::Accept {
+                        return Poll::Ready(ToSwarm::GenerateEvent(WakuRelayEvent::GossipSub(
+                            gossipsub::Event::Message {
+                                propagation_source,
+                                message_id,
+                                message,
+                            },
+                        )));
+                    }
+                    info!(
+                        "WakuRelay: dropping message {} from {}: {:?}",
+                        message_id, propagation_source, result
+                    );
+                }
+                Poll::Ready(action) => return Poll::Ready(action.map_out(WakuRelayEvent::from)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Default for WakuRelayBehaviour {
+    fn default() -> Self {
+        Self::new(WakuRelayConfig::default())
+    }
+}
+
 impl WakuRelayBehaviour {
-    pub fn new() -> Self {
-        let message_id_fn = |message: &GossipsubMessage| {
+    pub fn new(config: WakuRelayConfig) -> Self {
+        let message_id_fn = |message: &gossipsub::Message| {
             let mut s = DefaultHasher::new();
             message.data.hash(&mut s);
             MessageId::from(s.finish().to_string())
         };
 
-        let gossipsub_config = GossipsubConfigBuilder::default()
-            .protocol_id(RELAY_PROTOCOL_ID, GossipsubVersion::V1_1)
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .protocol_id(RELAY_PROTOCOL_ID, Version::V1_1)
             .validation_mode(ValidationMode::Anonymous) // StrictNoSign
+            .validate_messages()
             .message_id_fn(message_id_fn)
+            .mesh_n(config.mesh_n)
+            .mesh_n_low(config.mesh_n_low)
+            .mesh_n_high(config.mesh_n_high)
+            .heartbeat_interval(config.heartbeat_interval)
+            .history_length(config.history_length)
+            .max_transmit_size(config.max_transmit_size)
+            .flood_publish(config.flood_publish)
+            .duplicate_cache_time(config.duplicate_cache_time)
             .build()
             .expect("Valid config");
 
-        let gossipsub: Gossipsub = Gossipsub::new(MessageAuthenticity::Anonymous, gossipsub_config)
-            .expect("Correct configuration");
+        let mut gossipsub =
+            gossipsub::Behaviour::new(MessageAuthenticity::Anonymous, gossipsub_config)
+                .expect("Correct configuration");
+
+        let topic_score_params = match config.peer_scoring {
+            Some(scoring) => {
+                gossipsub
+                    .with_peer_score(scoring.peer_score_params, scoring.thresholds)
+                    .expect("Valid peer score parameters");
+                Some(scoring.topic_score_params)
+            }
+            None => None,
+        };
+
+        let mut relay = WakuRelayBehaviour {
+            gossipsub,
+            topic_score_params,
+            timestamp_validator: TimestampValidator::new(config.max_timestamp_drift),
+            validators: Vec::new(),
+            topic_validators: HashMap::new(),
+            signing_keys: HashMap::new(),
+            rln: None,
+            proofs: FuturesUnordered::new(),
+        };
+        for protected in config.protected_topics {
+            relay.add_topic_validator(
+                &protected.pubsub_topic,
+                ProtectedTopicValidator::new(protected.public_key),
+            );
+            if let Some(signing_key) = protected.signing_key {
+                relay
+                    .signing_keys
+                    .insert(protected.pubsub_topic, signing_key);
+            }
+        }
+        relay
+    }
+
+    // Requires a valid rate limit proof on every relayed message and attaches one to every
+    // published message
+    pub fn enable_rln(&mut self, rln: WakuRlnRelay) {
+        self.rln = Some(rln);
+    }
+
+    pub fn rln(&self) -> Option<&WakuRlnRelay> {
+        self.rln.as_ref()
+    }
+
+    // Registers a validator that runs on messages of every pubsub topic
+    pub fn add_validator(&mut self, validator: impl WakuMessageValidator + 'static) {
+        self.validators.push(Box::new(validator));
+    }
+
+    // Registers a validator that runs only on messages of the given pubsub topic
+    pub fn add_topic_validator(
+        &mut self,
+        topic: &str,
+        validator: impl WakuMessageValidator + 'static,
+    ) {
+        self.topic_validators
+            .entry(topic.to_string())
+            .or_default()
+            .push(Box::new(validator));
+    }
+
+    // Runs the validator pipeline, stopping at the first validator that does not accept
+    fn validate(&mut self, topic: &str, data: &[u8]) -> ValidationResult {
+        let waku_message = match decode_waku_message(data) {
+            Ok(m) => m,
+            Err(result) => return result,
+        };
+        let result = self.timestamp_validator.validate(topic, &waku_message);
+        if result != ValidationResult::Accept {
+            return result;
+        }
+        let result = self.run_validators(topic, &waku_message);
+        if result != ValidationResult::Accept {
+            return result;
+        }
+
+        // Checked last, as verifying the proof is the most expensive step
+        match self.rln.as_mut() {
+            Some(rln) => rln.validate_message(&waku_message),
+            None => ValidationResult::Accept,
+        }
+    }
 
-        WakuRelayBehaviour { gossipsub }
+    // Runs the validator pipeline on a message a store peer archived, rather than relayed.
+    // It may be arbitrarily old, so its timestamp and RLN epoch aren't checked for freshness
+    pub fn validate_archived(&mut self, topic: &str, message: &WakuMessage) -> ValidationResult {
+        if message.compute_size() as usize > MAX_MESSAGE_SIZE {
+            return ValidationResult::Reject;
+        }
+        let result = self.timestamp_validator.validate_archived(message);
+        if result != ValidationResult::Accept {
+            return result;
+        }
+        let result = self.run_validators(topic, message);
+        if result != ValidationResult::Accept {
+            return result;
+        }
+        match self.rln.as_mut() {
+            Some(rln) => rln.validate_archived_message(message),
+            None => ValidationResult::Accept,
+        }
+    }
+
+    // Global validators run before the ones of the topic
+    fn run_validators(&self, topic: &str, message: &WakuMessage) -> ValidationResult {
+        let topic_validators = self.topic_validators.get(topic).into_iter().flatten();
+        for validator in self.validators.iter().chain(topic_validators) {
+            let result = validator.validate(topic, message);
+            if result != ValidationResult::Accept {
+                return result;
+            }
+        }
+        ValidationResult::Accept
     }
 
     pub fn subscribe(&mut self, topic: &str) -> Result<bool, SubscriptionError> {
         let ident_topic = IdentTopic::new(topic);
+        if let Some(params) = &self.topic_score_params {
+            self.gossipsub
+                .set_topic_params(ident_topic.clone(), params.clone())
+                .expect("Peer scoring enabled");
+        }
         self.gossipsub.subscribe(&ident_topic)
     }
 
@@ -64,16 +378,233 @@ impl WakuRelayBehaviour {
         self.gossipsub.unsubscribe(&ident_topic)
     }
 
-    pub fn publish(&mut self, topic: &str, msg: WakuMessage) -> Result<MessageId, PublishError> {
+    // Returns the ID of the published message, or None when RLN is enabled. The proof is then
+    // generated in the background and the message published once it is ready, which emits
+    // WakuRelayEvent::ProvenMessagePublished
+    pub fn publish(
+        &mut self,
+        topic: &str,
+        mut msg: WakuMessage,
+    ) -> Result<Option<MessageId>, PublishError> {
         let ident_topic = IdentTopic::new(topic);
-        let msg_bytes = match msg.write_to_bytes() {
-            Ok(b) => b,
-            Err(_) => panic!("can't write WakuMessage bytes"), // todo: proper error propagation
+        if let Some(signing_key) = self.signing_keys.get(topic) {
+            // Protected topics require a timestamp, which the signature covers
+            if msg.get_timestamp() == 0 {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards");
+                msg.set_timestamp(now.as_nanos() as i64);
+            }
+            sign_message(topic, &mut msg, signing_key);
+        }
+        let rln = match self.rln.as_mut() {
+            Some(rln) => rln,
+            None => return self.publish_now(&ident_topic, msg).map(Some),
         };
-        self.gossipsub.publish(ident_topic, msg_bytes)
+
+        let prover = rln
+            .prepare_proof(&msg)
+            .map_err(PublishError::TransformFailed)?;
+        let (proof_tx, proof_rx) = oneshot::channel();
+        thread::spawn(move || {
+            let _ = proof_tx.send(prover.prove());
+        });
+        self.proofs.push(
+            proof_rx
+                .map(move |proof| {
+                    let proof = proof.unwrap_or_else(|_| Err(io::Error::other("prover panicked")));
+                    (ident_topic, msg, proof)
+                })
+                .boxed(),
+        );
+        Ok(None)
+    }
+
+    fn publish_now(
+        &mut self,
+        topic: &IdentTopic,
+        msg: WakuMessage,
+    ) -> Result<MessageId, PublishError> {
+        let msg_bytes = msg
+            .write_to_bytes()
+            .map_err(|e| PublishError::TransformFailed(io::Error::other(e)))?;
+        self.gossipsub.publish(topic.clone(), msg_bytes)
     }
 
     pub fn add_peer(&mut self, peer_id: &PeerId) {
         self.gossipsub.add_explicit_peer(peer_id);
     }
+
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.gossipsub
+            .topics()
+            .map(|t| t.as_str().to_string())
+            .collect()
+    }
+
+    // Returns None if peer scoring is disabled or the peer is unknown
+    pub fn peer_score(&self, peer_id: &PeerId) -> Option<f64> {
+        self.gossipsub.peer_score(peer_id)
+    }
+
+    // Returns the score of every known relay peer, empty if peer scoring is disabled
+    pub fn peer_scores(&self) -> Vec<(PeerId, f64)> {
+        self.gossipsub
+            .all_peers()
+            .filter_map(|(peer_id, _)| {
+                self.gossipsub
+                    .peer_score(peer_id)
+                    .map(|score| (*peer_id, score))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::waku_message_pb::WakuMessage;
+    use crate::waku_relay::{
+        config::WakuRelayConfig,
+        network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
+        validation::ValidationResult,
+    };
+    use crate::waku_rln_relay::{
+        membership::StaticMembership,
+        rln::{IdentityCredential, RlnKeys, WakuRlnConfig, WakuRlnRelay},
+    };
+    use futures::{executor::block_on, future::poll_fn};
+    use libp2p::swarm::{NetworkBehaviour, ToSwarm};
+    use protobuf::Message;
+    use rand::rngs::OsRng;
+    use std::{
+        sync::{Arc, Mutex},
+        task::Poll,
+        time::Duration,
+    };
+
+    const TOPIC: &str = "/waku/2/rs/1/0";
+    const OTHER_TOPIC: &str = "/waku/2/rs/1/1";
+
+    // A validator that records its name when run and returns a fixed result
+    fn recording(
+        calls: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+        result: ValidationResult,
+    ) -> impl Fn(&str, &WakuMessage) -> ValidationResult + Send {
+        let calls = calls.clone();
+        move |_: &str, _: &WakuMessage| {
+            calls.lock().unwrap().push(name);
+            result
+        }
+    }
+
+    fn message_bytes() -> Vec<u8> {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(b"payload".to_vec());
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        msg.write_to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_validate_pipeline() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut relay = WakuRelayBehaviour::new(WakuRelayConfig::default());
+        relay.add_validator(recording(&calls, "global", ValidationResult::Accept));
+        relay.add_topic_validator(TOPIC, recording(&calls, "topic", ValidationResult::Accept));
+
+        // Global validators run before the ones of the topic
+        assert_eq!(
+            ValidationResult::Accept,
+            relay.validate(TOPIC, &message_bytes())
+        );
+        assert_eq!(vec!["global", "topic"], *calls.lock().unwrap());
+
+        // Topic validators only run on their own topic
+        calls.lock().unwrap().clear();
+        assert_eq!(
+            ValidationResult::Accept,
+            relay.validate(OTHER_TOPIC, &message_bytes())
+        );
+        assert_eq!(vec!["global"], *calls.lock().unwrap());
+
+        // Malformed messages are rejected before any validator runs
+        calls.lock().unwrap().clear();
+        assert_eq!(ValidationResult::Reject, relay.validate(TOPIC, &[0xff; 3]));
+        assert!(calls.lock().unwrap().is_empty());
+
+        // The first result other than Accept is returned, and no later validator runs
+        for result in [ValidationResult::Reject, ValidationResult::Ignore] {
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let mut relay = WakuRelayBehaviour::new(WakuRelayConfig::default());
+            relay.add_validator(recording(&calls, "first", result));
+            relay.add_validator(recording(&calls, "second", ValidationResult::Accept));
+            relay.add_topic_validator(TOPIC, recording(&calls, "topic", ValidationResult::Accept));
+            assert_eq!(result, relay.validate(TOPIC, &message_bytes()));
+            assert_eq!(vec!["first"], *calls.lock().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_validate_rln_last() {
+        let config = WakuRlnConfig {
+            tree_depth: 4,
+            ..Default::default()
+        };
+        let keys = RlnKeys::generate(config.tree_depth, &mut OsRng).unwrap();
+        let rln = WakuRlnRelay::new(config, keys, StaticMembership::new(Vec::new()), None).unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut relay = WakuRelayBehaviour::new(WakuRelayConfig::default());
+        relay.enable_rln(rln);
+        relay.add_topic_validator(TOPIC, recording(&calls, "topic", ValidationResult::Ignore));
+
+        // A topic validator that ignores the message spares the proof check, which would
+        // reject a message without a proof
+        assert_eq!(
+            ValidationResult::Ignore,
+            relay.validate(TOPIC, &message_bytes())
+        );
+        assert_eq!(
+            ValidationResult::Reject,
+            relay.validate(OTHER_TOPIC, &message_bytes())
+        );
+        assert_eq!(vec!["topic"], *calls.lock().unwrap());
+    }
+
+    #[test]
+    fn test_publish_rln() {
+        let config = WakuRlnConfig {
+            tree_depth: 4,
+            epoch_period: Duration::from_secs(u64::MAX),
+            ..Default::default()
+        };
+        let keys = RlnKeys::generate(config.tree_depth, &mut OsRng).unwrap();
+        let credential = IdentityCredential::generate(&mut OsRng);
+        let membership = StaticMembership::new(vec![credential.id_commitment]);
+        let rln = WakuRlnRelay::new(config, keys, membership, Some(credential)).unwrap();
+        let mut relay = WakuRelayBehaviour::new(WakuRelayConfig::default());
+        relay.enable_rln(rln);
+
+        // The proof is generated in the background, and the epoch is claimed right away
+        let msg = WakuMessage::parse_from_bytes(&message_bytes()).unwrap();
+        assert_eq!(None, relay.publish(TOPIC, msg.clone()).unwrap());
+        assert!(relay.publish(TOPIC, msg).is_err());
+        assert_eq!(1, relay.proofs.len());
+
+        // Without peers the proven message fails to publish, so it is not reported as published
+        let mut published = 0;
+        block_on(poll_fn(|cx| {
+            while let Poll::Ready(event) = relay.poll(cx) {
+                if let ToSwarm::GenerateEvent(WakuRelayEvent::ProvenMessagePublished { .. }) = event
+                {
+                    published += 1;
+                }
+            }
+            match relay.proofs.is_empty() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        }));
+        assert_eq!(0, published);
+    }
 }
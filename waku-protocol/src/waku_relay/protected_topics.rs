@@ -0,0 +1,159 @@
+use crate::{
+    pb::waku_message_pb::WakuMessage,
+    waku_relay::validation::{ValidationResult, WakuMessageValidator},
+};
+use k256::ecdsa::{
+    signature::hazmat::{PrehashSigner, PrehashVerifier},
+    Signature, SigningKey, VerifyingKey,
+};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+// A pubsub topic that only carries messages signed by the owner of a secp256k1 key,
+// following nwaku's signed shards. The signature goes in the message's meta field.
+#[derive(Clone, Debug)]
+pub struct ProtectedTopic {
+    pub pubsub_topic: String,
+    pub public_key: VerifyingKey,
+    // Private key matching public_key, for nodes that publish on the topic
+    pub signing_key: Option<SigningKey>,
+}
+
+impl ProtectedTopic {
+    pub fn new(pubsub_topic: &str, public_key: VerifyingKey) -> Self {
+        ProtectedTopic {
+            pubsub_topic: pubsub_topic.to_string(),
+            public_key,
+            signing_key: None,
+        }
+    }
+
+    pub fn with_signing_key(pubsub_topic: &str, signing_key: SigningKey) -> Self {
+        ProtectedTopic {
+            pubsub_topic: pubsub_topic.to_string(),
+            public_key: *signing_key.verifying_key(),
+            signing_key: Some(signing_key),
+        }
+    }
+}
+
+// Parses <pubsub_topic>:<hex key>. A 32 byte key is taken as the private key used for
+// signing, anything else as a SEC1 encoded public key
+impl FromStr for ProtectedTopic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (topic, key) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <pubsub_topic>:<key>, got {}", s))?;
+        let key = hex::decode(key.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+        match key.len() {
+            32 => Ok(Self::with_signing_key(
+                topic,
+                SigningKey::from_slice(&key).map_err(|e| e.to_string())?,
+            )),
+            _ => Ok(Self::new(
+                topic,
+                VerifyingKey::from_sec1_bytes(&key).map_err(|e| e.to_string())?,
+            )),
+        }
+    }
+}
+
+// The hash that gets signed: everything but meta, which holds the signature itself
+pub fn message_hash(pubsub_topic: &str, message: &WakuMessage) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(pubsub_topic.as_bytes());
+    hasher.update(message.get_payload());
+    hasher.update(message.get_content_topic().as_bytes());
+    hasher.update((message.get_timestamp() as u64).to_le_bytes());
+    hasher.finalize().into()
+}
+
+pub fn sign_message(pubsub_topic: &str, message: &mut WakuMessage, signing_key: &SigningKey) {
+    let signature: Signature = signing_key
+        .sign_prehash(&message_hash(pubsub_topic, message))
+        .expect("Hash is 32 bytes");
+    message.set_meta(signature.to_bytes().to_vec());
+}
+
+// Rejects messages of a protected topic that are not signed by its key. Signed messages
+// must carry a timestamp, so that together with TimestampValidator they can't be replayed
+// indefinitely.
+pub struct ProtectedTopicValidator {
+    public_key: VerifyingKey,
+}
+
+impl ProtectedTopicValidator {
+    pub fn new(public_key: VerifyingKey) -> Self {
+        ProtectedTopicValidator { public_key }
+    }
+}
+
+impl WakuMessageValidator for ProtectedTopicValidator {
+    fn validate(&self, pubsub_topic: &str, message: &WakuMessage) -> ValidationResult {
+        if message.get_timestamp() == 0 {
+            return ValidationResult::Reject;
+        }
+        let signature = match Signature::from_slice(message.get_meta()) {
+            Ok(s) => s,
+            Err(_) => return ValidationResult::Reject,
+        };
+        match self
+            .public_key
+            .verify_prehash(&message_hash(pubsub_topic, message), &signature)
+        {
+            Ok(()) => ValidationResult::Accept,
+            Err(_) => ValidationResult::Reject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::waku_message_pb::WakuMessage;
+    use crate::waku_relay::{
+        protected_topics::{sign_message, ProtectedTopic, ProtectedTopicValidator},
+        validation::{ValidationResult, WakuMessageValidator},
+    };
+    use k256::ecdsa::SigningKey;
+
+    const TOPIC: &str = "/waku/2/rs/1/0";
+
+    #[test]
+    fn test_protected_topic_validator() {
+        let signing_key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let protected: ProtectedTopic = format!("{}:{}", TOPIC, hex::encode([7; 32]))
+            .parse()
+            .unwrap();
+        assert_eq!(Some(signing_key.clone()), protected.signing_key);
+        let validator = ProtectedTopicValidator::new(protected.public_key);
+
+        let mut msg = WakuMessage::new();
+        msg.set_payload(b"payload".to_vec());
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        msg.set_timestamp(1);
+        assert_eq!(ValidationResult::Reject, validator.validate(TOPIC, &msg));
+
+        sign_message(TOPIC, &mut msg, &signing_key);
+        assert_eq!(ValidationResult::Accept, validator.validate(TOPIC, &msg));
+        // The signature covers the pubsub topic, the payload and the timestamp
+        assert_eq!(ValidationResult::Reject, validator.validate("other", &msg));
+        let mut tampered = msg.clone();
+        tampered.set_payload(b"tampered".to_vec());
+        assert_eq!(
+            ValidationResult::Reject,
+            validator.validate(TOPIC, &tampered)
+        );
+        let mut tampered = msg.clone();
+        tampered.set_timestamp(2);
+        assert_eq!(
+            ValidationResult::Reject,
+            validator.validate(TOPIC, &tampered)
+        );
+
+        let other_key = SigningKey::from_slice(&[8; 32]).unwrap();
+        sign_message(TOPIC, &mut msg, &other_key);
+        assert_eq!(ValidationResult::Reject, validator.validate(TOPIC, &msg));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{pb::waku_message_pb::WakuMessage, waku_message::MAX_MESSAGE_SIZE};
+use libp2p::gossipsub::MessageAcceptance;
+use protobuf::Message;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Outcome of validating a message received via WakuRelay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationResult {
+    // Deliver the message and forward it to the network
+    Accept,
+    // Drop the message and penalize the peer that propagated it
+    Reject,
+    // Drop the message without penalizing the propagating peer
+    Ignore,
+}
+
+impl From<ValidationResult> for MessageAcceptance {
+    fn from(result: ValidationResult) -> Self {
+        match result {
+            ValidationResult::Accept => MessageAcceptance::Accept,
+            ValidationResult::Reject => MessageAcceptance::Reject,
+            ValidationResult::Ignore => MessageAcceptance::Ignore,
+        }
+    }
+}
+
+// A check run on every decoded WakuMessage before it is delivered or forwarded.
+pub trait WakuMessageValidator: Send {
+    fn validate(&self, pubsub_topic: &str, message: &WakuMessage) -> ValidationResult;
+}
+
+impl<F> WakuMessageValidator for F
+where
+    F: Fn(&str, &WakuMessage) -> ValidationResult + Send,
+{
+    fn validate(&self, pubsub_topic: &str, message: &WakuMessage) -> ValidationResult {
+        self(pubsub_topic, message)
+    }
+}
+
+// Decodes raw gossipsub data into a WakuMessage, rejecting oversized or malformed payloads
+pub fn decode_waku_message(data: &[u8]) -> Result<WakuMessage, ValidationResult> {
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(ValidationResult::Reject);
+    }
+    WakuMessage::parse_from_bytes(data).map_err(|_| ValidationResult::Reject)
+}
+
+// Rejects messages whose timestamp deviates from the local clock by more than max_drift.
+// Messages without a timestamp are accepted, since the field is optional.
+pub struct TimestampValidator {
+    max_drift: Duration,
+}
+
+impl TimestampValidator {
+    pub fn new(max_drift: Duration) -> Self {
+        TimestampValidator { max_drift }
+    }
+
+    // Archived messages may be arbitrarily old, but not from the future either
+    pub fn validate_archived(&self, message: &WakuMessage) -> ValidationResult {
+        match self.drift(message) > self.max_drift.as_nanos() as i64 {
+            true => ValidationResult::Reject,
+            false => ValidationResult::Accept,
+        }
+    }
+
+    // How far ahead of the local clock the message is, negative if it is behind
+    fn drift(&self, message: &WakuMessage) -> i64 {
+        let timestamp = message.get_timestamp();
+        if timestamp == 0 {
+            return 0;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_nanos() as i64;
+        timestamp - now
+    }
+}
+
+impl WakuMessageValidator for TimestampValidator {
+    fn validate(&self, _: &str, message: &WakuMessage) -> ValidationResult {
+        match self.drift(message).abs() > self.max_drift.as_nanos() as i64 {
+            true => ValidationResult::Reject,
+            false => ValidationResult::Accept,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::waku_message_pb::WakuMessage;
+    use crate::waku_message::MAX_MESSAGE_SIZE;
+    use crate::waku_relay::validation::{
+        decode_waku_message, TimestampValidator, ValidationResult, WakuMessageValidator,
+    };
+    use protobuf::Message;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn now_nanos() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as i64
+    }
+
+    #[test]
+    fn test_decode_waku_message() {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(b"payload".to_vec());
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        let bytes = msg.write_to_bytes().unwrap();
+        assert_eq!(Ok(msg), decode_waku_message(&bytes));
+
+        assert_eq!(
+            Err(ValidationResult::Reject),
+            decode_waku_message(&[0xff, 0xff, 0xff])
+        );
+        assert_eq!(
+            Err(ValidationResult::Reject),
+            decode_waku_message(&vec![0; MAX_MESSAGE_SIZE + 1])
+        );
+    }
+
+    #[test]
+    fn test_timestamp_validator() {
+        let validator = TimestampValidator::new(Duration::from_secs(20));
+        let mut msg = WakuMessage::new();
+
+        assert_eq!(ValidationResult::Accept, validator.validate("topic", &msg));
+
+        msg.set_timestamp(now_nanos());
+        assert_eq!(ValidationResult::Accept, validator.validate("topic", &msg));
+
+        msg.set_timestamp(now_nanos() + Duration::from_secs(60).as_nanos() as i64);
+        assert_eq!(ValidationResult::Reject, validator.validate("topic", &msg));
+
+        msg.set_timestamp(now_nanos() - Duration::from_secs(60).as_nanos() as i64);
+        assert_eq!(ValidationResult::Reject, validator.validate("topic", &msg));
+        assert_eq!(ValidationResult::Accept, validator.validate_archived(&msg));
+
+        msg.set_timestamp(now_nanos() + Duration::from_secs(60).as_nanos() as i64);
+        assert_eq!(ValidationResult::Reject, validator.validate_archived(&msg));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::waku_rln_relay::{merkle_tree::MerkleProof, poseidon::poseidon_hash_var, Fr};
+use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
+use ark_r1cs_std::{
+    alloc::AllocVar, boolean::Boolean, eq::EqGadget, fields::fp::FpVar, select::CondSelectGadget,
+};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+// The RLN statement: the prover knows the identity secret behind a leaf of the membership
+// tree with the given root, and (x, y) and the nullifier are derived from that secret
+// for the given external nullifier.
+//
+// Public inputs, in order: y, root, nullifier, x, external_nullifier.
+#[derive(Clone)]
+pub struct RlnCircuit {
+    pub config: PoseidonConfig<Fr>,
+    pub identity_secret: Fr,
+    pub merkle_proof: MerkleProof,
+    pub root: Fr,
+    pub external_nullifier: Fr,
+    pub x: Fr,
+    pub y: Fr,
+    pub nullifier: Fr,
+}
+
+impl RlnCircuit {
+    // A circuit with the right shape for the given tree depth, used for the key setup
+    pub fn empty(config: PoseidonConfig<Fr>, depth: usize) -> Self {
+        RlnCircuit {
+            config,
+            identity_secret: Fr::default(),
+            merkle_proof: MerkleProof {
+                path_elements: vec![Fr::default(); depth],
+                path_index: vec![false; depth],
+            },
+            root: Fr::default(),
+            external_nullifier: Fr::default(),
+            x: Fr::default(),
+            y: Fr::default(),
+            nullifier: Fr::default(),
+        }
+    }
+
+    pub fn public_inputs(&self) -> Vec<Fr> {
+        vec![
+            self.y,
+            self.root,
+            self.nullifier,
+            self.x,
+            self.external_nullifier,
+        ]
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for RlnCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let y = FpVar::new_input(cs.clone(), || Ok(self.y))?;
+        let root = FpVar::new_input(cs.clone(), || Ok(self.root))?;
+        let nullifier = FpVar::new_input(cs.clone(), || Ok(self.nullifier))?;
+        let x = FpVar::new_input(cs.clone(), || Ok(self.x))?;
+        let external_nullifier = FpVar::new_input(cs.clone(), || Ok(self.external_nullifier))?;
+        let identity_secret = FpVar::new_witness(cs.clone(), || Ok(self.identity_secret))?;
+
+        // Membership: the id commitment is a leaf of the tree
+        let id_commitment = poseidon_hash_var(
+            cs.clone(),
+            &self.config,
+            std::slice::from_ref(&identity_secret),
+        )?;
+        let mut node = id_commitment;
+        for (sibling, is_right) in self
+            .merkle_proof
+            .path_elements
+            .iter()
+            .zip(&self.merkle_proof.path_index)
+        {
+            let sibling = FpVar::new_witness(cs.clone(), || Ok(*sibling))?;
+            let is_right = Boolean::new_witness(cs.clone(), || Ok(*is_right))?;
+            let left = FpVar::conditionally_select(&is_right, &sibling, &node)?;
+            let right = FpVar::conditionally_select(&is_right, &node, &sibling)?;
+            node = poseidon_hash_var(cs.clone(), &self.config, &[left, right])?;
+        }
+        node.enforce_equal(&root)?;
+
+        // Shamir share of the secret on the line defined by the epoch
+        let a1 = poseidon_hash_var(
+            cs.clone(),
+            &self.config,
+            &[identity_secret.clone(), external_nullifier],
+        )?;
+        (identity_secret + &a1 * &x).enforce_equal(&y)?;
+
+        poseidon_hash_var(cs, &self.config, &[a1])?.enforce_equal(&nullifier)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use crate::waku_rln_relay::{fr_from_hex, Fr};
+use std::{fs, io, path::Path};
+
+// Source of the RLN group, i.e. the id commitments of all registered members.
+// On-chain implementations follow the membership contract; StaticMembership reads a file.
+pub trait RlnMembershipSource: Send {
+    // Returns the id commitments registered since the previous call, in registration order.
+    fn poll_new_members(&mut self) -> io::Result<Vec<Fr>>;
+}
+
+// A fixed group, loaded once from a local list of id commitments.
+pub struct StaticMembership {
+    pending: Vec<Fr>,
+}
+
+impl StaticMembership {
+    pub fn new(id_commitments: Vec<Fr>) -> Self {
+        StaticMembership {
+            pending: id_commitments,
+        }
+    }
+
+    // Reads one hex encoded id commitment per line. Blank lines and lines starting with # are skipped
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut id_commitments = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match fr_from_hex(line) {
+                Some(id_commitment) => id_commitments.push(id_commitment),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid id commitment on line {}", i + 1),
+                    ))
+                }
+            }
+        }
+        Ok(Self::new(id_commitments))
+    }
+}
+
+impl RlnMembershipSource for StaticMembership {
+    fn poll_new_members(&mut self) -> io::Result<Vec<Fr>> {
+        Ok(std::mem::take(&mut self.pending))
+    }
+}
@@ -0,0 +1,171 @@
+use crate::waku_rln_relay::{poseidon::poseidon_hash, Fr};
+use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
+use ark_ff::Zero;
+
+// Fixed-depth Poseidon Merkle tree holding the id commitments of RLN group members.
+// Only the filled part of each layer is stored, empty subtrees hash to precomputed zeroes.
+pub struct MerkleTree {
+    config: PoseidonConfig<Fr>,
+    depth: usize,
+    // zeroes[level] is the root of an empty subtree of height level
+    zeroes: Vec<Fr>,
+    // layers[0] holds the leaves, layers[depth] the root
+    layers: Vec<Vec<Fr>>,
+}
+
+// Sibling hashes from a leaf up to the root, with whether the path goes through a right child.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub path_elements: Vec<Fr>,
+    pub path_index: Vec<bool>,
+}
+
+impl MerkleTree {
+    pub fn new(config: PoseidonConfig<Fr>, depth: usize) -> Self {
+        let mut zeroes = vec![Fr::zero()];
+        for level in 0..depth {
+            zeroes.push(poseidon_hash(&config, &[zeroes[level], zeroes[level]]));
+        }
+        MerkleTree {
+            config,
+            depth,
+            zeroes,
+            layers: vec![Vec::new(); depth + 1],
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn capacity(&self) -> usize {
+        1 << self.depth
+    }
+
+    pub fn leaves_count(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    pub fn root(&self) -> Fr {
+        self.node(self.depth, 0)
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<Fr> {
+        self.layers[0].get(index).copied()
+    }
+
+    // Appends a leaf and returns its index, or None if the tree is full
+    pub fn insert(&mut self, leaf: Fr) -> Option<usize> {
+        let index = self.leaves_count();
+        if index >= self.capacity() {
+            return None;
+        }
+        self.layers[0].push(Fr::zero());
+        self.set(index, leaf);
+        Some(index)
+    }
+
+    // Overwrites an already inserted leaf, used to remove a member by zeroing it
+    pub fn set(&mut self, index: usize, leaf: Fr) {
+        self.layers[0][index] = leaf;
+        let mut index = index;
+        for level in 0..self.depth {
+            let parent = index / 2;
+            let hash = poseidon_hash(
+                &self.config,
+                &[
+                    self.node(level, parent * 2),
+                    self.node(level, parent * 2 + 1),
+                ],
+            );
+            let layer = &mut self.layers[level + 1];
+            if layer.len() <= parent {
+                layer.resize(parent + 1, self.zeroes[level + 1]);
+            }
+            layer[parent] = hash;
+            index = parent;
+        }
+    }
+
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaves_count() {
+            return None;
+        }
+        let mut path_elements = Vec::with_capacity(self.depth);
+        let mut path_index = Vec::with_capacity(self.depth);
+        let mut index = index;
+        for level in 0..self.depth {
+            path_elements.push(self.node(level, index ^ 1));
+            path_index.push(index & 1 == 1);
+            index /= 2;
+        }
+        Some(MerkleProof {
+            path_elements,
+            path_index,
+        })
+    }
+
+    // Recomputes the root from a leaf and its proof
+    pub fn compute_root(config: &PoseidonConfig<Fr>, leaf: Fr, proof: &MerkleProof) -> Fr {
+        proof
+            .path_elements
+            .iter()
+            .zip(&proof.path_index)
+            .fold(leaf, |node, (sibling, is_right)| match is_right {
+                true => poseidon_hash(config, &[*sibling, node]),
+                false => poseidon_hash(config, &[node, *sibling]),
+            })
+    }
+
+    fn node(&self, level: usize, index: usize) -> Fr {
+        self.layers[level]
+            .get(index)
+            .copied()
+            .unwrap_or(self.zeroes[level])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waku_rln_relay::{
+        merkle_tree::MerkleTree,
+        poseidon::{poseidon_config, poseidon_hash},
+        Fr,
+    };
+
+    #[test]
+    fn test_merkle_tree() {
+        let config = poseidon_config();
+        let mut tree = MerkleTree::new(config.clone(), 3);
+        let empty_root = tree.root();
+
+        let leaves: Vec<Fr> = (1..=5u64).map(Fr::from).collect();
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(Some(i), tree.insert(*leaf));
+        }
+        assert_ne!(empty_root, tree.root());
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(i).unwrap();
+            assert_eq!(
+                tree.root(),
+                MerkleTree::compute_root(&config, *leaf, &proof)
+            );
+        }
+        assert_eq!(None, tree.proof(5));
+
+        // The root only depends on the leaves, not on how they were inserted
+        let h = |a, b| poseidon_hash(&config, &[a, b]);
+        let zero = Fr::from(0u64);
+        let expected = h(
+            h(h(leaves[0], leaves[1]), h(leaves[2], leaves[3])),
+            h(h(leaves[4], zero), h(zero, zero)),
+        );
+        assert_eq!(expected, tree.root());
+
+        for _ in 5..8 {
+            assert!(tree.insert(zero).is_some());
+        }
+        assert_eq!(None, tree.insert(zero));
+    }
+}
@@ -0,0 +1,39 @@
+pub mod circuit;
+pub mod membership;
+pub mod merkle_tree;
+pub mod poseidon;
+pub mod rln;
+
+use ark_ff::{BigInteger, PrimeField};
+use ark_serialize::CanonicalDeserialize;
+use sha2::{Digest, Sha256};
+
+pub use ark_bn254::Fr;
+
+// Field elements travel as 32 little-endian bytes, both on the wire and in files
+pub const FIELD_ELEMENT_SIZE: usize = 32;
+
+pub fn fr_to_bytes(value: &Fr) -> Vec<u8> {
+    value.into_bigint().to_bytes_le()
+}
+
+// Returns None unless bytes is the canonical encoding of a field element
+pub fn fr_from_bytes(bytes: &[u8]) -> Option<Fr> {
+    if bytes.len() != FIELD_ELEMENT_SIZE {
+        return None;
+    }
+    Fr::deserialize_compressed(bytes).ok()
+}
+
+pub fn fr_to_hex(value: &Fr) -> String {
+    hex::encode(fr_to_bytes(value))
+}
+
+pub fn fr_from_hex(s: &str) -> Option<Fr> {
+    fr_from_bytes(&hex::decode(s.trim_start_matches("0x")).ok()?)
+}
+
+// Maps arbitrary data to a field element by reducing its sha256 digest
+pub fn hash_to_field(data: &[u8]) -> Fr {
+    Fr::from_le_bytes_mod_order(&Sha256::digest(data))
+}
@@ -0,0 +1,519 @@
+use crate::{
+    pb::waku_message_pb::{RateLimitProof, WakuMessage},
+    waku_relay::validation::ValidationResult,
+    waku_rln_relay::{
+        circuit::RlnCircuit,
+        fr_from_bytes, fr_from_hex, fr_to_bytes, fr_to_hex, hash_to_field,
+        membership::RlnMembershipSource,
+        merkle_tree::MerkleTree,
+        poseidon::{poseidon_config, poseidon_hash},
+        Fr, FIELD_ELEMENT_SIZE,
+    },
+};
+use ark_bn254::Bn254;
+use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
+use ark_ff::{Field, UniformRand};
+use ark_groth16::{Groth16, PreparedVerifyingKey, Proof, ProvingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_snark::SNARK;
+use log::{info, warn};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Defaults follow 17/WAKU2-RLN-RELAY and nwaku
+pub const DEFAULT_TREE_DEPTH: usize = 20;
+pub const DEFAULT_EPOCH_PERIOD_SECS: u64 = 1;
+pub const DEFAULT_MAX_EPOCH_GAP: u64 = 20;
+pub const DEFAULT_ROOTS_WINDOW: usize = 5;
+
+// Domain separator for this application, so that shares cannot be replayed across RLN apps
+const RLN_IDENTIFIER: &[u8] = b"waku-rln-relay";
+
+// Parameters of the RLN group and of proof validation.
+#[derive(Clone, Debug)]
+pub struct WakuRlnConfig {
+    // Depth of the membership Merkle tree. Must match the one the keys were generated for
+    pub tree_depth: usize,
+    // Length of an epoch. Each member may publish one message per epoch
+    pub epoch_period: Duration,
+    // Maximum number of epochs between a message's epoch and the local one
+    pub max_epoch_gap: u64,
+    // Number of recent membership roots accepted in proofs
+    pub roots_window: usize,
+}
+
+impl Default for WakuRlnConfig {
+    fn default() -> Self {
+        WakuRlnConfig {
+            tree_depth: DEFAULT_TREE_DEPTH,
+            epoch_period: Duration::from_secs(DEFAULT_EPOCH_PERIOD_SECS),
+            max_epoch_gap: DEFAULT_MAX_EPOCH_GAP,
+            roots_window: DEFAULT_ROOTS_WINDOW,
+        }
+    }
+}
+
+// An RLN identity. The id commitment is what gets registered in the membership group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityCredential {
+    pub identity_secret: Fr,
+    pub id_commitment: Fr,
+}
+
+impl IdentityCredential {
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        Self::from_secret(Fr::rand(rng))
+    }
+
+    pub fn from_secret(identity_secret: Fr) -> Self {
+        IdentityCredential {
+            identity_secret,
+            id_commitment: poseidon_hash(&poseidon_config(), &[identity_secret]),
+        }
+    }
+
+    // The file holds the hex encoded identity secret
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let secret = fr_from_hex(fs::read_to_string(path)?.trim())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid identity secret"))?;
+        Ok(Self::from_secret(secret))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, fr_to_hex(&self.identity_secret) + "\n")
+    }
+}
+
+// Groth16 keys of the RLN circuit. Every member of a group has to use the same keys.
+#[derive(Clone)]
+pub struct RlnKeys {
+    // Shared with the provers of published messages
+    proving_key: Arc<ProvingKey<Bn254>>,
+    verifying_key: PreparedVerifyingKey<Bn254>,
+}
+
+impl RlnKeys {
+    // Runs a local circuit specific setup. Fine for tests and private groups, but whoever
+    // generated the keys could forge proofs
+    pub fn generate<R: RngCore + CryptoRng>(tree_depth: usize, rng: &mut R) -> io::Result<Self> {
+        let circuit = RlnCircuit::empty(poseidon_config(), tree_depth);
+        let (proving_key, _) =
+            Groth16::<Bn254>::circuit_specific_setup(circuit, rng).map_err(io::Error::other)?;
+        Ok(Self::from_proving_key(proving_key))
+    }
+
+    // The file is trusted local configuration, so the costly curve point checks are skipped
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let proving_key = ProvingKey::deserialize_uncompressed_unchecked(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Self::from_proving_key(proving_key))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        self.proving_key
+            .serialize_uncompressed(writer)
+            .map_err(io::Error::other)
+    }
+
+    fn from_proving_key(proving_key: ProvingKey<Bn254>) -> Self {
+        let verifying_key = ark_groth16::prepare_verifying_key(&proving_key.vk);
+        RlnKeys {
+            proving_key: Arc::new(proving_key),
+            verifying_key,
+        }
+    }
+}
+
+// The proof of a message, ready to be generated, see WakuRlnRelay::prepare_proof
+pub struct RlnProver {
+    proving_key: Arc<ProvingKey<Bn254>>,
+    circuit: RlnCircuit,
+    epoch: u64,
+    rln_identifier: Fr,
+}
+
+impl RlnProver {
+    pub fn prove(self) -> io::Result<RateLimitProof> {
+        let circuit = self.circuit;
+        let mut proof_bytes = Vec::new();
+        Groth16::<Bn254>::prove(&self.proving_key, circuit.clone(), &mut OsRng)
+            .map_err(io::Error::other)?
+            .serialize_compressed(&mut proof_bytes)
+            .map_err(io::Error::other)?;
+
+        let mut proof = RateLimitProof::new();
+        proof.set_proof(proof_bytes);
+        proof.set_merkle_root(fr_to_bytes(&circuit.root));
+        proof.set_epoch(epoch_to_bytes(self.epoch));
+        proof.set_share_x(fr_to_bytes(&circuit.x));
+        proof.set_share_y(fr_to_bytes(&circuit.y));
+        proof.set_nullifier(fr_to_bytes(&circuit.nullifier));
+        proof.set_rln_identifier(fr_to_bytes(&self.rln_identifier));
+        Ok(proof)
+    }
+}
+
+// Rate Limiting Nullifier for WakuRelay, modelled on 17/WAKU2-RLN-RELAY.
+// Attaches a zk proof of membership to outgoing messages and validates the proofs,
+// epochs and nullifiers of incoming ones, detecting members that exceed the rate limit.
+// Its Poseidon parameters and Groth16 setup are local, so proofs and id commitments only
+// interoperate with other waku-rs nodes sharing the same keys, not with nwaku or zerokit.
+pub struct WakuRlnRelay {
+    config: WakuRlnConfig,
+    poseidon: PoseidonConfig<Fr>,
+    keys: RlnKeys,
+    membership: Box<dyn RlnMembershipSource>,
+    tree: MerkleTree,
+    // Most recent roots first
+    valid_roots: VecDeque<Fr>,
+    rln_identifier: Fr,
+    // None for nodes that only validate
+    credential: Option<IdentityCredential>,
+    member_index: Option<usize>,
+    last_published_epoch: Option<u64>,
+    // Shares seen in each epoch, by nullifier
+    nullifier_log: BTreeMap<u64, HashMap<Fr, (Fr, Fr)>>,
+    // Id commitments of members caught exceeding the rate limit
+    slashed: Vec<Fr>,
+}
+
+impl WakuRlnRelay {
+    pub fn new(
+        config: WakuRlnConfig,
+        keys: RlnKeys,
+        membership: impl RlnMembershipSource + 'static,
+        credential: Option<IdentityCredential>,
+    ) -> io::Result<Self> {
+        let poseidon = poseidon_config();
+        let tree = MerkleTree::new(poseidon.clone(), config.tree_depth);
+        let mut rln = WakuRlnRelay {
+            valid_roots: VecDeque::from([tree.root()]),
+            config,
+            poseidon,
+            keys,
+            membership: Box::new(membership),
+            tree,
+            rln_identifier: hash_to_field(RLN_IDENTIFIER),
+            credential,
+            member_index: None,
+            last_published_epoch: None,
+            nullifier_log: BTreeMap::new(),
+            slashed: Vec::new(),
+        };
+        rln.sync_membership()?;
+        Ok(rln)
+    }
+
+    // Adds newly registered members to the tree and updates the window of valid roots
+    pub fn sync_membership(&mut self) -> io::Result<()> {
+        let new_members = self.membership.poll_new_members()?;
+        if new_members.is_empty() {
+            return Ok(());
+        }
+
+        for id_commitment in new_members {
+            let index = self
+                .tree
+                .insert(id_commitment)
+                .ok_or_else(|| io::Error::other("RLN membership tree is full"))?;
+            if self.member_index.is_none()
+                && self.credential.as_ref().map(|c| c.id_commitment) == Some(id_commitment)
+            {
+                info!("WakuRlnRelay: registered as member {}", index);
+                self.member_index = Some(index);
+            }
+        }
+
+        self.valid_roots.push_front(self.tree.root());
+        self.valid_roots.truncate(self.config.roots_window);
+        Ok(())
+    }
+
+    pub fn member_index(&self) -> Option<usize> {
+        self.member_index
+    }
+
+    pub fn slashed_members(&self) -> &[Fr] {
+        &self.slashed
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards");
+        (now.as_millis() / self.config.epoch_period.as_millis().max(1)) as u64
+    }
+
+    // Generates the proof for a message to be published in the current epoch
+    pub fn attach_proof(&mut self, message: &mut WakuMessage) -> io::Result<()> {
+        let proof = self.prepare_proof(message)?.prove()?;
+        message.set_rate_limit_proof(proof);
+        Ok(())
+    }
+
+    // Claims the current epoch for a message and returns what is needed to prove it. Proving
+    // takes seconds for deep trees, so it can be left to RlnProver::prove on another thread
+    pub fn prepare_proof(&mut self, message: &WakuMessage) -> io::Result<RlnProver> {
+        self.sync_membership()?;
+        let epoch = self.current_epoch();
+        let (credential, member_index) = match (&self.credential, self.member_index) {
+            (Some(credential), Some(index)) => (credential, index),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "not a member of the RLN group",
+                ))
+            }
+        };
+        if self.last_published_epoch == Some(epoch) {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "RLN rate limit reached for the current epoch",
+            ));
+        }
+
+        let identity_secret = credential.identity_secret;
+        let external_nullifier = self.external_nullifier(epoch);
+        let x = signal_hash(message);
+        let a1 = poseidon_hash(&self.poseidon, &[identity_secret, external_nullifier]);
+        let circuit = RlnCircuit {
+            config: self.poseidon.clone(),
+            identity_secret,
+            merkle_proof: self
+                .tree
+                .proof(member_index)
+                .expect("Member is in the tree"),
+            root: self.tree.root(),
+            external_nullifier,
+            x,
+            y: identity_secret + a1 * x,
+            nullifier: poseidon_hash(&self.poseidon, &[a1]),
+        };
+        self.last_published_epoch = Some(epoch);
+
+        Ok(RlnProver {
+            proving_key: self.keys.proving_key.clone(),
+            circuit,
+            epoch,
+            rln_identifier: self.rln_identifier,
+        })
+    }
+
+    // Checks the proof attached to a received message. Valid duplicates are ignored, while
+    // a second message from the same member in one epoch reveals its secret and is rejected
+    pub fn validate_message(&mut self, message: &WakuMessage) -> ValidationResult {
+        if let Err(e) = self.sync_membership() {
+            warn!("WakuRlnRelay: failed to sync membership: {}", e);
+        }
+        if !message.has_rate_limit_proof() {
+            return ValidationResult::Reject;
+        }
+        let proof = message.get_rate_limit_proof();
+
+        let epoch = match epoch_from_bytes(proof.get_epoch()) {
+            Some(epoch) => epoch,
+            None => return ValidationResult::Reject,
+        };
+        let current_epoch = self.current_epoch();
+        if epoch.abs_diff(current_epoch) > self.config.max_epoch_gap {
+            return ValidationResult::Reject;
+        }
+        let (nullifier, x, y) = match self.verify_proof(message, epoch) {
+            Some(shares) => shares,
+            None => return ValidationResult::Reject,
+        };
+
+        let result = self.check_nullifier(epoch, nullifier, x, y);
+        self.prune_nullifier_log(current_epoch);
+        result
+    }
+
+    // Checks the proof of a message archived by a store node. Its epoch may be long past, so
+    // neither the epoch gap nor the nullifier log apply
+    pub fn validate_archived_message(&mut self, message: &WakuMessage) -> ValidationResult {
+        if let Err(e) = self.sync_membership() {
+            warn!("WakuRlnRelay: failed to sync membership: {}", e);
+        }
+        let epoch = match epoch_from_bytes(message.get_rate_limit_proof().get_epoch()) {
+            Some(epoch) if message.has_rate_limit_proof() => epoch,
+            _ => return ValidationResult::Reject,
+        };
+        match self.verify_proof(message, epoch) {
+            Some(_) => ValidationResult::Accept,
+            None => ValidationResult::Reject,
+        }
+    }
+
+    // Verifies the proof attached to a message, returning its nullifier and share
+    fn verify_proof(&self, message: &WakuMessage, epoch: u64) -> Option<(Fr, Fr, Fr)> {
+        let proof = message.get_rate_limit_proof();
+        let fields = (
+            fr_from_bytes(proof.get_merkle_root()),
+            fr_from_bytes(proof.get_share_x()),
+            fr_from_bytes(proof.get_share_y()),
+            fr_from_bytes(proof.get_nullifier()),
+            fr_from_bytes(proof.get_rln_identifier()),
+        );
+        let (root, x, y, nullifier) = match fields {
+            (Some(root), Some(x), Some(y), Some(nullifier), Some(rln_identifier))
+                if rln_identifier == self.rln_identifier =>
+            {
+                (root, x, y, nullifier)
+            }
+            _ => return None,
+        };
+        if !self.valid_roots.contains(&root) || x != signal_hash(message) {
+            return None;
+        }
+
+        let public_inputs = [y, root, nullifier, x, self.external_nullifier(epoch)];
+        let proof = Proof::deserialize_compressed(proof.get_proof()).ok()?;
+        Groth16::<Bn254>::verify_with_processed_vk(&self.keys.verifying_key, &public_inputs, &proof)
+            .ok()?
+            .then_some((nullifier, x, y))
+    }
+
+    fn check_nullifier(&mut self, epoch: u64, nullifier: Fr, x: Fr, y: Fr) -> ValidationResult {
+        let shares = self.nullifier_log.entry(epoch).or_default();
+        let (x1, y1) = match shares.get(&nullifier) {
+            Some(share) => *share,
+            None => {
+                shares.insert(nullifier, (x, y));
+                return ValidationResult::Accept;
+            }
+        };
+        if (x1, y1) == (x, y) {
+            return ValidationResult::Ignore;
+        }
+
+        // Two points on the line y = secret + a1 * x are enough to recover the secret
+        match (x1 - x).inverse() {
+            Some(inv) => {
+                let a1 = (y1 - y) * inv;
+                let credential = IdentityCredential::from_secret(y - a1 * x);
+                warn!(
+                    "WakuRlnRelay: member {} exceeded the rate limit in epoch {}",
+                    fr_to_hex(&credential.id_commitment),
+                    epoch
+                );
+                if !self.slashed.contains(&credential.id_commitment) {
+                    self.slashed.push(credential.id_commitment);
+                }
+            }
+            None => warn!(
+                "WakuRlnRelay: conflicting shares for one signal in epoch {}",
+                epoch
+            ),
+        }
+        ValidationResult::Reject
+    }
+
+    // Shares of epochs that are no longer accepted can't be used for slashing anymore
+    fn prune_nullifier_log(&mut self, current_epoch: u64) {
+        let oldest = current_epoch.saturating_sub(self.config.max_epoch_gap);
+        self.nullifier_log = self.nullifier_log.split_off(&oldest);
+    }
+
+    fn external_nullifier(&self, epoch: u64) -> Fr {
+        poseidon_hash(&self.poseidon, &[Fr::from(epoch), self.rln_identifier])
+    }
+}
+
+// The signal a proof is bound to: the message contents, without the proof itself
+fn signal_hash(message: &WakuMessage) -> Fr {
+    let mut signal = message.get_payload().to_vec();
+    signal.extend_from_slice(message.get_content_topic().as_bytes());
+    hash_to_field(&signal)
+}
+
+// Epochs are encoded like field elements: 32 little-endian bytes
+fn epoch_to_bytes(epoch: u64) -> Vec<u8> {
+    let mut bytes = vec![0; FIELD_ELEMENT_SIZE];
+    bytes[..8].copy_from_slice(&epoch.to_le_bytes());
+    bytes
+}
+
+fn epoch_from_bytes(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() != FIELD_ELEMENT_SIZE || bytes[8..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(u64::from_le_bytes(bytes[..8].try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::waku_message_pb::WakuMessage;
+    use crate::waku_relay::validation::ValidationResult;
+    use crate::waku_rln_relay::{
+        membership::StaticMembership,
+        rln::{IdentityCredential, RlnKeys, WakuRlnConfig, WakuRlnRelay},
+    };
+    use rand::rngs::OsRng;
+    use std::time::Duration;
+
+    fn message(payload: &[u8]) -> WakuMessage {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(payload.to_vec());
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        msg
+    }
+
+    #[test]
+    fn test_rln_proofs_and_slashing() {
+        let config = WakuRlnConfig {
+            tree_depth: 4,
+            // A single epoch for the whole test, however long proving takes
+            epoch_period: Duration::from_secs(u64::MAX),
+            ..Default::default()
+        };
+        let keys = RlnKeys::generate(config.tree_depth, &mut OsRng).unwrap();
+        let alice = IdentityCredential::generate(&mut OsRng);
+        let bob = IdentityCredential::generate(&mut OsRng);
+        let members = vec![alice.id_commitment, bob.id_commitment];
+
+        let mut publisher = WakuRlnRelay::new(
+            config.clone(),
+            keys.clone(),
+            StaticMembership::new(members.clone()),
+            Some(alice.clone()),
+        )
+        .unwrap();
+        let mut validator =
+            WakuRlnRelay::new(config, keys, StaticMembership::new(members), None).unwrap();
+        assert_eq!(Some(0), publisher.member_index());
+
+        let mut msg = message(b"first");
+        assert_eq!(ValidationResult::Reject, validator.validate_message(&msg));
+        publisher.attach_proof(&mut msg).unwrap();
+        assert_eq!(ValidationResult::Accept, validator.validate_message(&msg));
+        assert_eq!(ValidationResult::Ignore, validator.validate_message(&msg));
+
+        // The proof is bound to the message contents
+        let mut tampered = msg.clone();
+        tampered.set_payload(b"tampered".to_vec());
+        assert_eq!(
+            ValidationResult::Reject,
+            validator.validate_message(&tampered)
+        );
+
+        // Publishing twice in an epoch is refused locally, and detected by validators otherwise
+        let mut second = message(b"second");
+        assert!(publisher.attach_proof(&mut second).is_err());
+        publisher.last_published_epoch = None;
+        publisher.attach_proof(&mut second).unwrap();
+        assert_eq!(
+            ValidationResult::Reject,
+            validator.validate_message(&second)
+        );
+        assert_eq!(&[alice.id_commitment], validator.slashed_members());
+    }
+}
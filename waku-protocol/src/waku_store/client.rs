@@ -0,0 +1,617 @@
+// Client side of 21/WAKU2-FT-STORE: queries go to several store peers, either all at once or
+// one after the other until one answers, and their results are merged. StoreClient only
+// keeps track of queries, WakuStoreClientBehaviour sends the requests it asks for. The client
+// needs no archive of its own, so it runs whether or not this node is a store node.
+use crate::{
+    pb::{
+        waku_message_pb::WakuMessage,
+        waku_store_pb::{HistoryQuery, HistoryRPC, HistoryResponse, HistoryResponse_Error},
+    },
+    waku_store::{
+        codec::{WakuStoreCodec, WakuStoreProtocol},
+        network_behaviour::{compute_digest, WakuMessageDigest},
+    },
+};
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    request_response::{self, OutboundRequestId, ProtocolSupport},
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use log::info;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    iter::once,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+// Weight of the latest response in a peer's average latency
+const LATENCY_SMOOTHING: f64 = 0.2;
+// Pages followed per peer and query, to bound queries that never run out of cursors
+const MAX_PAGES_PER_PEER: usize = 100;
+
+// How a query is spread over the store peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryStrategy {
+    // Queries the best n peers at once and merges all their results
+    FanOut(usize),
+    // Queries the best peer, moving on to the next one when it fails
+    FailOver,
+}
+
+// How a store peer has been answering queries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorePeerStats {
+    pub successes: u64,
+    pub failures: u64,
+    pub latency: Option<Duration>,
+}
+
+impl StorePeerStats {
+    // Laplace smoothed, so that new peers start at 0.5
+    pub fn success_rate(&self) -> f64 {
+        (self.successes + 1) as f64 / (self.successes + self.failures + 2) as f64
+    }
+
+    fn record(&mut self, success: bool, latency: Duration) {
+        match success {
+            true => self.successes += 1,
+            false => self.failures += 1,
+        }
+        self.latency = Some(match self.latency {
+            Some(l) => l.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING),
+            None => latency,
+        });
+    }
+}
+
+// The merged outcome of a query, with messages deduplicated and in timestamp order.
+#[derive(Clone, Debug)]
+pub struct StoreQueryResult {
+    pub query_id: u64,
+    pub messages: Vec<WakuMessage>,
+    pub succeeded: Vec<PeerId>,
+    pub failed: Vec<PeerId>,
+}
+
+// Requests to send and queries that are done, after a response or failure was handled.
+#[derive(Debug, Default)]
+pub struct ClientStep {
+    pub requests: Vec<(u64, PeerId, HistoryQuery)>,
+    pub completed: Option<StoreQueryResult>,
+}
+
+struct PendingQuery {
+    query: HistoryQuery,
+    strategy: QueryStrategy,
+    // Peers still to try when failing over
+    fallback: Vec<PeerId>,
+    in_flight: usize,
+    messages: BTreeMap<(i64, WakuMessageDigest), WakuMessage>,
+    pages: HashMap<PeerId, usize>,
+    succeeded: Vec<PeerId>,
+    failed: Vec<PeerId>,
+}
+
+#[derive(Default)]
+pub struct StoreClient {
+    peers: HashMap<PeerId, StorePeerStats>,
+    queries: HashMap<u64, PendingQuery>,
+    // A query has at most one request in flight per peer, as pages are fetched in turn
+    sent_at: HashMap<(u64, PeerId), Instant>,
+    next_query_id: u64,
+}
+
+impl StoreClient {
+    pub fn add_peer(&mut self, peer_id: PeerId) {
+        self.peers.entry(peer_id).or_default();
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    pub fn peer_stats(&self, peer_id: &PeerId) -> Option<&StorePeerStats> {
+        self.peers.get(peer_id)
+    }
+
+    // Best first: highest success rate, then lowest latency, with unmeasured latencies last
+    pub fn ranked_peers(&self) -> Vec<PeerId> {
+        let mut peers: Vec<(&PeerId, &StorePeerStats)> = self.peers.iter().collect();
+        peers.sort_by(|(_, a), (_, b)| {
+            b.success_rate()
+                .total_cmp(&a.success_rate())
+                .then(match (a.latency, b.latency) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (a, b) => a.is_none().cmp(&b.is_none()),
+                })
+        });
+        peers.into_iter().map(|(p, _)| *p).collect()
+    }
+
+    // Returns the requests to send, or None if there are no store peers
+    pub fn start(&mut self, query: HistoryQuery, strategy: QueryStrategy) -> Option<ClientStep> {
+        let mut peers = self.ranked_peers();
+        if peers.is_empty() {
+            return None;
+        }
+        let targets: Vec<PeerId> = match strategy {
+            QueryStrategy::FanOut(n) => peers.drain(..n.clamp(1, peers.len())).collect(),
+            QueryStrategy::FailOver => vec![peers.remove(0)],
+        };
+
+        let query_id = self.next_query_id;
+        self.next_query_id += 1;
+        let requests = targets
+            .iter()
+            .map(|p| (query_id, *p, query.clone()))
+            .collect();
+        self.queries.insert(
+            query_id,
+            PendingQuery {
+                query,
+                strategy,
+                fallback: match strategy {
+                    QueryStrategy::FanOut(_) => Vec::new(),
+                    QueryStrategy::FailOver => peers,
+                },
+                in_flight: targets.len(),
+                messages: BTreeMap::new(),
+                pages: HashMap::new(),
+                succeeded: Vec::new(),
+                failed: Vec::new(),
+            },
+        );
+        Some(ClientStep {
+            requests,
+            completed: None,
+        })
+    }
+
+    // Tracks a request sent for one of the steps
+    pub fn sent(&mut self, query_id: u64, peer_id: PeerId) {
+        self.sent_at.insert((query_id, peer_id), Instant::now());
+    }
+
+    pub fn on_response(
+        &mut self,
+        query_id: u64,
+        peer_id: PeerId,
+        response: &HistoryResponse,
+    ) -> ClientStep {
+        let sent_at = match self.sent_at.remove(&(query_id, peer_id)) {
+            Some(t) => t,
+            None => return ClientStep::default(),
+        };
+        let success = response.get_error() == HistoryResponse_Error::NONE;
+        if let Some(stats) = self.peers.get_mut(&peer_id) {
+            stats.record(success, sent_at.elapsed());
+        }
+        if !success {
+            return self.peer_failed(query_id, peer_id);
+        }
+
+        let query = match self.queries.get_mut(&query_id) {
+            Some(q) => q,
+            None => return ClientStep::default(),
+        };
+        let pubsub_topic = query.query.get_pubsub_topic();
+        for message in response.get_messages() {
+            let key = (
+                message.get_timestamp(),
+                compute_digest(pubsub_topic, message),
+            );
+            query.messages.insert(key, message.clone());
+        }
+
+        let pages = query.pages.entry(peer_id).or_default();
+        *pages += 1;
+        let paging_info = response.get_paging_info();
+        if paging_info.has_cursor() && *pages < MAX_PAGES_PER_PEER {
+            let mut next = query.query.clone();
+            next.mut_paging_info()
+                .set_cursor(paging_info.get_cursor().clone());
+            return ClientStep {
+                requests: vec![(query_id, peer_id, next)],
+                completed: None,
+            };
+        }
+
+        query.succeeded.push(peer_id);
+        query.in_flight -= 1;
+        // One complete answer is enough when failing over
+        query.fallback.clear();
+        self.complete_if_done(query_id)
+    }
+
+    pub fn on_failure(&mut self, query_id: u64, peer_id: PeerId) -> ClientStep {
+        match self.sent_at.remove(&(query_id, peer_id)) {
+            Some(sent_at) => {
+                if let Some(stats) = self.peers.get_mut(&peer_id) {
+                    stats.record(false, sent_at.elapsed());
+                }
+                self.peer_failed(query_id, peer_id)
+            }
+            None => ClientStep::default(),
+        }
+    }
+
+    fn peer_failed(&mut self, query_id: u64, peer_id: PeerId) -> ClientStep {
+        let query = match self.queries.get_mut(&query_id) {
+            Some(q) => q,
+            None => return ClientStep::default(),
+        };
+        query.failed.push(peer_id);
+        if query.strategy == QueryStrategy::FailOver && !query.fallback.is_empty() {
+            let next = query.fallback.remove(0);
+            return ClientStep {
+                requests: vec![(query_id, next, query.query.clone())],
+                completed: None,
+            };
+        }
+        query.in_flight -= 1;
+        self.complete_if_done(query_id)
+    }
+
+    fn complete_if_done(&mut self, query_id: u64) -> ClientStep {
+        match self.queries.get(&query_id) {
+            Some(query) if query.in_flight == 0 => {}
+            _ => return ClientStep::default(),
+        }
+        let query = self.queries.remove(&query_id).expect("Query is pending");
+        ClientStep {
+            requests: Vec::new(),
+            completed: Some(StoreQueryResult {
+                query_id,
+                messages: query.messages.into_values().collect(),
+                succeeded: query.succeeded,
+                failed: query.failed,
+            }),
+        }
+    }
+}
+
+pub struct WakuStoreClientBehaviour {
+    req_res: request_response::Behaviour<WakuStoreCodec>,
+    // Store peers queried by query(), and the query and peer of each request sent for it
+    client: StoreClient,
+    client_requests: HashMap<OutboundRequestId, (u64, PeerId)>,
+    events: VecDeque<WakuStoreClientEvent>,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+pub enum WakuStoreClientEvent {
+    // Responses to, and failures of, queries sent with send_history_query
+    RequestResponseBehaviour(request_response::Event<HistoryRPC, HistoryRPC>),
+    // A query sent with query() is done
+    QueryCompleted(StoreQueryResult),
+}
+
+impl NetworkBehaviour for WakuStoreClientBehaviour {
+    type ConnectionHandler = THandler<request_response::Behaviour<WakuStoreCodec>>;
+    type ToSwarm = WakuStoreClientEvent;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.req_res
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.req_res.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.req_res.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.req_res.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.req_res.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.req_res
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(ToSwarm::GenerateEvent(event));
+            }
+
+            match self.req_res.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => self.handle_event(event),
+                Poll::Ready(action) => {
+                    return Poll::Ready(
+                        action.map_out(WakuStoreClientEvent::RequestResponseBehaviour),
+                    )
+                }
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl Default for WakuStoreClientBehaviour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WakuStoreClientBehaviour {
+    // Only sends queries, store nodes answer them with WakuStoreBehaviour
+    pub fn new() -> Self {
+        Self {
+            req_res: request_response::Behaviour::with_codec(
+                WakuStoreCodec,
+                once((WakuStoreProtocol(), ProtocolSupport::Outbound)),
+                request_response::Config::default(),
+            ),
+            client: StoreClient::default(),
+            client_requests: HashMap::new(),
+            events: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    pub fn add_peer(&mut self, peer_id: PeerId) {
+        self.client.add_peer(peer_id);
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.client.remove_peer(peer_id);
+    }
+
+    pub fn peer_stats(&self, peer_id: &PeerId) -> Option<&StorePeerStats> {
+        self.client.peer_stats(peer_id)
+    }
+
+    // Queries the store peers, ranked by how they answered so far, and reports the merged
+    // results with a QueryCompleted event. Returns the query ID, or None without store peers
+    pub fn query(&mut self, query: HistoryQuery, strategy: QueryStrategy) -> Option<u64> {
+        let step = self.client.start(query, strategy)?;
+        let query_id = step.requests.first().map(|(id, _, _)| *id);
+        self.run_client_step(step);
+        query_id
+    }
+
+    // Sends a query to a single store peer, which doesn't have to be one of the store peers
+    // of query(). Its response, or failure, is reported with a RequestResponseBehaviour event
+    // carrying the returned request ID
+    pub fn send_history_query(
+        &mut self,
+        peer_id: PeerId,
+        request_id: String,
+        query: HistoryQuery,
+    ) -> OutboundRequestId {
+        info!("WakuStoreClient: sending query: {:?}", query);
+
+        let mut query_rpc = HistoryRPC::new();
+        query_rpc.set_request_id(request_id);
+        query_rpc.set_query(query);
+
+        self.req_res.send_request(&peer_id, query_rpc)
+    }
+
+    fn run_client_step(&mut self, step: ClientStep) {
+        for (query_id, peer_id, query) in step.requests {
+            let request_id = self.send_history_query(peer_id, format!("query-{}", query_id), query);
+            self.client.sent(query_id, peer_id);
+            self.client_requests.insert(request_id, (query_id, peer_id));
+        }
+        if let Some(result) = step.completed {
+            info!(
+                "WakuStoreClient: query {} returned {} messages from {} peers, {} failed",
+                result.query_id,
+                result.messages.len(),
+                result.succeeded.len(),
+                result.failed.len()
+            );
+            self.push_event(WakuStoreClientEvent::QueryCompleted(result));
+        }
+    }
+
+    fn push_event(&mut self, event: WakuStoreClientEvent) {
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn handle_event(&mut self, event: request_response::Event<HistoryRPC, HistoryRPC>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => match self.client_requests.remove(&request_id) {
+                Some((query_id, peer_id)) => {
+                    let step = self
+                        .client
+                        .on_response(query_id, peer_id, response.get_response());
+                    self.run_client_step(step)
+                }
+                None => self.push_event(WakuStoreClientEvent::RequestResponseBehaviour(
+                    request_response::Event::Message {
+                        peer,
+                        message: request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                    },
+                )),
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => match self.client_requests.remove(&request_id) {
+                Some((query_id, peer_id)) => {
+                    info!("WakuStoreClient: query to {} failed: {}", peer, error);
+                    let step = self.client.on_failure(query_id, peer_id);
+                    self.run_client_step(step)
+                }
+                None => self.push_event(WakuStoreClientEvent::RequestResponseBehaviour(
+                    request_response::Event::OutboundFailure {
+                        peer,
+                        request_id,
+                        error,
+                    },
+                )),
+            },
+            // Inbound requests are not supported, so there are none to answer
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::{
+        waku_message_pb::WakuMessage,
+        waku_store_pb::{HistoryQuery, HistoryResponse, HistoryResponse_Error},
+    };
+    use crate::waku_store::client::{QueryStrategy, StoreClient};
+    use libp2p::PeerId;
+
+    fn response(timestamps: &[i64]) -> HistoryResponse {
+        let mut response = HistoryResponse::new();
+        for t in timestamps {
+            let mut msg = WakuMessage::new();
+            msg.set_payload(t.to_le_bytes().to_vec());
+            msg.set_timestamp(*t);
+            response.mut_messages().push(msg);
+        }
+        response
+    }
+
+    fn timestamps(messages: &[WakuMessage]) -> Vec<i64> {
+        messages.iter().map(|m| m.get_timestamp()).collect()
+    }
+
+    #[test]
+    fn test_store_client() {
+        let mut client = StoreClient::default();
+        assert!(client
+            .start(HistoryQuery::new(), QueryStrategy::FailOver)
+            .is_none());
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        for p in [a, b, c] {
+            client.add_peer(p);
+        }
+
+        // Fan out merges, dedupes and orders the results of every peer
+        let step = client
+            .start(HistoryQuery::new(), QueryStrategy::FanOut(3))
+            .unwrap();
+        assert_eq!(3, step.requests.len());
+        let query_id = step.requests[0].0;
+        for p in [a, b, c] {
+            client.sent(query_id, p);
+        }
+        let mut failed = HistoryResponse::new();
+        failed.set_error(HistoryResponse_Error::INVALID_CURSOR);
+        assert!(client.on_response(query_id, a, &failed).completed.is_none());
+        assert!(client
+            .on_response(query_id, b, &response(&[3, 1]))
+            .completed
+            .is_none());
+        let result = client
+            .on_response(query_id, c, &response(&[2, 3]))
+            .completed
+            .unwrap();
+        assert_eq!(vec![1, 2, 3], timestamps(&result.messages));
+        assert_eq!(vec![a], result.failed);
+
+        // Fail over goes to the best ranked peer first, then to the next one
+        let ranked = client.ranked_peers();
+        assert_eq!(a, ranked[2]);
+        let step = client
+            .start(HistoryQuery::new(), QueryStrategy::FailOver)
+            .unwrap();
+        let (query_id, first, _) = step.requests[0].clone();
+        assert_eq!(ranked[0], first);
+        client.sent(query_id, first);
+        let step = client.on_failure(query_id, first);
+        let (_, second, _) = step.requests[0].clone();
+        assert_eq!(ranked[1], second);
+        client.sent(query_id, second);
+
+        // Pages are followed until there is no cursor
+        let mut page = response(&[5]);
+        page.mut_paging_info().mut_cursor().set_digest(vec![1]);
+        let step = client.on_response(query_id, second, &page);
+        assert!(step.requests[0].2.get_paging_info().has_cursor());
+        client.sent(query_id, second);
+        let result = client
+            .on_response(query_id, second, &response(&[4]))
+            .completed
+            .unwrap();
+        assert_eq!(vec![4, 5], timestamps(&result.messages));
+        assert_eq!(vec![second], result.succeeded);
+        assert_eq!(1, client.peer_stats(&first).unwrap().failures);
+    }
+}
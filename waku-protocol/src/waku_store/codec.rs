@@ -1,10 +1,11 @@
-use crate::{pb::waku_store_pb::HistoryRPC, waku_message::MAX_MESSAGE_SIZE};
+use crate::{
+    length_prefixed::{read_length_prefixed, write_length_prefixed},
+    pb::waku_store_pb::HistoryRPC,
+    waku_message::MAX_MESSAGE_SIZE,
+};
 use async_trait::async_trait;
 use futures::prelude::*;
-use libp2p::{
-    core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName},
-    request_response::RequestResponseCodec,
-};
+use libp2p::request_response::Codec;
 use protobuf::Message;
 use std::io;
 
@@ -17,14 +18,14 @@ pub struct WakuStoreProtocol();
 #[derive(Clone)]
 pub struct WakuStoreCodec;
 
-impl ProtocolName for WakuStoreProtocol {
-    fn protocol_name(&self) -> &[u8] {
-        STORE_PROTOCOL_ID.as_bytes()
+impl AsRef<str> for WakuStoreProtocol {
+    fn as_ref(&self) -> &str {
+        STORE_PROTOCOL_ID
     }
 }
 
 #[async_trait]
-impl RequestResponseCodec for WakuStoreCodec {
+impl Codec for WakuStoreCodec {
     type Protocol = WakuStoreProtocol;
     type Request = HistoryRPC;
     type Response = HistoryRPC;
@@ -34,8 +35,8 @@ impl RequestResponseCodec for WakuStoreCodec {
         T: AsyncRead + Unpin + Send,
     {
         let rpc_bytes = read_length_prefixed(io, MAX_STORE_RPC_SIZE).await?;
-        let rpc: HistoryRPC = protobuf::Message::parse_from_bytes(&rpc_bytes).unwrap();
-        Ok(rpc)
+        HistoryRPC::parse_from_bytes(&rpc_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     async fn read_response<T>(
@@ -47,8 +48,8 @@ impl RequestResponseCodec for WakuStoreCodec {
         T: AsyncRead + Unpin + Send,
     {
         let rpc_bytes = read_length_prefixed(io, MAX_STORE_RPC_SIZE).await?;
-        let rpc: HistoryRPC = protobuf::Message::parse_from_bytes(&rpc_bytes).unwrap();
-        Ok(rpc)
+        HistoryRPC::parse_from_bytes(&rpc_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     async fn write_request<T>(
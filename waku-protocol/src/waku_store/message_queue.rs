@@ -1,5 +1,19 @@
-use crate::pb::{waku_message_pb::WakuMessage, waku_store_pb::Index};
-use std::collections::{vec_deque::Iter, HashSet, VecDeque};
+use crate::{
+    pb::{
+        waku_message_pb::WakuMessage,
+        waku_store_pb::{
+            ContentFilter, HistoryQuery, HistoryResponse, HistoryResponse_Error, Index, PagingInfo,
+            PagingInfo_Direction,
+        },
+        waku_store_v3_pb::{StoreQueryRequest, StoreQueryResponse, WakuMessageKeyValue},
+    },
+    waku_store::{retention::MessageArchive, sync::SyncItem},
+};
+use protobuf::Message;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ops::Bound,
+};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct IndexedWakuMessage {
@@ -26,17 +40,54 @@ impl IndexedWakuMessage {
     }
 
     pub fn content_topic(&self) -> &str {
-        &self.message.get_content_topic()
+        self.message.get_content_topic()
     }
 
     pub fn message(&self) -> &WakuMessage {
         &self.message
     }
+
+    // Encoded size of the message, which is what retention by size accounts for
+    fn size(&self) -> usize {
+        self.message.compute_size() as usize
+    }
 }
 
+// Messages are ordered by sender time, falling back to the receiver time for messages
+// without a timestamp, and then by digest
+type MessageKey = (i64, Vec<u8>);
+
+fn message_key(index: &Index) -> MessageKey {
+    let time = match index.get_sender_time() {
+        0 => index.get_receiver_time(),
+        t => t,
+    };
+    (time, index.get_digest().to_vec())
+}
+
+// Default and maximum number of messages returned per page
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+// Status codes of Store v3 responses, which follow HTTP
+pub const STATUS_OK: u32 = 200;
+pub const STATUS_BAD_REQUEST: u32 = 400;
+pub const STATUS_TOO_MANY_REQUESTS: u32 = 429;
+
+// A page of messages, and the last of them if there are more results
+type Page<'a> = (Vec<&'a IndexedWakuMessage>, Option<&'a IndexedWakuMessage>);
+
 pub struct WakuMessageQueue {
-    messages: VecDeque<IndexedWakuMessage>,
-    queued_digests: HashSet<Vec<u8>>,
+    messages: BTreeMap<MessageKey, IndexedWakuMessage>,
+    // Secondary indexes into messages
+    by_digest: HashMap<Vec<u8>, MessageKey>,
+    by_receiver_time: BTreeSet<(i64, Vec<u8>)>,
+    // By sender time alone, which unlike the receiver time is the same on every store node
+    sync_items: BTreeSet<SyncItem>,
+    by_pubsub_topic: HashMap<String, BTreeSet<MessageKey>>,
+    by_content_topic: HashMap<String, HashMap<String, BTreeSet<MessageKey>>>,
+    max_messages: usize,
+    size_bytes: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,8 +98,14 @@ pub enum WakuMessageQueueErrors {
 impl WakuMessageQueue {
     pub fn new(max_messages: usize) -> Self {
         WakuMessageQueue {
-            messages: VecDeque::with_capacity(max_messages),
-            queued_digests: HashSet::new(),
+            messages: BTreeMap::new(),
+            by_digest: HashMap::new(),
+            by_receiver_time: BTreeSet::new(),
+            sync_items: BTreeSet::new(),
+            by_pubsub_topic: HashMap::new(),
+            by_content_topic: HashMap::new(),
+            max_messages,
+            size_bytes: 0,
         }
     }
 
@@ -56,10 +113,8 @@ impl WakuMessageQueue {
         &mut self,
         indexed_message: IndexedWakuMessage,
     ) -> Result<(), WakuMessageQueueErrors> {
-        if self
-            .queued_digests
-            .contains(indexed_message.index.get_digest())
-        {
+        let digest = indexed_message.index.get_digest();
+        if self.by_digest.contains_key(digest) {
             return Err(WakuMessageQueueErrors::Duplicated);
         }
 
@@ -70,43 +125,338 @@ impl WakuMessageQueue {
         //     return Err(WakuMessageQueueErrors::FutureMessageError);
         // }
 
-        self.queued_digests
-            .insert(indexed_message.index.get_digest().to_vec());
-
-        if self.messages.len() == self.messages.capacity() {
-            // drop oldest from VecDeque
-            if let Some(front) = self.messages.pop_front() {
-                // drop oldest from HashSet
-                self.queued_digests.take(front.index.get_digest());
-            }
+        if self.messages.len() == self.max_messages {
+            // drop oldest
+            self.pop_oldest();
         }
-        self.messages.push_back(indexed_message);
+
+        let key = message_key(&indexed_message.index);
+        self.by_digest.insert(digest.to_vec(), key.clone());
+        self.by_receiver_time
+            .insert((indexed_message.index.get_receiver_time(), digest.to_vec()));
+        self.sync_items
+            .insert((indexed_message.index.get_sender_time(), digest.to_vec()));
+        self.by_pubsub_topic
+            .entry(indexed_message.pubsub_topic.clone())
+            .or_default()
+            .insert(key.clone());
+        self.by_content_topic
+            .entry(indexed_message.pubsub_topic.clone())
+            .or_default()
+            .entry(indexed_message.content_topic().to_string())
+            .or_default()
+            .insert(key.clone());
+        self.size_bytes += indexed_message.size();
+        self.messages.insert(key, indexed_message);
 
         Ok(())
     }
 
-    pub fn iter(&self) -> Iter<'_, IndexedWakuMessage> {
-        self.messages.iter()
+    // Iterates over the messages in timestamp order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &IndexedWakuMessage> {
+        self.messages.values()
     }
 
     pub fn len(&self) -> usize {
         self.messages.len()
     }
 
-    // pub fn front(&self) -> Option<&IndexedWakuMessage> {
-    //     self.messages.front()
-    // }
-    //
-    // pub fn back(&self) -> Option<&IndexedWakuMessage> {
-    //     self.messages.back()
-    // }
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
 
-    pub fn get(&self, i: usize) -> Option<&IndexedWakuMessage> {
-        self.messages.get(i)
+    #[cfg(test)]
+    pub fn front(&self) -> Option<&IndexedWakuMessage> {
+        let (_, digest) = self.by_receiver_time.first()?;
+        self.get(digest)
+    }
+
+    #[cfg(test)]
+    pub fn back(&self) -> Option<&IndexedWakuMessage> {
+        let (_, digest) = self.by_receiver_time.last()?;
+        self.get(digest)
+    }
+
+    pub fn get(&self, digest: &[u8]) -> Option<&IndexedWakuMessage> {
+        self.messages.get(self.by_digest.get(digest)?)
+    }
+
+    pub fn sync_items(&self) -> &BTreeSet<SyncItem> {
+        &self.sync_items
     }
 
     pub fn has_queued_digest(&self, digest: Vec<u8>) -> bool {
-        self.queued_digests.contains(&digest)
+        self.by_digest.contains_key(&digest)
+    }
+
+    // Answers a history query with one page of messages, in chronological order. The
+    // cursor is exclusive, and the response only carries one if there are more results.
+    pub fn query(&self, query: &HistoryQuery) -> HistoryResponse {
+        let mut response = HistoryResponse::new();
+        let paging_info = query.get_paging_info();
+        let (messages, last) = match self.query_page(query) {
+            Ok(page) => page,
+            Err(error) => {
+                response.set_error(error);
+                response.set_paging_info(paging_info.clone());
+                return response;
+            }
+        };
+
+        let mut response_paging_info = PagingInfo::new();
+        response_paging_info.set_page_size(messages.len() as u64);
+        response_paging_info.set_direction(paging_info.get_direction());
+        if let Some(last) = last {
+            response_paging_info.set_cursor(last.index.clone());
+        }
+        response.set_messages(messages.into_iter().map(|m| m.message.clone()).collect());
+        response.set_paging_info(response_paging_info);
+        response
+    }
+
+    // Answers a Store v3 query. Messages are indexed by their hash, which v3 cursors are, so
+    // content queries are answered like history queries. Hash lookups ignore every filter.
+    pub fn query_v3(&self, request: &StoreQueryRequest) -> StoreQueryResponse {
+        let mut response = StoreQueryResponse::new();
+        response.set_request_id(request.get_request_id().to_string());
+        let hashes = request.get_message_hashes();
+        let page = match hashes.is_empty() {
+            true => self
+                .query_page(&history_query(request))
+                .map_err(|_| "invalid cursor"),
+            false if hashes.len() > MAX_PAGE_SIZE => Err("too many message hashes"),
+            false => Ok((hashes.iter().filter_map(|h| self.get(h)).collect(), None)),
+        };
+        let (messages, last) = match page {
+            Ok(page) => page,
+            Err(e) => {
+                response.set_status_code(STATUS_BAD_REQUEST);
+                response.set_status_desc(e.to_string());
+                return response;
+            }
+        };
+
+        response.set_status_code(STATUS_OK);
+        response.set_status_desc("OK".to_string());
+        for indexed_message in messages {
+            let mut key_value = WakuMessageKeyValue::new();
+            key_value.set_message_hash(indexed_message.index.get_digest().to_vec());
+            if request.get_include_data() {
+                key_value.set_message(indexed_message.message.clone());
+                key_value.set_pubsub_topic(indexed_message.pubsub_topic.clone());
+            }
+            response.mut_messages().push(key_value);
+        }
+        if let Some(last) = last {
+            response.set_pagination_cursor(last.index.get_digest().to_vec());
+        }
+        response
+    }
+
+    fn query_page(&self, query: &HistoryQuery) -> Result<Page<'_>, HistoryResponse_Error> {
+        let paging_info = query.get_paging_info();
+        let forward = paging_info.get_direction() == PagingInfo_Direction::FORWARD;
+        let page_size = match paging_info.get_page_size() as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+
+        let cursor_digest = paging_info.get_cursor().get_digest();
+        let cursor = match cursor_digest.is_empty() {
+            true => None,
+            false => match self.by_digest.get(cursor_digest) {
+                Some(key) => Some(key),
+                None => return Err(HistoryResponse_Error::INVALID_CURSOR),
+            },
+        };
+
+        // Time bounds are inclusive, 0 leaves them open
+        let start = (query.get_start_time(), Vec::new());
+        let end = match query.get_end_time() {
+            0 => (i64::MAX, vec![u8::MAX; 33]),
+            t => (t, vec![u8::MAX; 33]),
+        };
+        let (lower, upper) = match cursor {
+            Some(cursor) if forward && *cursor >= start => {
+                (Bound::Excluded(cursor), Bound::Included(&end))
+            }
+            Some(cursor) if !forward && *cursor <= end => {
+                (Bound::Included(&start), Bound::Excluded(cursor))
+            }
+            _ => (Bound::Included(&start), Bound::Included(&end)),
+        };
+        let empty_range = match (lower, upper) {
+            (Bound::Excluded(l), Bound::Included(u)) | (Bound::Included(l), Bound::Excluded(u)) => {
+                l >= u
+            }
+            (Bound::Included(l), Bound::Included(u)) => l > u,
+            _ => false,
+        };
+
+        // Fetches one more than the page size to tell whether there are more results
+        let limit = page_size + 1;
+        let mut keys = Vec::new();
+        if !empty_range {
+            let pubsub_topic = query.get_pubsub_topic();
+            let content_topics: HashSet<&str> = query
+                .get_content_filters()
+                .iter()
+                .map(|f| f.get_contentTopic())
+                .collect();
+            let range = (lower, upper);
+            if pubsub_topic.is_empty() && content_topics.is_empty() {
+                keys = take(
+                    self.messages.range::<MessageKey, _>(range).map(|(k, _)| k),
+                    forward,
+                    limit,
+                );
+            } else if content_topics.is_empty() {
+                if let Some(index) = self.by_pubsub_topic.get(pubsub_topic) {
+                    keys = take(index.range::<MessageKey, _>(range), forward, limit);
+                }
+            } else {
+                // Each content topic index is ordered, so the first page of the union is
+                // within the first pages of every index. Indexes are disjoint, so there is
+                // nothing to deduplicate
+                let indexes = self
+                    .by_content_topic
+                    .iter()
+                    .filter(|(p, _)| pubsub_topic.is_empty() || *p == pubsub_topic)
+                    .flat_map(|(_, topics)| content_topics.iter().filter_map(|t| topics.get(*t)));
+                for index in indexes {
+                    keys.extend(take(index.range::<MessageKey, _>(range), forward, limit));
+                }
+                keys.sort();
+                if !forward {
+                    keys.reverse();
+                }
+                keys.truncate(limit);
+            }
+        }
+
+        let more = keys.len() > page_size;
+        keys.truncate(page_size);
+        let last = match more {
+            true => keys.last().map(|k| &self.messages[*k]),
+            false => None,
+        };
+        if !forward {
+            keys.reverse();
+        }
+        Ok((keys.into_iter().map(|k| &self.messages[k]).collect(), last))
+    }
+
+    fn remove(&mut self, digest: &[u8]) -> Option<IndexedWakuMessage> {
+        let key = self.by_digest.remove(digest)?;
+        let removed = self.messages.remove(&key)?;
+        self.by_receiver_time
+            .remove(&(removed.index.get_receiver_time(), digest.to_vec()));
+        self.sync_items
+            .remove(&(removed.index.get_sender_time(), digest.to_vec()));
+        if let Some(index) = self.by_pubsub_topic.get_mut(&removed.pubsub_topic) {
+            index.remove(&key);
+            if index.is_empty() {
+                self.by_pubsub_topic.remove(&removed.pubsub_topic);
+            }
+        }
+        if let Some(topics) = self.by_content_topic.get_mut(&removed.pubsub_topic) {
+            if let Some(index) = topics.get_mut(removed.content_topic()) {
+                index.remove(&key);
+                if index.is_empty() {
+                    topics.remove(removed.content_topic());
+                }
+            }
+            if topics.is_empty() {
+                self.by_content_topic.remove(&removed.pubsub_topic);
+            }
+        }
+        self.size_bytes -= removed.size();
+        Some(removed)
+    }
+
+    fn topic_index(
+        &self,
+        pubsub_topic: &str,
+        content_topic: Option<&str>,
+    ) -> Option<&BTreeSet<MessageKey>> {
+        match content_topic {
+            Some(content_topic) => self.by_content_topic.get(pubsub_topic)?.get(content_topic),
+            None => self.by_pubsub_topic.get(pubsub_topic),
+        }
+    }
+}
+
+// The history query a Store v3 content query translates to
+fn history_query(request: &StoreQueryRequest) -> HistoryQuery {
+    let mut query = HistoryQuery::new();
+    query.set_pubsub_topic(request.get_pubsub_topic().to_string());
+    for content_topic in request.get_content_topics() {
+        let mut filter = ContentFilter::new();
+        filter.set_contentTopic(content_topic.clone());
+        query.mut_content_filters().push(filter);
+    }
+    query.set_start_time(request.get_time_start());
+    query.set_end_time(request.get_time_end());
+    let paging_info = query.mut_paging_info();
+    paging_info.set_page_size(request.get_pagination_limit());
+    paging_info.set_direction(match request.get_pagination_forward() {
+        true => PagingInfo_Direction::FORWARD,
+        false => PagingInfo_Direction::BACKWARD,
+    });
+    if !request.get_pagination_cursor().is_empty() {
+        paging_info
+            .mut_cursor()
+            .set_digest(request.get_pagination_cursor().to_vec());
+    }
+    query
+}
+
+// Takes the first n keys of an ordered iterator, from the back when going backward
+fn take<'a>(
+    keys: impl DoubleEndedIterator<Item = &'a MessageKey>,
+    forward: bool,
+    n: usize,
+) -> Vec<&'a MessageKey> {
+    match forward {
+        true => keys.take(n).collect(),
+        false => keys.rev().take(n).collect(),
+    }
+}
+
+// Retention evicts by receiver time, which is what by_receiver_time is ordered by
+impl MessageArchive for WakuMessageQueue {
+    fn size_bytes(&self) -> usize {
+        self.size_bytes
+    }
+
+    fn topic_len(&self, pubsub_topic: &str, content_topic: Option<&str>) -> usize {
+        self.topic_index(pubsub_topic, content_topic)
+            .map_or(0, |index| index.len())
+    }
+
+    fn oldest_receiver_time(&self) -> Option<i64> {
+        self.by_receiver_time.first().map(|(t, _)| *t)
+    }
+
+    fn pop_oldest(&mut self) -> bool {
+        match self.by_receiver_time.first() {
+            Some((_, digest)) => {
+                let digest = digest.clone();
+                self.remove(&digest).is_some()
+            }
+            None => false,
+        }
+    }
+
+    fn pop_oldest_in_topic(&mut self, pubsub_topic: &str, content_topic: Option<&str>) -> bool {
+        let digest = match self
+            .topic_index(pubsub_topic, content_topic)
+            .and_then(|index| index.first())
+        {
+            Some((_, digest)) => digest.clone(),
+            None => return false,
+        };
+        self.remove(&digest).is_some()
     }
 }
 
@@ -114,9 +464,13 @@ impl WakuMessageQueue {
 mod tests {
     use crate::pb::waku_message_pb::WakuMessage;
     use crate::waku_store::message_queue::{
-        IndexedWakuMessage, WakuMessageQueue, WakuMessageQueueErrors,
+        IndexedWakuMessage, WakuMessageQueue, WakuMessageQueueErrors, STATUS_BAD_REQUEST, STATUS_OK,
+    };
+    use crate::waku_store::network_behaviour::{compute_digest, compute_index};
+    use crate::waku_store::{
+        ContentFilter, HistoryQuery, HistoryResponse_Error, PagingInfo, PagingInfo_Direction,
+        StoreQueryRequest, StoreQueryResponse,
     };
-    use crate::waku_store::network_behaviour::compute_index;
 
     fn create_indexed_message(
         payload: Vec<u8>,
@@ -126,7 +480,7 @@ mod tests {
         let mut msg = WakuMessage::new();
         msg.set_payload(payload);
         msg.set_content_topic(content_topic);
-        IndexedWakuMessage::new(msg.clone(), compute_index(msg.clone()), pubsub_topic)
+        IndexedWakuMessage::new(msg.clone(), compute_index(&pubsub_topic, msg), pubsub_topic)
     }
 
     #[test]
@@ -182,4 +536,178 @@ mod tests {
         assert_eq!(&indexed_msg4, msg_queue.back().unwrap());
         assert!(!msg_queue.has_queued_digest(indexed_msg1.index.digest));
     }
+
+    fn query(
+        queue: &WakuMessageQueue,
+        content_topics: &[&str],
+        cursor: Option<&IndexedWakuMessage>,
+        forward: bool,
+        time_range: (i64, i64),
+    ) -> (Vec<i64>, bool) {
+        let mut query = HistoryQuery::new();
+        query.set_pubsub_topic("pubsub".to_string());
+        for t in content_topics {
+            let mut filter = ContentFilter::new();
+            filter.set_contentTopic(t.to_string());
+            query.mut_content_filters().push(filter);
+        }
+        query.set_start_time(time_range.0);
+        query.set_end_time(time_range.1);
+        let mut paging_info = PagingInfo::new();
+        paging_info.set_page_size(3);
+        if let Some(cursor) = cursor {
+            paging_info.set_cursor(cursor.index().clone());
+        }
+        paging_info.set_direction(match forward {
+            true => PagingInfo_Direction::FORWARD,
+            false => PagingInfo_Direction::BACKWARD,
+        });
+        query.set_paging_info(paging_info);
+
+        let response = queue.query(&query);
+        assert_eq!(HistoryResponse_Error::NONE, response.get_error());
+        let timestamps = response.get_messages().iter().map(|m| m.get_timestamp());
+        (
+            timestamps.collect(),
+            response.get_paging_info().has_cursor(),
+        )
+    }
+
+    #[test]
+    fn test_query() {
+        let mut queue = WakuMessageQueue::new(100);
+        let mut messages = Vec::new();
+        // Pushed out of order, queries return them by timestamp
+        for t in [5, 1, 8, 3, 9, 2, 7, 4, 6, 10] {
+            let content_topic = if t % 2 == 0 { "even" } else { "odd" };
+            let mut msg = WakuMessage::new();
+            msg.set_payload(vec![t as u8]);
+            msg.set_content_topic(content_topic.to_string());
+            msg.set_timestamp(t);
+            let indexed = IndexedWakuMessage::new(
+                msg.clone(),
+                compute_index("pubsub", msg),
+                "pubsub".to_string(),
+            );
+            queue.push(indexed.clone()).unwrap();
+            messages.push((t, indexed));
+        }
+        let message = |t: i64| &messages.iter().find(|(m, _)| *m == t).unwrap().1;
+
+        assert_eq!(
+            (vec![1, 2, 3], true),
+            query(&queue, &[], None, true, (0, 0))
+        );
+        assert_eq!(
+            (vec![4, 5, 6], true),
+            query(&queue, &[], Some(message(3)), true, (0, 0))
+        );
+        assert_eq!(
+            (vec![8, 9, 10], true),
+            query(&queue, &[], None, false, (0, 0))
+        );
+        assert_eq!(
+            (vec![5, 6, 7], true),
+            query(&queue, &[], Some(message(8)), false, (0, 0))
+        );
+        assert_eq!(
+            (vec![7, 9], false),
+            query(&queue, &["odd"], Some(message(5)), true, (0, 0))
+        );
+        assert_eq!(
+            (vec![4, 6, 8], false),
+            query(&queue, &["even", "other"], None, true, (3, 8))
+        );
+        assert_eq!(
+            (vec![4, 5, 6], true),
+            query(&queue, &["even", "odd"], None, false, (2, 6))
+        );
+        assert_eq!(
+            (vec![], false),
+            query(&queue, &[], Some(message(10)), true, (0, 0))
+        );
+
+        let mut query = HistoryQuery::new();
+        query.mut_paging_info().mut_cursor().set_digest(vec![0; 32]);
+        assert_eq!(
+            HistoryResponse_Error::INVALID_CURSOR,
+            queue.query(&query).get_error()
+        );
+
+        // Store v3 content queries page by message hash
+        let mut request = StoreQueryRequest::new();
+        request.set_pubsub_topic("pubsub".to_string());
+        request.set_content_topics(vec!["odd".to_string()].into());
+        request.set_pagination_limit(2);
+        request.set_pagination_forward(true);
+        request.set_include_data(true);
+        let response = queue.query_v3(&request);
+        assert_eq!(STATUS_OK, response.get_status_code());
+        assert_eq!(vec![1, 3], v3_timestamps(&response));
+        assert_eq!(
+            message(3).index().get_digest(),
+            response.get_pagination_cursor()
+        );
+        request.set_pagination_cursor(response.get_pagination_cursor().to_vec());
+        assert_eq!(vec![5, 7], v3_timestamps(&queue.query_v3(&request)));
+        request.set_pagination_cursor(vec![0; 32]);
+        assert_eq!(
+            STATUS_BAD_REQUEST,
+            queue.query_v3(&request).get_status_code()
+        );
+
+        // Hash lookups return the stored messages among the queried hashes, in query order
+        let mut request = StoreQueryRequest::new();
+        request.set_message_hashes(
+            vec![
+                message(4).index().get_digest().to_vec(),
+                vec![0; 32],
+                message(2).index().get_digest().to_vec(),
+            ]
+            .into(),
+        );
+        let response = queue.query_v3(&request);
+        let hashes: Vec<&[u8]> = response
+            .get_messages()
+            .iter()
+            .map(|m| m.get_message_hash())
+            .collect();
+        assert_eq!(
+            vec![
+                message(4).index().get_digest(),
+                message(2).index().get_digest()
+            ],
+            hashes
+        );
+        // Without include_data, only the hashes are returned
+        assert!(response.get_messages().iter().all(|m| !m.has_message()));
+
+        request.set_message_hashes(vec![vec![0; 32]; 101].into());
+        assert_eq!(
+            STATUS_BAD_REQUEST,
+            queue.query_v3(&request).get_status_code()
+        );
+    }
+
+    fn v3_timestamps(response: &StoreQueryResponse) -> Vec<i64> {
+        response
+            .get_messages()
+            .iter()
+            .map(|m| m.get_message().get_timestamp())
+            .collect()
+    }
+
+    #[test]
+    fn test_compute_digest() {
+        // Test vector of 14/WAKU2-MESSAGE
+        let mut msg = WakuMessage::new();
+        msg.set_payload(hex::decode("010203045445535405060708").unwrap());
+        msg.set_content_topic("/waku/2/default-content/proto".to_string());
+        msg.set_meta(hex::decode("73757065722d736563726574").unwrap());
+        msg.set_timestamp(0x175789bfa23f8400);
+        assert_eq!(
+            "64cce733fed134e83da02b02c6f689814872b1a0ac97ea56b76095c3c72bfe05",
+            hex::encode(compute_digest("/waku/2/default-waku/proto", &msg))
+        );
+    }
 }
@@ -1,3 +1,14 @@
+pub mod client;
 mod codec;
-mod message_queue;
+pub mod message_queue;
 pub mod network_behaviour;
+mod query_codec;
+pub mod retention;
+pub mod sync;
+mod sync_codec;
+
+pub use crate::pb::waku_store_pb::{
+    ContentFilter, HistoryQuery, HistoryResponse, HistoryResponse_Error, Index, PagingInfo,
+    PagingInfo_Direction,
+};
+pub use crate::pb::waku_store_v3_pb::{StoreQueryRequest, StoreQueryResponse, WakuMessageKeyValue};
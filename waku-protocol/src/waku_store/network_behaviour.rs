@@ -5,55 +5,124 @@ use crate::{
             ContentFilter, HistoryQuery, HistoryRPC, HistoryResponse, HistoryResponse_Error, Index,
             PagingInfo, PagingInfo_Direction,
         },
+        waku_store_sync_pb::{SyncMessage, SyncRPC, SyncRange},
+        waku_store_v3_pb::{StoreQueryRequest, StoreQueryResponse},
     },
-    waku_relay::network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
+    rate_limit::{PeerRateLimiter, RateLimit, RequestMetrics},
+    waku_relay::{
+        config::WakuRelayConfig,
+        network_behaviour::{WakuRelayBehaviour, WakuRelayEvent},
+        validation::{ValidationResult, WakuMessageValidator},
+    },
+    waku_rln_relay::rln::WakuRlnRelay,
     waku_store::{
         codec::{WakuStoreCodec, WakuStoreProtocol},
-        message_queue::{IndexedWakuMessage, WakuMessageQueue},
+        message_queue::{
+            IndexedWakuMessage, WakuMessageQueue, MAX_PAGE_SIZE, STATUS_TOO_MANY_REQUESTS,
+        },
+        query_codec::{WakuStoreQueryCodec, WakuStoreQueryProtocol},
+        retention::RetentionPolicy,
+        sync::{initial_ranges, reconcile, Differences},
+        sync_codec::{WakuStoreSyncCodec, WakuStoreSyncProtocol},
     },
 };
+use futures::FutureExt;
+use futures_timer::Delay;
 use libp2p::{
-    gossipsub::{
-        error::{PublishError, SubscriptionError},
-        GossipsubEvent, MessageId,
-    },
-    request_response::{
-        ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
-        RequestResponseMessage,
-    },
+    core::{transport::PortUse, Endpoint},
+    gossipsub::{self, MessageId, PublishError, SubscriptionError},
+    request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel},
     swarm::{
-        NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters,
+        behaviour::ConnectionEstablished, ConnectionDenied, ConnectionId, FromSwarm,
+        NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
     },
-    Multiaddr, NetworkBehaviour, PeerId,
+    Multiaddr, PeerId,
 };
 use log::info;
+use prometheus_client::registry::Registry;
 use protobuf::{Message, RepeatedField};
 use sha2::{Digest, Sha256};
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     iter::once,
-    task::{Context, Poll},
-    time::{SystemTime, UNIX_EPOCH},
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+// How often retention policies are enforced, in addition to after every insert
+const RETENTION_INTERVAL: Duration = Duration::from_secs(30);
+// Labels of the store protocols in the request metrics
+const PROTOCOL_LABEL: &str = "store";
+const SYNC_PROTOCOL_LABEL: &str = "store_sync";
+// Resume starts this much before the last archived message, to make up for clock skew
+// between the store nodes. Overlapping messages are deduplicated
+const RESUME_OFFSET: Duration = Duration::from_secs(20);
+// Resume doesn't look further back than this, nor when nothing was archived yet
+pub const DEFAULT_RESUME_WINDOW: Duration = Duration::from_secs(6 * 60 * 60);
+// Pages resumed per peer and topic, to bound peers that never run out of cursors
+const MAX_RESUME_PAGES: usize = 100;
+// How often archives are reconciled with the sync peers, in addition to on connection
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// What became of the messages of an imported archive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveImportCounts {
+    pub imported: usize,
+    // Already archived
+    pub duplicates: usize,
+    // Failed validation, or ephemeral
+    pub rejected: usize,
+}
+
+// Sub-behaviours of WakuStoreBehaviour. Their events are intercepted and handled by
+// WakuStoreBehaviour::poll before anything is reported to the Swarm.
 #[derive(NetworkBehaviour)]
-#[behaviour(
-    event_process = true,
-    out_event = "WakuStoreEvent",
-    poll_method = "poll"
-)]
-pub struct WakuStoreBehaviour {
-    req_res: RequestResponse<WakuStoreCodec>,
+#[behaviour(to_swarm = "WakuStoreEvent")]
+pub struct WakuStoreInner {
+    req_res: request_response::Behaviour<WakuStoreCodec>,
+    sync: request_response::Behaviour<WakuStoreSyncCodec>,
+    query_v3: request_response::Behaviour<WakuStoreQueryCodec>,
     relay: WakuRelayBehaviour, // todo: Either filter
-    #[behaviour(ignore)]
+}
+
+pub struct WakuStoreBehaviour {
+    inner: WakuStoreInner,
     message_queue: WakuMessageQueue,
-    #[behaviour(ignore)]
-    events: Vec<WakuStoreEvent>,
+    retention_policies: Vec<Box<dyn RetentionPolicy>>,
+    retention_timer: Delay,
+    rate_limiter: PeerRateLimiter,
+    metrics: RequestMetrics,
+    // Subscribed pubsub topics, and the timestamp of the newest message archived for each
+    topics: HashSet<String>,
+    last_archived: HashMap<String, i64>,
+    // Store peers that history is backfilled from whenever a connection to them is established
+    resume_peers: HashSet<PeerId>,
+    resume_window: Duration,
+    // Each resume query, and how many pages were requested for it
+    resume_queries: HashMap<OutboundRequestId, (HistoryQuery, usize)>,
+    // Store peers whose archives are kept in sync with this one, see sync()
+    sync_peers: HashSet<PeerId>,
+    sync_interval: Duration,
+    sync_timer: Delay,
+    sync_sessions: HashMap<OutboundRequestId, SyncSession>,
+    events: VecDeque<WakuStoreEvent>,
+    waker: Option<Waker>,
 }
 
 #[derive(Debug)]
 pub enum WakuStoreEvent {
     WakuRelayBehaviour(WakuRelayEvent),
-    RequestResponseBehaviour(RequestResponseEvent<HistoryRPC, HistoryRPC>),
+    RequestResponseBehaviour(request_response::Event<HistoryRPC, HistoryRPC>),
+    SyncBehaviour(request_response::Event<SyncRPC, SyncRPC>),
+    QueryV3Behaviour(request_response::Event<StoreQueryRequest, StoreQueryResponse>),
+}
+
+// A reconciliation this node initiated, and the transfers it still has to make
+struct SyncSession {
+    peer_id: PeerId,
+    differences: Differences,
+    sent: usize,
+    received: usize,
 }
 
 impl From<WakuRelayEvent> for WakuStoreEvent {
@@ -62,131 +131,140 @@ impl From<WakuRelayEvent> for WakuStoreEvent {
     }
 }
 
-impl From<RequestResponseEvent<HistoryRPC, HistoryRPC>> for WakuStoreEvent {
-    fn from(event: RequestResponseEvent<HistoryRPC, HistoryRPC>) -> Self {
+impl From<request_response::Event<HistoryRPC, HistoryRPC>> for WakuStoreEvent {
+    fn from(event: request_response::Event<HistoryRPC, HistoryRPC>) -> Self {
         Self::RequestResponseBehaviour(event)
     }
 }
 
-impl NetworkBehaviourEventProcess<WakuRelayEvent> for WakuStoreBehaviour {
-    fn inject_event(&mut self, event: WakuRelayEvent) {
-        if let WakuRelayEvent::GossipSub(GossipsubEvent::Message {
-            propagation_source,
-            message_id,
-            message,
-        }) = event
-        {
-            let topic = message.topic.to_string();
-            let mut waku_message = WakuMessage::new();
-            waku_message.merge_from_bytes(&message.data).unwrap();
-            let indexed_message = IndexedWakuMessage::new(
-                waku_message.clone(),
-                compute_index(waku_message.clone()),
-                topic.clone(),
-            );
-            info!(
-                "WakuStore: message received via WakuRelay: {:?}",
-                indexed_message
-            );
-            match self.message_queue.push(indexed_message) {
-                Ok(_) => info!("WakuStore: successfully queued message"),
-                Err(e) => info!("WakuStore: not queueing message: {:?}", e),
-            };
-            self.events.push(WakuStoreEvent::WakuRelayBehaviour(
-                WakuRelayEvent::GossipSub(GossipsubEvent::Message {
-                    propagation_source,
-                    message_id,
-                    message,
-                }),
-            ));
-        }
+impl From<request_response::Event<SyncRPC, SyncRPC>> for WakuStoreEvent {
+    fn from(event: request_response::Event<SyncRPC, SyncRPC>) -> Self {
+        Self::SyncBehaviour(event)
     }
 }
 
-impl NetworkBehaviourEventProcess<RequestResponseEvent<HistoryRPC, HistoryRPC>>
-    for WakuStoreBehaviour
-{
-    fn inject_event(&mut self, event: RequestResponseEvent<HistoryRPC, HistoryRPC>) {
-        if let RequestResponseEvent::Message {
-            peer: _,
-            message:
-                RequestResponseMessage::Request {
-                    channel, request, ..
-                },
-        } = event
-        {
-            let request_id = request.get_request_id();
-            let query = request.get_query();
-            info!(
-                "WakuStore: received request. Request ID: {}, Query: {:?}",
-                request_id, query
-            );
+impl From<request_response::Event<StoreQueryRequest, StoreQueryResponse>> for WakuStoreEvent {
+    fn from(event: request_response::Event<StoreQueryRequest, StoreQueryResponse>) -> Self {
+        Self::QueryV3Behaviour(event)
+    }
+}
 
-            let query_pubsub_topic = query.get_pubsub_topic();
-            let query_content_filters = query.get_content_filters();
-            let query_paging_info = query.get_paging_info();
-            let query_page_size = query_paging_info.get_page_size();
-            let query_direction = query_paging_info.get_direction();
-            let query_cursor = query.get_paging_info().get_cursor();
-            let query_digest = query_cursor.get_digest().to_vec();
+impl NetworkBehaviour for WakuStoreBehaviour {
+    type ConnectionHandler = THandler<WakuStoreInner>;
+    type ToSwarm = WakuStoreEvent;
 
-            let mut response = HistoryResponse::new();
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
 
-            if !self.message_queue.has_queued_digest(query_digest) {
-                info!("WakuStore: query not found");
-                response.set_error(HistoryResponse_Error::INVALID_CURSOR);
-            } else {
-                let mut res_messages = Vec::new();
-                let mut j = 0;
-                for (i, indexed_message) in self.message_queue.iter().enumerate() {
-                    if indexed_message.index() == query_cursor {
-                        j = i;
-                    }
-                }
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
 
-                let mut page_count = 0;
-                let dir: i32 = match query_direction {
-                    PagingInfo_Direction::FORWARD => 1,
-                    PagingInfo_Direction::BACKWARD => -1,
-                };
-                for k in 0..self.message_queue.len() {
-                    let i = (j as i32 + k as i32 * dir) % self.message_queue.len() as i32;
-                    if let Some(indexed_message) = self.message_queue.get(i as usize) {
-                        let mut cf = ContentFilter::new();
-                        cf.set_contentTopic(indexed_message.content_topic().to_string());
-                        if indexed_message.pubsub_topic() == query_pubsub_topic
-                            && query_content_filters.contains(&cf)
-                        {
-                            res_messages.push(indexed_message.message().clone());
-                            page_count = page_count + 1;
-
-                            if page_count == query_page_size {
-                                break;
-                            }
-                        }
-                    };
-                }
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
 
-                response.set_messages(RepeatedField::from_vec(res_messages));
-            }
-            response.set_paging_info(query_paging_info.clone());
-
-            let mut res_rpc = HistoryRPC::new();
-            res_rpc.set_request_id(request_id.to_string());
-            res_rpc.set_query(query.clone());
-            res_rpc.set_response(response.clone());
-
-            info!("WakuStore: sending query response: {:?}", response);
-            self.req_res.send_response(channel, res_rpc).unwrap();
-        } else if let RequestResponseEvent::Message {
-            peer: _,
-            message: RequestResponseMessage::Response { response, .. },
-        } = event
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event);
+
+        if let FromSwarm::ConnectionEstablished(ConnectionEstablished {
+            peer_id,
+            other_established: 0,
+            ..
+        }) = event
         {
-            match response.get_response().get_error() {
-                HistoryResponse_Error::INVALID_CURSOR => info!("WakuStore: failed query."),
-                HistoryResponse_Error::NONE => {
-                    info!("WakuStore: received response. {:?}", response)
+            if self.resume_peers.contains(&peer_id) {
+                self.resume(peer_id);
+            }
+            if self.sync_peers.contains(&peer_id) {
+                self.sync(peer_id);
+            }
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        // Also catches messages that aged out while no new ones arrived
+        if self.retention_timer.poll_unpin(cx).is_ready() {
+            self.enforce_retention();
+            self.retention_timer.reset(RETENTION_INTERVAL);
+        }
+        if self.sync_timer.poll_unpin(cx).is_ready() {
+            for peer_id in self.sync_peers.clone() {
+                self.sync(peer_id);
+            }
+            self.sync_timer.reset(self.sync_interval);
+        }
+
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(ToSwarm::GenerateEvent(event));
+            }
+
+            match self.inner.poll(cx) {
+                Poll::Ready(ToSwarm::GenerateEvent(event)) => self.handle_event(event),
+                Poll::Ready(action) => return Poll::Ready(action),
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
                 }
             }
         }
@@ -194,39 +272,92 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent<HistoryRPC, HistoryRPC>>
 }
 
 impl WakuStoreBehaviour {
-    pub fn new(max_messages: usize) -> Self {
+    pub fn new(max_messages: usize, relay_config: WakuRelayConfig) -> Self {
         Self {
+            inner: WakuStoreInner {
+                req_res: request_response::Behaviour::with_codec(
+                    WakuStoreCodec,
+                    once((WakuStoreProtocol(), ProtocolSupport::Full)),
+                    request_response::Config::default(),
+                ),
+                sync: request_response::Behaviour::with_codec(
+                    WakuStoreSyncCodec,
+                    once((WakuStoreSyncProtocol(), ProtocolSupport::Full)),
+                    request_response::Config::default(),
+                ),
+                query_v3: request_response::Behaviour::with_codec(
+                    WakuStoreQueryCodec,
+                    once((WakuStoreQueryProtocol(), ProtocolSupport::Full)),
+                    request_response::Config::default(),
+                ),
+                relay: WakuRelayBehaviour::new(relay_config),
+            },
             message_queue: WakuMessageQueue::new(max_messages),
-            events: Vec::new(),
-            req_res: RequestResponse::new(
-                WakuStoreCodec,
-                once((WakuStoreProtocol(), ProtocolSupport::Full)),
-                RequestResponseConfig::default(),
-            ),
-            relay: WakuRelayBehaviour::new(),
+            retention_policies: Vec::new(),
+            retention_timer: Delay::new(RETENTION_INTERVAL),
+            rate_limiter: PeerRateLimiter::new(None),
+            metrics: RequestMetrics::default(),
+            topics: HashSet::new(),
+            last_archived: HashMap::new(),
+            resume_peers: HashSet::new(),
+            resume_window: DEFAULT_RESUME_WINDOW,
+            resume_queries: HashMap::new(),
+            sync_peers: HashSet::new(),
+            sync_interval: DEFAULT_SYNC_INTERVAL,
+            sync_timer: Delay::new(DEFAULT_SYNC_INTERVAL),
+            sync_sessions: HashMap::new(),
+            events: VecDeque::new(),
+            waker: None,
         }
     }
 
-    pub fn add_store_peer(&mut self, peer_id: PeerId, peer_addr: Multiaddr) {
-        self.req_res.add_address(&peer_id, peer_addr);
-    }
-
     pub fn add_relay_peer(&mut self, peer_id: &PeerId) {
-        self.relay.add_peer(peer_id);
+        self.inner.relay.add_peer(peer_id);
     }
 
-    pub fn publish(&mut self, topic: &str, msg: WakuMessage) -> Result<MessageId, PublishError> {
-        self.relay.publish(topic, msg)
+    pub fn publish(
+        &mut self,
+        topic: &str,
+        msg: WakuMessage,
+    ) -> Result<Option<MessageId>, PublishError> {
+        self.inner.relay.publish(topic, msg)
     }
 
     pub fn subscribe(&mut self, topic: &str) -> Result<bool, SubscriptionError> {
-        self.relay.subscribe(topic)
+        self.topics.insert(topic.to_string());
+        self.inner.relay.subscribe(topic)
     }
 
     pub fn unsubscribe(&mut self, topic: &str) -> Result<bool, PublishError> {
-        self.relay.unsubscribe(topic)
+        self.topics.remove(topic);
+        self.inner.relay.unsubscribe(topic)
     }
 
+    pub fn peer_scores(&self) -> Vec<(PeerId, f64)> {
+        self.inner.relay.peer_scores()
+    }
+
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.inner.relay.subscribed_topics()
+    }
+
+    pub fn add_validator(&mut self, validator: impl WakuMessageValidator + 'static) {
+        self.inner.relay.add_validator(validator)
+    }
+
+    pub fn add_topic_validator(
+        &mut self,
+        topic: &str,
+        validator: impl WakuMessageValidator + 'static,
+    ) {
+        self.inner.relay.add_topic_validator(topic, validator)
+    }
+
+    pub fn enable_rln(&mut self, rln: WakuRlnRelay) {
+        self.inner.relay.enable_rln(rln)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn send_query(
         &mut self,
         peer_id: PeerId,
@@ -236,7 +367,7 @@ impl WakuStoreBehaviour {
         direction: bool,
         pubsub_topic: String,
         content_topic: Vec<String>,
-    ) {
+    ) -> OutboundRequestId {
         let mut query = HistoryQuery::new();
         query.set_pubsub_topic(pubsub_topic);
 
@@ -256,53 +387,786 @@ impl WakuStoreBehaviour {
             content_filters.push(c);
         }
         query.set_content_filters(content_filters);
+
+        self.send_history_query(peer_id, request_id, query)
+    }
+
+    // Asks a store peer which of these message hashes it has, see compute_digest. The
+    // response lists the ones it has, with a QueryV3Behaviour event
+    pub fn send_hash_query(
+        &mut self,
+        peer_id: PeerId,
+        request_id: String,
+        hashes: Vec<Vec<u8>>,
+    ) -> OutboundRequestId {
+        let mut request = StoreQueryRequest::new();
+        request.set_request_id(request_id);
+        request.set_message_hashes(RepeatedField::from_vec(hashes));
+        self.send_query_v3(peer_id, request)
+    }
+
+    // Sends a Store v3 query to a single store peer
+    pub fn send_query_v3(
+        &mut self,
+        peer_id: PeerId,
+        request: StoreQueryRequest,
+    ) -> OutboundRequestId {
+        info!("WakuStore: sending v3 query: {:?}", request);
+        self.inner.query_v3.send_request(&peer_id, request)
+    }
+
+    // Sends a query to a single store peer. Its response, or failure, is reported with a
+    // RequestResponseBehaviour event carrying the returned request ID
+    pub fn send_history_query(
+        &mut self,
+        peer_id: PeerId,
+        request_id: String,
+        query: HistoryQuery,
+    ) -> OutboundRequestId {
         info!("WakuStore: sending query: {:?}", query);
 
         let mut query_rpc = HistoryRPC::new();
         query_rpc.set_request_id(request_id);
         query_rpc.set_query(query);
 
-        self.req_res.send_request(&peer_id, query_rpc);
+        self.inner.req_res.send_request(&peer_id, query_rpc)
     }
 
-    fn poll(
+    // Answers a query from the local archive, as it would be answered to a peer
+    pub fn local_query(&self, query: &HistoryQuery) -> HistoryResponse {
+        self.message_queue.query(query)
+    }
+
+    // Backfills the subscribed topics from this peer every time the node connects to it,
+    // e.g. after a restart or after losing connectivity
+    pub fn add_resume_peer(&mut self, peer_id: PeerId) {
+        self.resume_peers.insert(peer_id);
+    }
+
+    pub fn set_resume_window(&mut self, window: Duration) {
+        self.resume_window = window;
+    }
+
+    // Queries a store peer for what was published on the subscribed topics since the last
+    // archived message. Pages are requested as responses arrive, see handle_resume_response
+    pub fn resume(&mut self, peer_id: PeerId) {
+        let now = now();
+        let earliest = now - self.resume_window.as_nanos() as i64;
+        for topic in self.topics.clone() {
+            let start_time = match self.last_archived.get(&topic) {
+                Some(t) => (t - RESUME_OFFSET.as_nanos() as i64).max(earliest),
+                None => earliest,
+            };
+            info!(
+                "WakuStore: resuming {} from {} since {}",
+                topic, peer_id, start_time
+            );
+
+            let mut query = HistoryQuery::new();
+            query.set_pubsub_topic(topic);
+            query.set_start_time(start_time);
+            query.set_end_time(now);
+            query
+                .mut_paging_info()
+                .set_direction(PagingInfo_Direction::FORWARD);
+            query.mut_paging_info().set_page_size(MAX_PAGE_SIZE as u64);
+            self.send_resume_query(peer_id, query, 1);
+        }
+    }
+
+    fn send_resume_query(&mut self, peer_id: PeerId, query: HistoryQuery, pages: usize) {
+        let mut query_rpc = HistoryRPC::new();
+        query_rpc.set_request_id(format!("resume-{}", query.get_pubsub_topic()));
+        query_rpc.set_query(query.clone());
+        let request_id = self.inner.req_res.send_request(&peer_id, query_rpc);
+        self.resume_queries.insert(request_id, (query, pages));
+    }
+
+    // Archives a page of resumed messages that pass relay validation, and requests the next
+    fn handle_resume_response(
         &mut self,
-        _: &mut Context,
-        _: &mut impl PollParameters,
-    ) -> Poll<
-        NetworkBehaviourAction<
-            <Self as NetworkBehaviour>::OutEvent,
-            <Self as NetworkBehaviour>::ConnectionHandler,
-        >,
-    > {
-        if !self.events.is_empty() {
-            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(self.events.remove(0)));
+        peer: PeerId,
+        (mut query, pages): (HistoryQuery, usize),
+        rpc: HistoryRPC,
+    ) {
+        let response = rpc.get_response();
+        if response.get_error() != HistoryResponse_Error::NONE {
+            info!(
+                "WakuStore: resume from {} failed: {:?}",
+                peer,
+                response.get_error()
+            );
+            return;
+        }
+
+        let topic = query.get_pubsub_topic().to_string();
+        let archived = response
+            .get_messages()
+            .iter()
+            .filter(|m| self.archive_validated(m, &topic, &peer))
+            .count();
+        info!(
+            "WakuStore: resumed {} of {} messages on {} from {}",
+            archived,
+            response.get_messages().len(),
+            topic,
+            peer
+        );
+        if archived > 0 {
+            self.enforce_retention();
+        }
+
+        if !response.get_paging_info().has_cursor() {
+            return;
+        }
+        if pages >= MAX_RESUME_PAGES {
+            info!(
+                "WakuStore: stopped resuming {} from {} after {} pages",
+                topic, peer, pages
+            );
+            return;
+        }
+        query
+            .mut_paging_info()
+            .set_cursor(response.get_paging_info().get_cursor().clone());
+        self.send_resume_query(peer, query, pages + 1);
+    }
+
+    // Reconciles the archive with this peer's periodically and on every new connection
+    pub fn add_sync_peer(&mut self, peer_id: PeerId) {
+        self.sync_peers.insert(peer_id);
+    }
+
+    pub fn set_sync_interval(&mut self, interval: Duration) {
+        self.sync_interval = interval;
+        self.sync_timer.reset(interval);
+    }
+
+    // Starts reconciling the archive with a store peer, see the sync module. Each round
+    // carries the ranges left to reconcile and the messages found missing on either side,
+    // until there is nothing left.
+    pub fn sync(&mut self, peer_id: PeerId) {
+        if self.sync_sessions.values().any(|s| s.peer_id == peer_id) {
+            return;
+        }
+        info!("WakuStore: syncing with {}", peer_id);
+        let session = SyncSession {
+            peer_id,
+            differences: Differences::default(),
+            sent: 0,
+            received: 0,
+        };
+        let ranges = initial_ranges(self.message_queue.sync_items());
+        self.send_sync_round(session, ranges);
+    }
+
+    fn send_sync_round(&mut self, mut session: SyncSession, ranges: Vec<SyncRange>) {
+        let have = &mut session.differences.have;
+        let need = &mut session.differences.need;
+        let messages = self.sync_messages(have.drain(..have.len().min(MAX_PAGE_SIZE)));
+        let wanted: Vec<Vec<u8>> = need.drain(..need.len().min(MAX_PAGE_SIZE)).collect();
+        if ranges.is_empty() && messages.is_empty() && wanted.is_empty() {
+            info!(
+                "WakuStore: synced with {}, sent {} and received {} messages",
+                session.peer_id, session.sent, session.received
+            );
+            return;
+        }
+        session.sent += messages.len();
+
+        let mut rpc = SyncRPC::new();
+        rpc.set_ranges(RepeatedField::from_vec(ranges));
+        rpc.set_messages(RepeatedField::from_vec(messages));
+        rpc.set_wanted(RepeatedField::from_vec(wanted));
+        let request_id = self.inner.sync.send_request(&session.peer_id, rpc);
+        self.sync_sessions.insert(request_id, session);
+    }
+
+    fn sync_messages(&self, hashes: impl Iterator<Item = Vec<u8>>) -> Vec<SyncMessage> {
+        hashes
+            .filter_map(|hash| self.message_queue.get(&hash))
+            .map(|indexed_message| {
+                let mut message = SyncMessage::new();
+                message.set_pubsub_topic(indexed_message.pubsub_topic().clone());
+                message.set_message(indexed_message.message().clone());
+                message
+            })
+            .collect()
+    }
+
+    // Archives transferred messages that pass relay validation, returning how many were new
+    fn archive_sync_messages(&mut self, peer: &PeerId, messages: &[SyncMessage]) -> usize {
+        let archived = messages
+            .iter()
+            .filter(|m| self.archive_validated(m.get_message(), m.get_pubsub_topic(), peer))
+            .count();
+        if archived > 0 {
+            self.enforce_retention();
+        }
+        archived
+    }
+
+    fn handle_sync_event(&mut self, event: request_response::Event<SyncRPC, SyncRPC>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        channel, request, ..
+                    },
+            } => {
+                // Dropping the channel fails the request on the peer's side
+                if !self.sync_peers.contains(&peer) {
+                    info!("WakuStore: ignoring sync request from {}", peer);
+                    return;
+                }
+                if !self.rate_limiter.try_acquire(&peer) {
+                    info!("WakuStore: rate limiting sync request from {}", peer);
+                    self.metrics.inc_rate_limited(SYNC_PROTOCOL_LABEL);
+                    return;
+                }
+                self.metrics.inc_served(SYNC_PROTOCOL_LABEL);
+                let archived = self.archive_sync_messages(&peer, request.get_messages());
+                let ranges = reconcile(
+                    self.message_queue.sync_items(),
+                    request.get_ranges(),
+                    false,
+                    &mut Differences::default(),
+                );
+                let wanted = request.get_wanted();
+                let messages = self.sync_messages(wanted.iter().take(MAX_PAGE_SIZE).cloned());
+                info!(
+                    "WakuStore: sync round from {}, received {} and sent {} messages",
+                    peer,
+                    archived,
+                    messages.len()
+                );
+
+                let mut rpc = SyncRPC::new();
+                rpc.set_ranges(RepeatedField::from_vec(ranges));
+                rpc.set_messages(RepeatedField::from_vec(messages));
+                if self.inner.sync.send_response(channel, rpc).is_err() {
+                    info!("WakuStore: failed to send sync response to {}", peer);
+                    self.metrics.inc_response_failed(SYNC_PROTOCOL_LABEL);
+                }
+            }
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                let mut session = match self.sync_sessions.remove(&request_id) {
+                    Some(session) => session,
+                    None => return,
+                };
+                session.received += self.archive_sync_messages(&peer, response.get_messages());
+                let ranges = reconcile(
+                    self.message_queue.sync_items(),
+                    response.get_ranges(),
+                    true,
+                    &mut session.differences,
+                );
+                self.send_sync_round(session, ranges);
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } if self.sync_sessions.remove(&request_id).is_some() => {
+                info!("WakuStore: sync with {} failed: {}", peer, error)
+            }
+            _ => {}
+        }
+    }
+
+    // Archives a message received from a store peer if it passes relay validation, as it
+    // would have had it been relayed
+    fn archive_validated(
+        &mut self,
+        message: &WakuMessage,
+        pubsub_topic: &str,
+        peer: &PeerId,
+    ) -> bool {
+        match self.inner.relay.validate_archived(pubsub_topic, message) {
+            ValidationResult::Accept => self.archive(message, pubsub_topic),
+            result => {
+                info!(
+                    "WakuStore: dropping message on {} from {}: {:?}",
+                    pubsub_topic, peer, result
+                );
+                false
+            }
+        }
+    }
+
+    // Adds a message to the archive, returning false if it was already there or is ephemeral
+    fn archive(&mut self, message: &WakuMessage, pubsub_topic: &str) -> bool {
+        if message.get_ephemeral() {
+            return false;
+        }
+        let indexed_message = IndexedWakuMessage::new(
+            message.clone(),
+            compute_index(pubsub_topic, message.clone()),
+            pubsub_topic.to_string(),
+        );
+        self.insert(indexed_message)
+    }
+
+    fn insert(&mut self, indexed_message: IndexedWakuMessage) -> bool {
+        let time = match indexed_message.index().get_sender_time() {
+            0 => indexed_message.index().get_receiver_time(),
+            t => t,
+        };
+        let pubsub_topic = indexed_message.pubsub_topic().clone();
+        match self.message_queue.push(indexed_message) {
+            Ok(_) => {
+                let last = self.last_archived.entry(pubsub_topic).or_default();
+                *last = time.max(*last);
+                true
+            }
+            Err(e) => {
+                info!("WakuStore: not queueing message: {:?}", e);
+                false
+            }
+        }
+    }
+
+    // Every archived message, in timestamp order
+    pub fn export(&self) -> Vec<IndexedWakuMessage> {
+        self.message_queue.iter().cloned().collect()
+    }
+
+    // Archives messages with their original index, skipping the ones already archived.
+    // Archives are not trusted: messages are validated like resumed and synced ones, so
+    // that an import can't plant messages that relay would have rejected
+    pub fn import(&mut self, messages: Vec<IndexedWakuMessage>) -> ArchiveImportCounts {
+        let mut counts = ArchiveImportCounts::default();
+        for indexed_message in messages {
+            let (message, pubsub_topic) =
+                (indexed_message.message(), indexed_message.pubsub_topic());
+            let result = match message.get_ephemeral() {
+                true => ValidationResult::Reject,
+                false => self.inner.relay.validate_archived(pubsub_topic, message),
+            };
+            if result != ValidationResult::Accept {
+                info!(
+                    "WakuStore: rejecting imported message on {}: {:?}",
+                    pubsub_topic, result
+                );
+                counts.rejected += 1;
+            } else if self.insert(indexed_message) {
+                counts.imported += 1;
+            } else {
+                counts.duplicates += 1;
+            }
+        }
+        if counts.imported > 0 {
+            self.enforce_retention();
+        }
+        counts
+    }
+
+    pub fn add_retention_policy(&mut self, policy: impl RetentionPolicy + 'static) {
+        self.retention_policies.push(Box::new(policy));
+    }
+
+    fn enforce_retention(&mut self) {
+        let now = now();
+        for policy in &self.retention_policies {
+            let evicted = policy.apply(&mut self.message_queue, now);
+            if evicted > 0 {
+                info!("WakuStore: {:?} evicted {} messages", policy, evicted);
+            }
+        }
+    }
+
+    // Limits the requests each peer can make, None to serve every request
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limiter = PeerRateLimiter::new(limit);
+    }
+
+    pub fn register_metrics(&self, registry: &mut Registry) {
+        self.metrics.register(registry);
+    }
+
+    fn push_event(&mut self, event: WakuStoreEvent) {
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn handle_event(&mut self, event: WakuStoreEvent) {
+        match event {
+            WakuStoreEvent::WakuRelayBehaviour(e) => self.handle_relay_event(e),
+            WakuStoreEvent::RequestResponseBehaviour(e) => self.handle_request_response_event(e),
+            WakuStoreEvent::SyncBehaviour(e) => self.handle_sync_event(e),
+            WakuStoreEvent::QueryV3Behaviour(e) => self.handle_query_v3_event(e),
+        }
+    }
+
+    fn handle_relay_event(&mut self, event: WakuRelayEvent) {
+        match event {
+            WakuRelayEvent::GossipSub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            }) => {
+                let topic = message.topic.to_string();
+                let mut waku_message = WakuMessage::new();
+                waku_message.merge_from_bytes(&message.data).unwrap();
+                info!(
+                    "WakuStore: message received via WakuRelay: {:?}",
+                    waku_message
+                );
+                if self.archive(&waku_message, &topic) {
+                    info!("WakuStore: successfully queued message");
+                    self.enforce_retention();
+                }
+                self.push_event(WakuStoreEvent::WakuRelayBehaviour(
+                    WakuRelayEvent::GossipSub(gossipsub::Event::Message {
+                        propagation_source,
+                        message_id,
+                        message,
+                    }),
+                ));
+            }
+            e @ WakuRelayEvent::ProvenMessagePublished { .. } => {
+                self.push_event(WakuStoreEvent::WakuRelayBehaviour(e))
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request_response_event(
+        &mut self,
+        event: request_response::Event<HistoryRPC, HistoryRPC>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        channel, request, ..
+                    },
+            } => match self.rate_limiter.try_acquire(&peer) {
+                true => {
+                    self.metrics.inc_served(PROTOCOL_LABEL);
+                    self.handle_request(channel, request)
+                }
+                false => {
+                    info!("WakuStore: rate limiting request from {}", peer);
+                    self.metrics.inc_rate_limited(PROTOCOL_LABEL);
+                    self.reject_request(channel, request)
+                }
+            },
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                if let Some(query) = self.resume_queries.remove(&request_id) {
+                    return self.handle_resume_response(peer, query, response);
+                }
+                match response.get_response().get_error() {
+                    HistoryResponse_Error::INVALID_CURSOR => info!("WakuStore: failed query."),
+                    HistoryResponse_Error::BAD_REQUEST => info!("WakuStore: query was malformed."),
+                    HistoryResponse_Error::TOO_MANY_REQUESTS => {
+                        info!("WakuStore: query rejected by the rate limit.")
+                    }
+                    HistoryResponse_Error::NONE => {
+                        info!("WakuStore: received response. {:?}", response)
+                    }
+                }
+                // Responses are reported so that clients can act on them
+                self.push_event(WakuStoreEvent::RequestResponseBehaviour(
+                    request_response::Event::Message {
+                        peer,
+                        message: request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                    },
+                ));
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } if self.resume_queries.remove(&request_id).is_some() => {
+                info!("WakuStore: resume from {} failed: {}", peer, error)
+            }
+            // Failures of queries sent with send_history_query
+            event @ request_response::Event::OutboundFailure { .. } => {
+                self.push_event(WakuStoreEvent::RequestResponseBehaviour(event))
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_query_v3_event(
+        &mut self,
+        event: request_response::Event<StoreQueryRequest, StoreQueryResponse>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        channel, request, ..
+                    },
+            } => {
+                let response = match self.rate_limiter.try_acquire(&peer) {
+                    true => {
+                        self.metrics.inc_served(PROTOCOL_LABEL);
+                        self.message_queue.query_v3(&request)
+                    }
+                    false => {
+                        info!("WakuStore: rate limiting v3 query from {}", peer);
+                        self.metrics.inc_rate_limited(PROTOCOL_LABEL);
+                        let mut response = StoreQueryResponse::new();
+                        response.set_request_id(request.get_request_id().to_string());
+                        response.set_status_code(STATUS_TOO_MANY_REQUESTS);
+                        response.set_status_desc("too many requests".to_string());
+                        response
+                    }
+                };
+                if self
+                    .inner
+                    .query_v3
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    info!("WakuStore: failed to send v3 query response to {}", peer);
+                    self.metrics.inc_response_failed(PROTOCOL_LABEL);
+                }
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { ref response, .. },
+            } => {
+                info!(
+                    "WakuStore: v3 query to {} returned {} messages, status {}",
+                    peer,
+                    response.get_messages().len(),
+                    response.get_status_code()
+                );
+                self.push_event(WakuStoreEvent::QueryV3Behaviour(event))
+            }
+            event @ request_response::Event::OutboundFailure { .. } => {
+                self.push_event(WakuStoreEvent::QueryV3Behaviour(event))
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request(&mut self, channel: ResponseChannel<HistoryRPC>, request: HistoryRPC) {
+        let request_id = request.get_request_id();
+        let query = request.get_query();
+        info!(
+            "WakuStore: received request. Request ID: {}, Query: {:?}",
+            request_id, query
+        );
+
+        let response = self.message_queue.query(query);
+        if response.get_error() == HistoryResponse_Error::INVALID_CURSOR {
+            info!("WakuStore: query not found");
+        }
+
+        let mut res_rpc = HistoryRPC::new();
+        res_rpc.set_request_id(request_id.to_string());
+        res_rpc.set_query(query.clone());
+        res_rpc.set_response(response.clone());
+
+        info!("WakuStore: sending query response: {:?}", response);
+        if self.inner.req_res.send_response(channel, res_rpc).is_err() {
+            info!("WakuStore: failed to send query response");
+            self.metrics.inc_response_failed(PROTOCOL_LABEL);
+        }
+    }
+
+    fn reject_request(&mut self, channel: ResponseChannel<HistoryRPC>, request: HistoryRPC) {
+        let mut response = HistoryResponse::new();
+        response.set_error(HistoryResponse_Error::TOO_MANY_REQUESTS);
+        response.set_paging_info(request.get_query().get_paging_info().clone());
+
+        let mut res_rpc = HistoryRPC::new();
+        res_rpc.set_request_id(request.get_request_id().to_string());
+        res_rpc.set_query(request.get_query().clone());
+        res_rpc.set_response(response);
+
+        if self.inner.req_res.send_response(channel, res_rpc).is_err() {
+            info!("WakuStore: failed to send rate limit response");
         }
-        Poll::Pending
     }
 }
 
 pub type WakuMessageDigest = Vec<u8>;
 
-// Takes a WakuMessage and returns its Index.
-pub fn compute_index(msg: WakuMessage) -> Index {
+// The deterministic message hash of 14/WAKU2-MESSAGE, which a store indexes messages by and
+// Store v3 queries look up.
+pub fn compute_digest(pubsub_topic: &str, msg: &WakuMessage) -> WakuMessageDigest {
     let mut hasher = Sha256::new();
 
-    hasher.update(msg.payload);
-    hasher.update(msg.content_topic.as_bytes());
+    hasher.update(pubsub_topic.as_bytes());
+    hasher.update(msg.get_payload());
+    hasher.update(msg.get_content_topic().as_bytes());
+    hasher.update(msg.get_meta());
+    hasher.update(msg.get_timestamp().to_be_bytes());
+
+    hasher.finalize().as_slice().to_vec()
+}
 
-    let digest: WakuMessageDigest = hasher.finalize().as_slice().to_vec();
+// Nanoseconds since the Unix epoch
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_nanos() as i64
+}
 
+// Takes a WakuMessage and the pubsub topic it was received on, and returns its Index.
+pub fn compute_index(pubsub_topic: &str, msg: WakuMessage) -> Index {
     let mut index = Index::new();
-    index.set_digest(digest);
-    index.set_receiver_time(
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_nanos() as i64,
-    );
+    index.set_digest(compute_digest(pubsub_topic, &msg));
+    index.set_receiver_time(now());
     index.set_sender_time(msg.timestamp);
-    index.set_pubsub_topic(msg.content_topic);
+    index.set_pubsub_topic(pubsub_topic.to_string());
 
     index
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::{
+        waku_message_pb::WakuMessage,
+        waku_store_pb::{HistoryQuery, HistoryRPC, PagingInfo_Direction},
+    };
+    use crate::waku_relay::config::WakuRelayConfig;
+    use crate::waku_store::{
+        message_queue::IndexedWakuMessage,
+        network_behaviour::{
+            compute_digest, compute_index, now, ArchiveImportCounts, WakuStoreBehaviour,
+            MAX_RESUME_PAGES,
+        },
+    };
+    use libp2p::PeerId;
+    use std::time::Duration;
+
+    const TOPIC: &str = "/waku/2/rs/1/0";
+
+    fn message(payload: &str, timestamp: i64) -> WakuMessage {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(payload.as_bytes().to_vec());
+        msg.set_content_topic("/toy/1/chat/proto".to_string());
+        msg.set_timestamp(timestamp);
+        msg
+    }
+
+    fn page(messages: Vec<WakuMessage>, cursor: &WakuMessage) -> HistoryRPC {
+        let mut rpc = HistoryRPC::new();
+        rpc.mut_response().set_messages(messages.into());
+        rpc.mut_response()
+            .mut_paging_info()
+            .mut_cursor()
+            .set_digest(cursor.get_payload().to_vec());
+        rpc
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut store = WakuStoreBehaviour::new(100, WakuRelayConfig::default());
+        let first = message("first", now());
+        store.archive(&first, TOPIC);
+        store.archive(&message("second", now() + 1), TOPIC);
+
+        // Cursors carry the pubsub topic of the message, not its content topic
+        let mut query = HistoryQuery::new();
+        query.set_pubsub_topic(TOPIC.to_string());
+        query.mut_paging_info().set_page_size(1);
+        query
+            .mut_paging_info()
+            .set_direction(PagingInfo_Direction::FORWARD);
+        let response = store.local_query(&query);
+        let cursor = response.get_paging_info().get_cursor();
+        assert_eq!(TOPIC, cursor.get_pubsub_topic());
+        assert_eq!(compute_digest(TOPIC, &first), cursor.get_digest());
+    }
+
+    #[test]
+    fn test_import() {
+        let mut store = WakuStoreBehaviour::new(100, WakuRelayConfig::default());
+        let indexed = |msg: WakuMessage| {
+            IndexedWakuMessage::new(msg.clone(), compute_index(TOPIC, msg), TOPIC.to_string())
+        };
+        let valid = indexed(message("valid", now()));
+        let future = indexed(message(
+            "future",
+            now() + Duration::from_secs(60 * 60).as_nanos() as i64,
+        ));
+        let mut ephemeral = message("ephemeral", now());
+        ephemeral.set_ephemeral(true);
+
+        // Imported messages are validated like resumed ones
+        let counts = store.import(vec![
+            valid.clone(),
+            future,
+            indexed(ephemeral),
+            valid.clone(),
+        ]);
+        let expected = ArchiveImportCounts {
+            imported: 1,
+            duplicates: 1,
+            rejected: 2,
+        };
+        assert_eq!(expected, counts);
+        assert_eq!(vec![valid], store.export());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut store = WakuStoreBehaviour::new(100, WakuRelayConfig::default());
+        store.subscribe(TOPIC).unwrap();
+        let peer = PeerId::random();
+        let mut query = HistoryQuery::new();
+        query.set_pubsub_topic(TOPIC.to_string());
+
+        // Resumed messages are validated like relayed ones, but may be old
+        let old = message(
+            "old",
+            now() - Duration::from_secs(60 * 60).as_nanos() as i64,
+        );
+        let future = message(
+            "future",
+            now() + Duration::from_secs(60 * 60).as_nanos() as i64,
+        );
+        let mut ephemeral = message("ephemeral", now());
+        ephemeral.set_ephemeral(true);
+        let rpc = page(vec![old.clone(), future, ephemeral], &old);
+        store.handle_resume_response(peer, (query.clone(), 1), rpc);
+        let archived: Vec<WakuMessage> =
+            store.export().iter().map(|m| m.message().clone()).collect();
+        assert_eq!(vec![old.clone()], archived);
+
+        // The next page is requested, up to MAX_RESUME_PAGES
+        assert_eq!(
+            vec![2],
+            store
+                .resume_queries
+                .values()
+                .map(|(_, p)| *p)
+                .collect::<Vec<_>>()
+        );
+        store.resume_queries.clear();
+        let rpc = page(vec![message("last", now())], &old);
+        store.handle_resume_response(peer, (query, MAX_RESUME_PAGES), rpc);
+        assert_eq!(2, store.export().len());
+        assert!(store.resume_queries.is_empty());
+    }
+}
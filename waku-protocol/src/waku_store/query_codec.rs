@@ -0,0 +1,83 @@
+use crate::{
+    length_prefixed::{read_length_prefixed, write_length_prefixed},
+    pb::waku_store_v3_pb::{StoreQueryRequest, StoreQueryResponse},
+    waku_message::MAX_MESSAGE_SIZE,
+    waku_store::message_queue::MAX_PAGE_SIZE,
+};
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::Codec;
+use protobuf::Message;
+use std::io;
+
+const STORE_QUERY_PROTOCOL_ID: &str = "/vac/waku/store-query/3.0.0";
+// A response carries at most a page of messages, with their hashes and topics
+const MAX_STORE_QUERY_RPC_SIZE: usize = MAX_PAGE_SIZE * MAX_MESSAGE_SIZE + 1024 * 1024;
+
+#[derive(Clone)]
+pub struct WakuStoreQueryProtocol();
+#[derive(Clone)]
+pub struct WakuStoreQueryCodec;
+
+impl AsRef<str> for WakuStoreQueryProtocol {
+    fn as_ref(&self) -> &str {
+        STORE_QUERY_PROTOCOL_ID
+    }
+}
+
+#[async_trait]
+impl Codec for WakuStoreQueryCodec {
+    type Protocol = WakuStoreQueryProtocol;
+    type Request = StoreQueryRequest;
+    type Response = StoreQueryResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let rpc_bytes = read_length_prefixed(io, MAX_STORE_QUERY_RPC_SIZE).await?;
+        StoreQueryRequest::parse_from_bytes(&rpc_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let rpc_bytes = read_length_prefixed(io, MAX_STORE_QUERY_RPC_SIZE).await?;
+        StoreQueryResponse::parse_from_bytes(&rpc_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        req: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let req_bytes = req.write_to_bytes()?;
+        write_length_prefixed(io, req_bytes).await?;
+        Ok(())
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        res: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let res_bytes = res.write_to_bytes()?;
+        write_length_prefixed(io, res_bytes).await?;
+        Ok(())
+    }
+}
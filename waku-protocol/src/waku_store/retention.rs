@@ -0,0 +1,154 @@
+use std::{fmt::Debug, str::FromStr, time::Duration};
+
+// The operations a store backend offers for retention policies to be enforced on it.
+pub trait MessageArchive {
+    // Total encoded size of the archived messages, in bytes
+    fn size_bytes(&self) -> usize;
+    // Number of messages of a pubsub topic, optionally restricted to one content topic
+    fn topic_len(&self, pubsub_topic: &str, content_topic: Option<&str>) -> usize;
+    // Receiver time of the oldest archived message, in nanoseconds since the Unix epoch
+    fn oldest_receiver_time(&self) -> Option<i64>;
+    // Removes the oldest message, returning false if the archive is empty
+    fn pop_oldest(&mut self) -> bool;
+    // Removes the oldest message of a topic, returning false if there is none
+    fn pop_oldest_in_topic(&mut self, pubsub_topic: &str, content_topic: Option<&str>) -> bool;
+}
+
+// Decides which messages a store keeps. Policies are enforced after every insert and
+// periodically, and always evict the oldest messages first.
+pub trait RetentionPolicy: Debug + Send {
+    // Evicts what the policy doesn't retain anymore. `now` is in nanoseconds since the
+    // Unix epoch. Returns the number of evicted messages
+    fn apply(&self, archive: &mut dyn MessageArchive, now: i64) -> usize;
+}
+
+// Keeps messages for at most max_age after they were received.
+#[derive(Clone, Debug)]
+pub struct TimeRetention {
+    pub max_age: Duration,
+}
+
+impl RetentionPolicy for TimeRetention {
+    fn apply(&self, archive: &mut dyn MessageArchive, now: i64) -> usize {
+        let cutoff = now - self.max_age.as_nanos() as i64;
+        let mut evicted = 0;
+        while archive.oldest_receiver_time().is_some_and(|t| t < cutoff) {
+            archive.pop_oldest();
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+// Keeps the total size of the archived messages under max_bytes.
+#[derive(Clone, Debug)]
+pub struct SizeRetention {
+    pub max_bytes: usize,
+}
+
+impl RetentionPolicy for SizeRetention {
+    fn apply(&self, archive: &mut dyn MessageArchive, _: i64) -> usize {
+        let mut evicted = 0;
+        while archive.size_bytes() > self.max_bytes && archive.pop_oldest() {
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+// Keeps at most max_messages of a pubsub topic, or of one of its content topics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopicRetention {
+    pub pubsub_topic: String,
+    pub content_topic: Option<String>,
+    pub max_messages: usize,
+}
+
+impl RetentionPolicy for TopicRetention {
+    fn apply(&self, archive: &mut dyn MessageArchive, _: i64) -> usize {
+        let content_topic = self.content_topic.as_deref();
+        let mut evicted = 0;
+        while archive.topic_len(&self.pubsub_topic, content_topic) > self.max_messages
+            && archive.pop_oldest_in_topic(&self.pubsub_topic, content_topic)
+        {
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+// Parses <pubsub_topic>=<max_messages> or <pubsub_topic>,<content_topic>=<max_messages>
+impl FromStr for TopicRetention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (topics, max_messages) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected <topic>=<max_messages>, got {}", s))?;
+        let max_messages = max_messages.parse::<usize>().map_err(|e| e.to_string())?;
+        let (pubsub_topic, content_topic) = match topics.split_once(',') {
+            Some((pubsub_topic, content_topic)) => (pubsub_topic, Some(content_topic.to_string())),
+            None => (topics, None),
+        };
+        Ok(TopicRetention {
+            pubsub_topic: pubsub_topic.to_string(),
+            content_topic,
+            max_messages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pb::waku_message_pb::WakuMessage;
+    use crate::waku_store::{
+        message_queue::{IndexedWakuMessage, WakuMessageQueue},
+        network_behaviour::compute_index,
+        retention::{
+            MessageArchive, RetentionPolicy, SizeRetention, TimeRetention, TopicRetention,
+        },
+    };
+    use std::time::Duration;
+
+    fn push(queue: &mut WakuMessageQueue, payload: &str, content_topic: &str, received: i64) {
+        let mut msg = WakuMessage::new();
+        msg.set_payload(payload.as_bytes().to_vec());
+        msg.set_content_topic(content_topic.to_string());
+        let mut index = compute_index("pubsub", msg.clone());
+        index.set_receiver_time(received);
+        queue
+            .push(IndexedWakuMessage::new(msg, index, "pubsub".to_string()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_retention_policies() {
+        let mut queue = WakuMessageQueue::new(100);
+        for i in 0..10 {
+            let content_topic = if i % 2 == 0 { "even" } else { "odd" };
+            push(&mut queue, &i.to_string(), content_topic, i * 1_000_000_000);
+        }
+
+        let now = 10_000_000_000;
+        let time = TimeRetention {
+            max_age: Duration::from_secs(8),
+        };
+        assert_eq!(2, time.apply(&mut queue, now));
+        assert_eq!(Some(2_000_000_000), queue.oldest_receiver_time());
+
+        let topic: TopicRetention = "pubsub,odd=2".parse().unwrap();
+        assert_eq!(2, topic.apply(&mut queue, now));
+        assert_eq!(2, queue.topic_len("pubsub", Some("odd")));
+        assert_eq!(4, queue.topic_len("pubsub", Some("even")));
+
+        let size = SizeRetention {
+            max_bytes: queue.size_bytes() - 1,
+        };
+        assert_eq!(1, size.apply(&mut queue, now));
+        assert_eq!(5, queue.len());
+
+        let topic: TopicRetention = "pubsub=1".parse().unwrap();
+        assert_eq!(4, topic.apply(&mut queue, now));
+        assert_eq!(1, queue.topic_len("pubsub", None));
+    }
+}
@@ -0,0 +1,246 @@
+// Range-based set reconciliation of store archives, after Negentropy
+// (https://logperiodic.com/rbsr.html). Archived messages are ordered by (timestamp, hash)
+// and the peers exchange fingerprints of ranges of them, splitting the ranges whose
+// fingerprints differ until they are small enough to exchange the items themselves. The
+// bandwidth is proportional to the difference between the archives, not their size.
+use crate::pb::waku_store_sync_pb::{SyncBound, SyncRange, SyncRange_Mode};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashSet},
+    ops::Bound,
+};
+
+// A message as reconciled: its timestamp, 0 if it has none, and its digest.
+pub type SyncItem = (i64, Vec<u8>);
+
+// Number of sub-ranges a range with a mismatching fingerprint is split into
+const BRANCHING_FACTOR: usize = 16;
+// Ranges with at most this many items are sent as item sets instead of being split
+const ITEM_SET_THRESHOLD: usize = 2 * BRANCHING_FACTOR;
+const FINGERPRINT_SIZE: usize = 16;
+
+// Ranges are consecutive, so only upper bounds are sent. These are the bounds of the
+// whole item space
+const MIN_BOUND: (i64, &[u8]) = (i64::MIN, &[]);
+const MAX_BOUND: (i64, &[u8]) = (i64::MAX, &[]);
+
+// What the initiator of a reconciliation learns about the two archives.
+#[derive(Debug, Default, PartialEq)]
+pub struct Differences {
+    // Hashes of messages only the initiator has
+    pub have: Vec<Vec<u8>>,
+    // Hashes of messages only the other peer has
+    pub need: Vec<Vec<u8>>,
+}
+
+// Sum of the hashes modulo 2^256 and the item count, hashed. Sums can be computed in any
+// order, so both peers get the same fingerprint for the same items.
+pub fn fingerprint<'a>(items: impl Iterator<Item = &'a SyncItem>) -> Vec<u8> {
+    let mut sum = [0u8; 32];
+    let mut count = 0u64;
+    for (_, hash) in items {
+        let mut carry = 0u16;
+        for (i, s) in sum.iter_mut().enumerate() {
+            let v = *s as u16 + *hash.get(i).unwrap_or(&0) as u16 + carry;
+            *s = v as u8;
+            carry = v >> 8;
+        }
+        count += 1;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(sum);
+    hasher.update(count.to_le_bytes());
+    hasher.finalize()[..FINGERPRINT_SIZE].to_vec()
+}
+
+// The first message of a reconciliation, covering the whole archive
+pub fn initial_ranges(items: &BTreeSet<SyncItem>) -> Vec<SyncRange> {
+    let mut ranges = Vec::new();
+    split(
+        &items.iter().collect::<Vec<_>>(),
+        to_bound(MAX_BOUND),
+        &mut ranges,
+    );
+    ranges
+}
+
+// Answers the ranges of the other peer. The initiator records the differences it finds in
+// item sets, the other peer answers them with its own items when they differ. An empty
+// answer means reconciliation is complete.
+pub fn reconcile(
+    items: &BTreeSet<SyncItem>,
+    ranges: &[SyncRange],
+    initiator: bool,
+    differences: &mut Differences,
+) -> Vec<SyncRange> {
+    let mut output = Vec::new();
+    let mut lower: SyncItem = (MIN_BOUND.0, MIN_BOUND.1.to_vec());
+
+    for range in ranges {
+        let upper = from_bound(range.get_upper_bound());
+        let own: Vec<&SyncItem> = match lower < upper {
+            true => items
+                .range((Bound::Included(&lower), Bound::Excluded(&upper)))
+                .collect(),
+            false => Vec::new(),
+        };
+
+        match range.get_mode() {
+            SyncRange_Mode::SKIP => skip(range.get_upper_bound(), &mut output),
+            SyncRange_Mode::FINGERPRINT => {
+                match fingerprint(own.iter().copied()) == range.get_fingerprint() {
+                    true => skip(range.get_upper_bound(), &mut output),
+                    false => split(&own, range.get_upper_bound().clone(), &mut output),
+                }
+            }
+            SyncRange_Mode::ITEM_SET => {
+                let theirs: HashSet<SyncItem> = range.get_items().iter().map(from_bound).collect();
+                let own_set: HashSet<&SyncItem> = own.iter().copied().collect();
+                let differ = own.len() != theirs.len() || own.iter().any(|i| !theirs.contains(*i));
+                if initiator {
+                    differences.have.extend(
+                        own.iter()
+                            .filter(|i| !theirs.contains(**i))
+                            .map(|(_, h)| h.clone()),
+                    );
+                    differences.need.extend(
+                        range
+                            .get_items()
+                            .iter()
+                            .map(from_bound)
+                            .filter(|i| !own_set.contains(i))
+                            .map(|(_, h)| h),
+                    );
+                    skip(range.get_upper_bound(), &mut output);
+                } else if differ {
+                    // Large ranges are narrowed down further rather than sent whole
+                    split(&own, range.get_upper_bound().clone(), &mut output);
+                } else {
+                    skip(range.get_upper_bound(), &mut output);
+                }
+            }
+        }
+        lower = upper;
+    }
+
+    // Trailing skips carry no information
+    while output
+        .last()
+        .is_some_and(|r| r.get_mode() == SyncRange_Mode::SKIP)
+    {
+        output.pop();
+    }
+    output
+}
+
+// Appends a skip, merging it with a preceding one
+fn skip(upper_bound: &SyncBound, output: &mut Vec<SyncRange>) {
+    if let Some(last) = output.last_mut() {
+        if last.get_mode() == SyncRange_Mode::SKIP {
+            last.set_upper_bound(upper_bound.clone());
+            return;
+        }
+    }
+    let mut range = SyncRange::new();
+    range.set_upper_bound(upper_bound.clone());
+    range.set_mode(SyncRange_Mode::SKIP);
+    output.push(range);
+}
+
+// Sends small ranges as item sets, and splits larger ones into fingerprinted sub-ranges
+// holding the same number of own items each
+fn split(items: &[&SyncItem], upper_bound: SyncBound, output: &mut Vec<SyncRange>) {
+    if items.len() <= ITEM_SET_THRESHOLD {
+        let mut range = SyncRange::new();
+        range.set_upper_bound(upper_bound);
+        range.set_mode(SyncRange_Mode::ITEM_SET);
+        range.set_items(items.iter().map(|(t, h)| to_bound((*t, h))).collect());
+        output.push(range);
+        return;
+    }
+
+    let chunk_size = items.len().div_ceil(BRANCHING_FACTOR);
+    let chunks: Vec<&[&SyncItem]> = items.chunks(chunk_size).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut range = SyncRange::new();
+        range.set_upper_bound(match chunks.get(i + 1) {
+            Some(next) => to_bound((next[0].0, &next[0].1)),
+            None => upper_bound.clone(),
+        });
+        range.set_mode(SyncRange_Mode::FINGERPRINT);
+        range.set_fingerprint(fingerprint(chunk.iter().copied()));
+        output.push(range);
+    }
+}
+
+fn to_bound((timestamp, hash): (i64, &[u8])) -> SyncBound {
+    let mut bound = SyncBound::new();
+    bound.set_timestamp(timestamp);
+    bound.set_hash(hash.to_vec());
+    bound
+}
+
+fn from_bound(bound: &SyncBound) -> SyncItem {
+    (bound.get_timestamp(), bound.get_hash().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::waku_store::sync::{initial_ranges, reconcile, Differences, SyncItem};
+    use std::collections::BTreeSet;
+
+    fn item(i: u32) -> SyncItem {
+        let mut hash = vec![0; 32];
+        hash[..4].copy_from_slice(&i.to_be_bytes());
+        hash[31] = i as u8;
+        (i as i64 * 1000, hash)
+    }
+
+    // Reconciles until done, returning the differences and the number of items exchanged
+    fn run(initiator: &BTreeSet<SyncItem>, other: &BTreeSet<SyncItem>) -> (Differences, usize) {
+        let mut differences = Differences::default();
+        let mut exchanged = 0;
+        let mut ranges = initial_ranges(initiator);
+        for _ in 0..20 {
+            if ranges.is_empty() {
+                differences.have.sort();
+                differences.need.sort();
+                return (differences, exchanged);
+            }
+            exchanged += ranges.iter().map(|r| r.get_items().len()).sum::<usize>();
+            let answer = reconcile(other, &ranges, false, &mut Differences::default());
+            exchanged += answer.iter().map(|r| r.get_items().len()).sum::<usize>();
+            ranges = reconcile(initiator, &answer, true, &mut differences);
+        }
+        panic!("Reconciliation did not converge");
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let shared: BTreeSet<SyncItem> = (0..10_000).map(item).collect();
+        let mut a = shared.clone();
+        a.extend([item(20_000), item(20_001)]);
+        a.remove(&item(5_000));
+        let mut b = shared.clone();
+        b.insert(item(30_000));
+
+        let (differences, exchanged) = run(&a, &b);
+        assert_eq!(
+            Differences {
+                have: vec![item(20_000).1, item(20_001).1],
+                need: vec![item(5_000).1, item(30_000).1],
+            },
+            differences
+        );
+        assert!(exchanged < 500, "exchanged {} items", exchanged);
+
+        // Identical archives are done after one round trip
+        assert_eq!((Differences::default(), 0), run(&shared, &shared));
+
+        let empty = BTreeSet::new();
+        let few: BTreeSet<SyncItem> = (0..5).map(item).collect();
+        assert_eq!(5, run(&empty, &few).0.need.len());
+        assert_eq!(10_000, run(&shared, &empty).0.have.len());
+    }
+}